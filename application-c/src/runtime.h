@@ -19,7 +19,47 @@ WASM_IMPORT("gpio_deinit",
 WASM_IMPORT("gpio_init",
             int gpio_init(unsigned int port, unsigned int pin, int is_input));
 WASM_IMPORT("delay_ms", void delay_ms(unsigned int ms));
+WASM_IMPORT("delay_us", void delay_us(unsigned int us));
+WASM_IMPORT("time_now_us", long long time_now_us(void));
+WASM_IMPORT("uptime_ms", long long uptime_ms(void));
+WASM_IMPORT("timer_start",
+            int timer_start(long long period_us, int periodic,
+                            void (*callback)(int handle), int* handle));
+WASM_IMPORT("timer_cancel", int timer_cancel(int handle));
+WASM_IMPORT("timer_poll", int timer_poll(long long timeout_us));
 WASM_IMPORT("print", void print(char const* offset, int len));
+WASM_IMPORT("kv_get",
+            int kv_get(char const* key, unsigned int key_len,
+                       unsigned char* buf, unsigned int cap,
+                       unsigned int* len));
+WASM_IMPORT("kv_set",
+            int kv_set(char const* key, unsigned int key_len,
+                       unsigned char const* value, unsigned int value_len));
+WASM_IMPORT("kv_delete", int kv_delete(char const* key, unsigned int key_len));
+WASM_IMPORT("kv_list",
+            int kv_list(char* buf, unsigned int cap, unsigned int* len));
+WASM_IMPORT("random_fill",
+            int random_fill(unsigned char* buf, unsigned int len));
+WASM_IMPORT("chan_open",
+            int chan_open(char const* name, unsigned int name_len,
+                          unsigned int* handle));
+WASM_IMPORT("chan_send",
+            int chan_send(unsigned int handle, unsigned char const* message,
+                          unsigned int len, unsigned long long timeout_us));
+WASM_IMPORT("chan_recv",
+            int chan_recv(unsigned int handle, unsigned char* buf,
+                          unsigned int cap, unsigned long long timeout_us,
+                          unsigned int* len));
+WASM_IMPORT("report_panic",
+            void report_panic(char const* msg, unsigned int msg_len,
+                              char const* file, unsigned int file_len,
+                              unsigned int line));
+// named differently in C, `log` is the logarithm of the C library
+WASM_IMPORT("log", void wasm_log(unsigned int level, char const* target,
+                                 unsigned int target_len, char const* msg,
+                                 unsigned int msg_len));
+WASM_IMPORT("log_max_level", unsigned int log_max_level());
+WASM_IMPORT("profile_mark", void profile_mark(unsigned int id));
 WASM_IMPORT("uart_init",
             int uart_init(unsigned char* handle, unsigned int tx_port,
                           unsigned int tx_pin, unsigned int rx_port,
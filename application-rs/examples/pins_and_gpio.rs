@@ -14,6 +14,9 @@ use wasm_embedded_hal::{
     Periphals,
 };
 
+// the pins and peripherals used below
+wasm_embedded_hal::manifest!(b"gpio 2 3 8 10\nuart");
+
 #[no_mangle]
 fn start() -> Result<(), ()> {
     let mut p = Periphals::take().ok_or(())?;
@@ -34,6 +37,7 @@ fn start() -> Result<(), ()> {
     loop {
         // turn the led on
         gpio_8.set_high().map_err(|_| ())?;
+        wasm_embedded_hal::debug!("led on");
 
         // read the value of pin 10
         let is_val_10_hi = gpio_10.is_high().map_err(|_| ())?;
@@ -57,6 +61,6 @@ fn start() -> Result<(), ()> {
 
 #[inline(never)]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {}
+fn panic(info: &PanicInfo) -> ! {
+    wasm_embedded_hal::panic::report(info)
 }
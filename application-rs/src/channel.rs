@@ -0,0 +1,117 @@
+use core::marker::PhantomData;
+use core::time::Duration;
+
+use crate::{error::WasmError, runtime, storage::StorageValue};
+
+/// Error code of the runtime for a channel that stayed full or empty (`ESP_ERR_TIMEOUT`).
+const TIMEOUT: i32 = 0x107;
+
+/// The maximum size of a single message in bytes.
+pub const MAX_MESSAGE_LEN: usize = 256;
+
+/// Converts a timeout into microseconds, `None` waits forever.
+fn timeout_us(timeout: Option<Duration>) -> u64 {
+    match timeout {
+        Some(timeout) => (timeout.as_micros() as u64).min(u64::MAX - 1),
+        None => u64::MAX,
+    }
+}
+
+/// A named channel for exchanging messages with other modules. All modules that
+/// open a channel with the same name share it. A channel holds a limited number
+/// of messages, senders wait while it is full and receivers while it is empty.
+pub struct Channel {
+    handle: u32,
+}
+
+impl Channel {
+    /// Open the channel with the given name.
+    pub fn open(name: &str) -> Result<Self, WasmError> {
+        let mut handle = 0_u32;
+        check_error!(unsafe {
+            runtime::chan_open(name.as_ptr(), name.len() as u32, &mut handle as *mut u32)
+        });
+
+        Ok(Self { handle })
+    }
+
+    /// Send a message of at most [`MAX_MESSAGE_LEN`] bytes. Waits up to `timeout`
+    /// while the channel is full, `None` waits forever. Returns false if the
+    /// channel stayed full.
+    pub fn send(&mut self, message: &[u8], timeout: Option<Duration>) -> Result<bool, WasmError> {
+        let res = unsafe {
+            runtime::chan_send(
+                self.handle,
+                message.as_ptr(),
+                message.len() as u32,
+                timeout_us(timeout),
+            )
+        };
+        if res == TIMEOUT {
+            return Ok(false);
+        }
+        check_error!(res);
+
+        Ok(true)
+    }
+
+    /// Receive a message into `buf` and return its length. Waits up to `timeout`
+    /// while the channel is empty, `None` waits forever. Returns `None` if the
+    /// channel stayed empty. If `buf` is too small, an error is returned and the
+    /// message stays in the channel.
+    pub fn recv(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> Result<Option<usize>, WasmError> {
+        let mut len = 0_u32;
+        let res = unsafe {
+            runtime::chan_recv(
+                self.handle,
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                timeout_us(timeout),
+                &mut len as *mut u32,
+            )
+        };
+        if res == TIMEOUT {
+            return Ok(None);
+        }
+        check_error!(res);
+
+        Ok(Some(len as usize))
+    }
+}
+
+/// A [`Channel`] that carries values of a single type, in the same encoding
+/// as values in the [`Storage`](crate::storage::Storage).
+pub struct TypedChannel<T> {
+    channel: Channel,
+    _value: PhantomData<T>,
+}
+
+impl<T: StorageValue> TypedChannel<T> {
+    /// Open the channel with the given name.
+    pub fn open(name: &str) -> Result<Self, WasmError> {
+        Ok(Self {
+            channel: Channel::open(name)?,
+            _value: PhantomData,
+        })
+    }
+
+    /// Send a value, see [`Channel::send`].
+    pub fn send(&mut self, value: T, timeout: Option<Duration>) -> Result<bool, WasmError> {
+        self.channel.send(value.to_bytes().as_ref(), timeout)
+    }
+
+    /// Receive a value, see [`Channel::recv`].
+    pub fn recv(&mut self, timeout: Option<Duration>) -> Result<Option<T>, WasmError> {
+        let mut bytes = T::Bytes::default();
+        match self.channel.recv(bytes.as_mut(), timeout)? {
+            Some(len) if len == bytes.as_ref().len() => Ok(Some(T::from_bytes(bytes))),
+            // sent with a different type
+            Some(_) => Err(WasmError::RuntimeError(-1)),
+            None => Ok(None),
+        }
+    }
+}
@@ -1,8 +1,8 @@
-use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 
 use crate::runtime;
 
-/// A struct that implements embedded_hal's DelayMs.
+/// A struct that implements embedded_hal's DelayMs and DelayUs.
 pub struct WasmDelay;
 
 impl DelayMs<u32> for WasmDelay {
@@ -11,3 +11,9 @@ impl DelayMs<u32> for WasmDelay {
         unsafe { runtime::delay_ms(ms) };
     }
 }
+
+impl DelayUs<u32> for WasmDelay {
+    fn delay_us(&mut self, us: u32) {
+        unsafe { runtime::delay_us(us) };
+    }
+}
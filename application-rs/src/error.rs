@@ -6,4 +6,19 @@ pub enum WasmError {
     /// An error returned by the runtime,
     /// identified by an error code.
     RuntimeError(ErrorCode),
+    /// The request is not covered by the manifest of this module.
+    PermissionDenied,
+}
+
+/// The error code returned by the runtime for requests outside of the manifest.
+pub(crate) const PERMISSION_DENIED: ErrorCode = -2;
+
+impl WasmError {
+    /// Converts an error code of the runtime.
+    pub(crate) fn from_code(code: ErrorCode) -> Self {
+        match code {
+            PERMISSION_DENIED => WasmError::PermissionDenied,
+            code => WasmError::RuntimeError(code),
+        }
+    }
 }
@@ -1,7 +1,7 @@
 #![no_std]
 
 use delay::WasmDelay;
-use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 use gpio::Pin;
 use gpio_mode::Unknown;
 
@@ -9,7 +9,7 @@ macro_rules! check_error {
     ($ub:expr) => {
         let res = $ub;
         if res != 0 {
-            return Err(WasmError::RuntimeError(res));
+            return Err(WasmError::from_code(res));
         }
     };
 }
@@ -18,17 +18,41 @@ macro_rules! check_nb_error {
     ($ub:expr) => {
         let res = $ub;
         if res != 0 {
-            return Err(nb::Error::Other(WasmError::RuntimeError(res)));
+            return Err(nb::Error::Other(WasmError::from_code(res)));
         }
     };
 }
 
+pub mod channel;
+/// Embed the manifest of this module, which declares the pins, peripherals and services
+/// the module needs. Requests outside of the manifest fail with
+/// [`WasmError::PermissionDenied`](error::WasmError::PermissionDenied).
+///
+/// ```ignore
+/// wasm_embedded_hal::manifest!(b"gpio 2 3 8 10\nuart\nstorage\nchannel commands");
+/// ```
+#[macro_export]
+macro_rules! manifest {
+    ($text:expr) => {
+        #[used]
+        #[link_section = "capabilities"]
+        static MANIFEST: [u8; $text.len()] = *$text;
+    };
+}
+
 pub mod delay;
 pub mod error;
 pub mod gpio;
+pub mod log;
+pub mod panic;
 pub mod print;
+pub mod profile;
+pub mod rng;
 mod runtime;
 pub mod serial;
+pub mod storage;
+pub mod time;
+pub mod timer;
 
 /// A struct for accessing the hardware, as in most hal implementations
 /// this struct is hand out as a singleton.
@@ -56,6 +80,12 @@ impl DelayMs<u32> for Periphals {
     }
 }
 
+impl DelayUs<u32> for Periphals {
+    fn delay_us(&mut self, us: u32) {
+        WasmDelay::delay_us(&mut WasmDelay, us);
+    }
+}
+
 pub mod gpio_mode {
     /// A trait specifying a pin with a port and a pin number.
     pub trait GpioPin {
@@ -0,0 +1,217 @@
+use core::fmt::{self, Write};
+
+/// The longest message that is sent to the runtime, longer messages are cut off.
+const MAX_MESSAGE_LEN: usize = 256;
+
+/// The level of a log record, with the same values as the `log` crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// The most verbose level that is logged, with the same values as the `log` crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum LevelFilter {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LevelFilter {
+    fn from_u32(level: u32) -> Self {
+        match level {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+}
+
+/// The most verbose level that is compiled in, selected with the `max_level_*` features.
+pub const STATIC_MAX_LEVEL: LevelFilter = if cfg!(feature = "max_level_off") {
+    LevelFilter::Off
+} else if cfg!(feature = "max_level_error") {
+    LevelFilter::Error
+} else if cfg!(feature = "max_level_warn") {
+    LevelFilter::Warn
+} else if cfg!(feature = "max_level_info") {
+    LevelFilter::Info
+} else if cfg!(feature = "max_level_debug") {
+    LevelFilter::Debug
+} else {
+    LevelFilter::Trace
+};
+
+/// Returns the most verbose level the runtime accepts from this module. It can change
+/// while the module runs.
+pub fn max_level() -> LevelFilter {
+    LevelFilter::from_u32(unsafe { crate::runtime::log_max_level() })
+}
+
+/// Returns true if a record with the given level would be logged.
+#[inline(always)]
+pub fn enabled(level: Level) -> bool {
+    // the first check is constant, so disabled log calls are removed entirely
+    level as u32 <= STATIC_MAX_LEVEL as u32 && level as u32 <= max_level() as u32
+}
+
+/// Sends a record to the runtime, use the macros instead.
+#[doc(hidden)]
+#[inline(always)]
+pub fn __log(level: Level, target: &str, args: fmt::Arguments) {
+    // messages without arguments are sent as they are, without the formatting code
+    match args.as_str() {
+        Some(message) => log_str(level, target, message),
+        None => log_fmt(level, target, args),
+    }
+}
+
+fn log_str(level: Level, target: &str, message: &str) {
+    unsafe {
+        crate::runtime::log(
+            level as u32,
+            target.as_ptr(),
+            target.len() as u32,
+            message.as_ptr(),
+            message.len() as u32,
+        )
+    };
+}
+
+#[inline(never)]
+fn log_fmt(level: Level, target: &str, args: fmt::Arguments) {
+    let mut buffer = Buffer {
+        bytes: [0; MAX_MESSAGE_LEN],
+        len: 0,
+    };
+    // a message that doesn't fit is cut off, which is not an error
+    let _ = buffer.write_fmt(args);
+    let message = match core::str::from_utf8(&buffer.bytes[..buffer.len]) {
+        Ok(message) => message,
+        // cut off in the middle of a character
+        Err(err) => unsafe { core::str::from_utf8_unchecked(&buffer.bytes[..err.valid_up_to()]) },
+    };
+
+    log_str(level, target, message);
+}
+
+/// A fixed buffer for formatting messages, the module has no allocator.
+struct Buffer {
+    bytes: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let free = MAX_MESSAGE_LEN - self.len;
+        let len = s.len().min(free);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+
+        if len < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Logs a record, like the macro of the `log` crate. The runtime prefixes the target with
+/// `guest::<module name>`, it defaults to the path of the calling module.
+///
+/// ```ignore
+/// use wasm_embedded_hal::log::Level;
+///
+/// wasm_embedded_hal::log!(Level::Info, "led is on");
+/// wasm_embedded_hal::log!(target: "uart", Level::Warn, "dropped {} bytes", 3);
+/// ```
+#[macro_export]
+macro_rules! log {
+    (target: $target:expr, $lvl:expr, $($arg:tt)+) => {{
+        let lvl = $lvl;
+        if $crate::log::enabled(lvl) {
+            $crate::log::__log(lvl, $target, format_args!($($arg)+));
+        }
+    }};
+    ($lvl:expr, $($arg:tt)+) => {
+        $crate::log!(target: module_path!(), $lvl, $($arg)+)
+    };
+}
+
+/// Logs a record at the error level.
+#[macro_export]
+macro_rules! error {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Error, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Error, $($arg)+)
+    };
+}
+
+/// Logs a record at the warn level.
+#[macro_export]
+macro_rules! warn {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Warn, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Warn, $($arg)+)
+    };
+}
+
+/// Logs a record at the info level.
+#[macro_export]
+macro_rules! info {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Info, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Info, $($arg)+)
+    };
+}
+
+/// Logs a record at the debug level.
+#[macro_export]
+macro_rules! debug {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Debug, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Debug, $($arg)+)
+    };
+}
+
+/// Logs a record at the trace level.
+#[macro_export]
+macro_rules! trace {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Trace, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Trace, $($arg)+)
+    };
+}
+
+/// Returns true if a record with the given level would be logged, to skip preparing it.
+#[macro_export]
+macro_rules! log_enabled {
+    (target: $target:expr, $lvl:expr) => {
+        $crate::log::enabled($lvl)
+    };
+    ($lvl:expr) => {
+        $crate::log::enabled($lvl)
+    };
+}
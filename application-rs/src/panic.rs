@@ -0,0 +1,35 @@
+use core::panic::PanicInfo;
+
+/// The message that is reported for panics with a formatted message, as formatting it
+/// would pull the formatting code into the module.
+const FORMATTED_MESSAGE: &str = "panicked";
+
+/// Reports a panic to the runtime, which logs it together with a backtrace of the module
+/// and stops the module. Call this from the panic handler of the module:
+///
+/// ```ignore
+/// #[panic_handler]
+/// fn panic(info: &core::panic::PanicInfo) -> ! {
+///     wasm_embedded_hal::panic::report(info)
+/// }
+/// ```
+pub fn report(info: &PanicInfo) -> ! {
+    let msg = info.message().as_str().unwrap_or(FORMATTED_MESSAGE);
+    let (file, line) = info
+        .location()
+        .map(|location| (location.file(), location.line()))
+        .unwrap_or(("<unknown>", 0));
+
+    unsafe {
+        crate::runtime::report_panic(
+            msg.as_ptr(),
+            msg.len() as u32,
+            file.as_ptr(),
+            file.len() as u32,
+            line,
+        )
+    };
+
+    // the runtime doesn't return from a panic, but an older one might
+    core::arch::wasm32::unreachable()
+}
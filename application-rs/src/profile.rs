@@ -0,0 +1,31 @@
+use crate::runtime;
+
+/// Starts the span with the given id, or ends it if it was started before. The runtime
+/// counts how often each span ran and how long it took, next to the stats of the host
+/// functions.
+pub fn mark(id: u32) {
+    unsafe { runtime::profile_mark(id) }
+}
+
+/// A span that ends once it is dropped:
+///
+/// ```ignore
+/// let _span = wasm_embedded_hal::profile::Span::start(1);
+/// ```
+pub struct Span {
+    id: u32,
+}
+
+impl Span {
+    /// Starts the span with the given id.
+    pub fn start(id: u32) -> Self {
+        mark(id);
+        Self { id }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        mark(self.id);
+    }
+}
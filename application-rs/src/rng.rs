@@ -0,0 +1,35 @@
+use core::num::NonZeroU32;
+
+use rand_core::{impls, Error, RngCore};
+
+use crate::runtime;
+
+/// A random number generator that implements rand_core's `RngCore`, backed
+/// by the hardware RNG of the runtime.
+pub struct WasmRng;
+
+impl RngCore for WasmRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        // the runtime only fails for buffers outside of our memory, which is a bug; the
+        // message isn't formatted, as that would pull the formatting code into the module
+        if self.try_fill_bytes(dest).is_err() {
+            panic!("could not fill the buffer with random bytes");
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        let res = unsafe { runtime::random_fill(dest.as_mut_ptr(), dest.len() as u32) };
+        match NonZeroU32::new(res as u32) {
+            Some(code) => Err(Error::from(code)),
+            None => Ok(()),
+        }
+    }
+}
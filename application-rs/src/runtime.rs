@@ -29,4 +29,58 @@ extern "C" {
     pub fn gpio_read(port: u32, pin: u32, value: *mut u32) -> ErrorCode;
 
     pub fn delay_ms(ms: u32);
+
+    pub fn delay_us(us: u32);
+
+    pub fn time_now_us() -> i64;
+
+    pub fn uptime_ms() -> i64;
+
+    pub fn timer_start(
+        period_us: u64,
+        periodic: bool,
+        callback: extern "C" fn(u32),
+        handle: *mut u32,
+    ) -> ErrorCode;
+
+    pub fn timer_cancel(handle: u32) -> ErrorCode;
+
+    pub fn timer_poll(timeout_us: u64) -> ErrorCode;
+
+    pub fn kv_get(key: *const u8, key_len: u32, buf: *mut u8, cap: u32, len: *mut u32)
+        -> ErrorCode;
+
+    pub fn kv_set(key: *const u8, key_len: u32, value: *const u8, value_len: u32) -> ErrorCode;
+
+    pub fn kv_delete(key: *const u8, key_len: u32) -> ErrorCode;
+
+    pub fn kv_list(buf: *mut u8, cap: u32, len: *mut u32) -> ErrorCode;
+
+    pub fn random_fill(buf: *mut u8, len: u32) -> ErrorCode;
+
+    pub fn chan_open(name: *const u8, name_len: u32, handle: *mut u32) -> ErrorCode;
+
+    pub fn chan_send(handle: u32, message: *const u8, len: u32, timeout_us: u64) -> ErrorCode;
+
+    pub fn chan_recv(
+        handle: u32,
+        buf: *mut u8,
+        cap: u32,
+        timeout_us: u64,
+        len: *mut u32,
+    ) -> ErrorCode;
+
+    pub fn report_panic(
+        msg: *const u8,
+        msg_len: u32,
+        file: *const u8,
+        file_len: u32,
+        line: u32,
+    );
+
+    pub fn log(level: u32, target: *const u8, target_len: u32, msg: *const u8, msg_len: u32);
+
+    pub fn log_max_level() -> u32;
+
+    pub fn profile_mark(id: u32);
 }
@@ -0,0 +1,145 @@
+use crate::{error::WasmError, runtime};
+
+/// Error code of the runtime for a key that doesn't exist (`ESP_ERR_NVS_NOT_FOUND`).
+const NOT_FOUND: i32 = 0x1102;
+
+/// A value that can be stored in [`Storage`] in a fixed size, little endian encoding.
+pub trait StorageValue: Sized {
+    /// The encoded representation.
+    type Bytes: AsRef<[u8]> + AsMut<[u8]> + Default;
+
+    fn to_bytes(&self) -> Self::Bytes;
+
+    fn from_bytes(bytes: Self::Bytes) -> Self;
+}
+
+macro_rules! impl_storage_value {
+    ($($ty:ty),*) => {
+        $(
+            impl StorageValue for $ty {
+                type Bytes = [u8; core::mem::size_of::<$ty>()];
+
+                fn to_bytes(&self) -> Self::Bytes {
+                    self.to_le_bytes()
+                }
+
+                fn from_bytes(bytes: Self::Bytes) -> Self {
+                    <$ty>::from_le_bytes(bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_storage_value!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl StorageValue for bool {
+    type Bytes = [u8; 1];
+
+    fn to_bytes(&self) -> Self::Bytes {
+        [*self as u8]
+    }
+
+    fn from_bytes(bytes: Self::Bytes) -> Self {
+        bytes[0] != 0
+    }
+}
+
+/// The persistent key-value storage of this module. Values survive a reset of
+/// the device and are not visible to other modules. Keys are limited to 15 bytes.
+pub struct Storage;
+
+impl Storage {
+    /// Read the value of `key` into `buf`. Returns the length of the value, or `None`
+    /// if there is no such key. If `buf` is too small, an error is returned.
+    pub fn get(&self, key: &str, buf: &mut [u8]) -> Result<Option<usize>, WasmError> {
+        let mut len = 0_u32;
+        let res = unsafe {
+            runtime::kv_get(
+                key.as_ptr(),
+                key.len() as u32,
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                &mut len as *mut u32,
+            )
+        };
+        if res == NOT_FOUND {
+            return Ok(None);
+        }
+        check_error!(res);
+
+        Ok(Some(len as usize))
+    }
+
+    /// Store `value` under `key`, an existing value gets overwritten.
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), WasmError> {
+        check_error!(unsafe {
+            runtime::kv_set(
+                key.as_ptr(),
+                key.len() as u32,
+                value.as_ptr(),
+                value.len() as u32,
+            )
+        });
+
+        Ok(())
+    }
+
+    /// Read a typed value, returns `None` if there is no such key.
+    pub fn get_value<T: StorageValue>(&self, key: &str) -> Result<Option<T>, WasmError> {
+        let mut bytes = T::Bytes::default();
+        match self.get(key, bytes.as_mut())? {
+            Some(len) if len == bytes.as_ref().len() => Ok(Some(T::from_bytes(bytes))),
+            // stored with a different type
+            Some(_) => Err(WasmError::RuntimeError(-1)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store a typed value.
+    pub fn set_value<T: StorageValue>(&mut self, key: &str, value: T) -> Result<(), WasmError> {
+        self.set(key, value.to_bytes().as_ref())
+    }
+
+    /// Remove `key` and its value. Returns false if there was no such key.
+    pub fn delete(&mut self, key: &str) -> Result<bool, WasmError> {
+        let res = unsafe { runtime::kv_delete(key.as_ptr(), key.len() as u32) };
+        if res == NOT_FOUND {
+            return Ok(false);
+        }
+        check_error!(res);
+
+        Ok(true)
+    }
+
+    /// Iterate over all keys of this module. The keys are read into `buf`,
+    /// which needs to fit all of them.
+    pub fn keys<'a>(&self, buf: &'a mut [u8]) -> Result<Keys<'a>, WasmError> {
+        let mut len = 0_u32;
+        check_error!(unsafe {
+            runtime::kv_list(buf.as_mut_ptr(), buf.len() as u32, &mut len as *mut u32)
+        });
+
+        Ok(Keys {
+            remaining: &buf[..len as usize],
+        })
+    }
+}
+
+/// An iterator over the keys of [`Storage`].
+pub struct Keys<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for Keys<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // every key is terminated by a NUL byte
+        let end = self.remaining.iter().position(|&byte| byte == 0)?;
+        let key = &self.remaining[..end];
+        self.remaining = &self.remaining[end + 1..];
+
+        core::str::from_utf8(key).ok()
+    }
+}
@@ -0,0 +1,94 @@
+use core::ops::{Add, AddAssign, Sub};
+use core::time::Duration;
+
+use crate::runtime;
+
+/// A measurement of the runtime's monotonic clock, comparable to
+/// `std::time::Instant`. The clock starts at zero when the device boots
+/// and is only useful for comparing two instants with each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    micros: u64,
+}
+
+impl Instant {
+    /// Returns the current instant.
+    pub fn now() -> Self {
+        Self {
+            micros: unsafe { runtime::time_now_us() } as u64,
+        }
+    }
+
+    /// Returns the time passed since `earlier`, or a zero duration if
+    /// `earlier` is later than this instant.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Returns the time passed since `earlier`, or `None` if `earlier`
+    /// is later than this instant.
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.micros
+            .checked_sub(earlier.micros)
+            .map(Duration::from_micros)
+    }
+
+    /// Returns the time passed since this instant was created.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the instant `duration` after this one, or `None` on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        u64::try_from(duration.as_micros())
+            .ok()
+            .and_then(|micros| self.micros.checked_add(micros))
+            .map(|micros| Instant { micros })
+    }
+
+    /// Returns the instant `duration` before this one, or `None` if that
+    /// would be before boot.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        u64::try_from(duration.as_micros())
+            .ok()
+            .and_then(|micros| self.micros.checked_sub(micros))
+            .map(|micros| Instant { micros })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        self.checked_add(rhs)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Self::Output {
+        self.checked_sub(rhs)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+/// Returns the time passed since the device booted.
+pub fn uptime() -> Duration {
+    Duration::from_millis(unsafe { runtime::uptime_ms() } as u64)
+}
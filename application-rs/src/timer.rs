@@ -0,0 +1,158 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+
+use embedded_hal::timer::{Cancel, CountDown, Periodic};
+use void::Void;
+
+use crate::{error::WasmError, runtime};
+
+/// The maximum number of timers the runtime hands out at once.
+const MAX_TIMERS: usize = 8;
+
+/// Counts the expirations of every timer that were not yet consumed by [`Timer::wait`].
+static EXPIRED: [AtomicU32; MAX_TIMERS] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+/// Counts the starts of every handle, so that a timer can tell whether its handle was given
+/// to another timer since.
+static STARTS: [AtomicU32; MAX_TIMERS] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+/// Called by the runtime whenever one of our timers expires.
+extern "C" fn on_expired(handle: u32) {
+    if let Some(expired) = EXPIRED.get(handle as usize) {
+        expired.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A software timer of the runtime that implements embedded_hal's `CountDown`.
+/// A one-shot timer is created with [`Timer::new`], a periodic one with
+/// [`Timer::periodic`].
+pub struct Timer {
+    // the handle together with the number of its start
+    handle: Option<(u32, u32)>,
+    periodic: bool,
+}
+
+impl Timer {
+    /// Create a timer that expires once per call to `start`.
+    pub fn new() -> Self {
+        Self {
+            handle: None,
+            periodic: false,
+        }
+    }
+
+    /// Create a timer that keeps expiring every period after `start`.
+    pub fn periodic() -> Self {
+        Self {
+            handle: None,
+            periodic: true,
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CountDown for Timer {
+    type Time = Duration;
+
+    /// Starts the timer, a running timer is restarted. If the runtime can't
+    /// create the timer, `wait` never returns.
+    fn start<T>(&mut self, count: T)
+    where
+        T: Into<Self::Time>,
+    {
+        let _ = self.cancel();
+
+        let period_us = count.into().as_micros() as u64;
+        let mut handle = 0_u32;
+        let res =
+            unsafe { runtime::timer_start(period_us, self.periodic, on_expired, &mut handle) };
+        if let (0, Some(expired), Some(starts)) = (
+            res,
+            EXPIRED.get(handle as usize),
+            STARTS.get(handle as usize),
+        ) {
+            expired.store(0, Ordering::Relaxed);
+            let start = starts.fetch_add(1, Ordering::Relaxed) + 1;
+            self.handle = Some((handle, start));
+        }
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        let expired = match self
+            .handle
+            .and_then(|(handle, _)| EXPIRED.get(handle as usize))
+        {
+            Some(expired) => expired,
+            None => return Err(nb::Error::WouldBlock),
+        };
+
+        // give the runtime the chance to deliver expired timers
+        if expired.load(Ordering::Relaxed) == 0 {
+            unsafe { runtime::timer_poll(0) };
+        }
+
+        if expired.load(Ordering::Relaxed) == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        expired.fetch_sub(1, Ordering::Relaxed);
+
+        // the runtime frees a one-shot timer once it expired
+        if !self.periodic {
+            self.handle = None;
+        }
+
+        Ok(())
+    }
+}
+
+impl Periodic for Timer {}
+
+impl Cancel for Timer {
+    type Error = WasmError;
+
+    /// Stops the timer. A one-shot timer that expired was freed by the runtime already, and
+    /// its handle might belong to another timer by now.
+    fn cancel(&mut self) -> Result<(), Self::Error> {
+        let handle = match self.handle.take() {
+            Some((handle, start)) if STARTS[handle as usize].load(Ordering::Relaxed) == start => {
+                handle
+            }
+            _ => return Ok(()),
+        };
+        if !self.periodic && EXPIRED[handle as usize].load(Ordering::Relaxed) > 0 {
+            return Ok(());
+        }
+        check_error!(unsafe { runtime::timer_cancel(handle) });
+
+        Ok(())
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let _ = self.cancel();
+    }
+}
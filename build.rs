@@ -1,4 +1,78 @@
+use std::path::PathBuf;
+use std::{env, fs};
+
+// the image format of the firmware, so that both agree on it
+#[path = "src/image.rs"]
+mod image;
+
+/// The module that is embedded into the firmware, unless `WASM_MODULE` points to another one.
+const DEFAULT_MODULE: &str = "modules/main.wasm";
+
+/// The module that takes over once a module crashed too often, unless `SAFE_MODE_MODULE`
+/// points to another one.
+const DEFAULT_SAFE_MODE_MODULE: &str = "modules/safe_mode.wasm";
+
+/// The public key that signed modules are verified against, unless `MODULE_SIGNING_KEY`
+/// points to another one.
+const DEFAULT_SIGNING_KEY: &str = "keys/module_signing.pub";
+
+/// The length of a raw Ed25519 public key.
+const PUBLIC_KEY_LEN: usize = 32;
+
 // Necessary because of this issue: https://github.com/rust-lang/cargo/issues/9641
 fn main() -> anyhow::Result<()> {
+    embed_module("WASM_MODULE", DEFAULT_MODULE, "module.img")?;
+    embed_module(
+        "SAFE_MODE_MODULE",
+        DEFAULT_SAFE_MODE_MODULE,
+        "safe_mode.img",
+    )?;
+    embed_signing_key()?;
+
     embuild::build::LinkArgs::output_propagated("ESP_IDF")
 }
+
+/// Compresses the module the environment variable `var` points to, or `default`, and writes
+/// the image to `OUT_DIR/<image>`, where it is picked up by `src/bytes.rs`.
+fn embed_module(var: &str, default: &str, image: &str) -> anyhow::Result<()> {
+    println!("cargo:rerun-if-env-changed={}", var);
+    let module = env::var(var).unwrap_or_else(|_| default.into());
+    println!("cargo:rerun-if-changed={}", module);
+
+    let wasm = fs::read(&module)?;
+    let compressed = image::compress(&wasm);
+    // make sure the firmware can load what is embedded
+    let decompressed = image::decompress(&compressed).map_err(anyhow::Error::msg)?;
+    anyhow::ensure!(
+        *decompressed == *wasm,
+        "the compressed image of {} doesn't match the module",
+        module
+    );
+
+    let out = PathBuf::from(env::var("OUT_DIR")?).join(image);
+    fs::write(out, compressed)?;
+
+    Ok(())
+}
+
+/// Copies the public key `MODULE_SIGNING_KEY` points to, or the default one, to
+/// `OUT_DIR/module_signing.pub`, where it is picked up by `src/bytes.rs`.
+fn embed_signing_key() -> anyhow::Result<()> {
+    println!("cargo:rerun-if-env-changed=MODULE_SIGNING_KEY");
+    let key = env::var("MODULE_SIGNING_KEY").unwrap_or_else(|_| DEFAULT_SIGNING_KEY.into());
+    println!("cargo:rerun-if-changed={}", key);
+
+    let public_key = fs::read(&key)?;
+    anyhow::ensure!(
+        public_key.len() == PUBLIC_KEY_LEN,
+        "{} is not a public key of tools/wasm-sign, it has {} bytes instead of {}",
+        key,
+        public_key.len(),
+        PUBLIC_KEY_LEN
+    );
+
+    let out = PathBuf::from(env::var("OUT_DIR")?).join("module_signing.pub");
+    fs::write(out, public_key)?;
+
+    Ok(())
+}
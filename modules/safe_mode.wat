@@ -0,0 +1,8 @@
+;; The module that takes over once another module crashed too often. It only
+;; reports that the device is in safe mode, build it with `wat2wasm safe_mode.wat`.
+(module
+  (import "env" "print" (func $print (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 16) "safe mode: a module crashed repeatedly and was stopped")
+  (func (export "start")
+    (call $print (i32.const 16) (i32.const 54))))
@@ -1,33 +1,18 @@
-#[link_section = ".iram.data"]
-pub static WASM_BYTES: [u8; 739] = [
-    0, 97, 115, 109, 1, 0, 0, 0, 1, 43, 7, 96, 9, 127, 127, 127, 127, 127, 127, 127, 127, 127, 1,
-    127, 96, 3, 127, 127, 127, 1, 127, 96, 2, 127, 127, 1, 127, 96, 2, 127, 127, 0, 96, 1, 127, 0,
-    96, 0, 1, 127, 96, 0, 0, 2, 110, 7, 3, 101, 110, 118, 9, 117, 97, 114, 116, 95, 105, 110, 105,
-    116, 0, 0, 3, 101, 110, 118, 10, 103, 112, 105, 111, 95, 119, 114, 105, 116, 101, 0, 1, 3, 101,
-    110, 118, 9, 103, 112, 105, 111, 95, 114, 101, 97, 100, 0, 1, 3, 101, 110, 118, 10, 117, 97,
-    114, 116, 95, 119, 114, 105, 116, 101, 0, 2, 3, 101, 110, 118, 5, 112, 114, 105, 110, 116, 0,
-    3, 3, 101, 110, 118, 9, 103, 112, 105, 111, 95, 105, 110, 105, 116, 0, 1, 3, 101, 110, 118, 8,
-    100, 101, 108, 97, 121, 95, 109, 115, 0, 4, 3, 5, 4, 5, 3, 3, 6, 5, 3, 1, 0, 1, 6, 22, 3, 127,
-    1, 65, 128, 128, 2, 11, 127, 0, 65, 147, 128, 2, 11, 127, 0, 65, 160, 128, 2, 11, 7, 45, 4, 6,
-    109, 101, 109, 111, 114, 121, 2, 0, 5, 115, 116, 97, 114, 116, 0, 7, 10, 95, 95, 100, 97, 116,
-    97, 95, 101, 110, 100, 3, 1, 11, 95, 95, 104, 101, 97, 112, 95, 98, 97, 115, 101, 3, 2, 10,
-    204, 3, 4, 209, 2, 1, 9, 127, 35, 0, 65, 16, 107, 34, 0, 36, 0, 65, 0, 45, 0, 146, 128, 2, 33,
-    1, 65, 0, 65, 1, 58, 0, 146, 128, 2, 2, 64, 32, 1, 13, 0, 32, 0, 65, 8, 16, 8, 32, 0, 40, 2, 0,
-    65, 1, 70, 13, 0, 32, 0, 65, 8, 106, 34, 1, 40, 2, 0, 33, 2, 32, 0, 40, 2, 4, 33, 3, 32, 0, 65,
-    10, 16, 9, 32, 0, 40, 2, 0, 65, 1, 70, 13, 0, 32, 1, 40, 2, 0, 33, 4, 32, 0, 40, 2, 4, 33, 5,
-    32, 0, 65, 2, 16, 9, 32, 0, 40, 2, 0, 65, 1, 70, 13, 0, 32, 0, 65, 8, 106, 34, 1, 40, 2, 0, 33,
-    6, 32, 0, 40, 2, 4, 33, 7, 32, 0, 65, 3, 16, 8, 32, 0, 40, 2, 0, 65, 1, 70, 13, 0, 32, 1, 40,
-    2, 0, 33, 1, 32, 0, 40, 2, 4, 33, 8, 32, 0, 65, 0, 58, 0, 0, 32, 0, 32, 8, 32, 1, 32, 7, 32, 6,
-    65, 0, 65, 0, 65, 0, 65, 0, 16, 0, 13, 0, 32, 0, 45, 0, 0, 65, 255, 1, 113, 33, 7, 3, 64, 32,
-    3, 32, 2, 65, 1, 16, 1, 13, 1, 32, 0, 65, 0, 54, 2, 0, 32, 5, 32, 4, 32, 0, 16, 2, 13, 1, 65,
-    137, 128, 2, 65, 146, 128, 2, 32, 0, 40, 2, 0, 65, 1, 70, 34, 1, 27, 33, 6, 65, 128, 128, 2,
-    65, 137, 128, 2, 32, 1, 27, 34, 8, 33, 1, 2, 64, 3, 64, 32, 1, 32, 6, 70, 13, 1, 32, 7, 32, 1,
-    45, 0, 0, 16, 3, 26, 32, 1, 65, 1, 106, 33, 1, 12, 0, 11, 11, 32, 8, 65, 9, 16, 4, 16, 10, 32,
-    3, 32, 2, 65, 0, 16, 1, 13, 1, 16, 10, 12, 0, 11, 11, 32, 0, 65, 16, 106, 36, 0, 65, 1, 11, 55,
-    1, 2, 127, 65, 1, 33, 2, 2, 64, 65, 0, 32, 1, 65, 0, 16, 5, 34, 3, 13, 0, 32, 0, 65, 8, 106,
-    32, 1, 54, 2, 0, 65, 0, 33, 3, 65, 0, 33, 2, 11, 32, 0, 32, 2, 54, 2, 0, 32, 0, 32, 3, 54, 2,
-    4, 11, 55, 1, 2, 127, 65, 1, 33, 2, 2, 64, 65, 0, 32, 1, 65, 1, 16, 5, 34, 3, 13, 0, 32, 0, 65,
-    8, 106, 32, 1, 54, 2, 0, 65, 0, 33, 3, 65, 0, 33, 2, 11, 32, 0, 32, 2, 54, 2, 0, 32, 0, 32, 3,
-    54, 2, 4, 11, 7, 0, 65, 232, 7, 16, 6, 11, 11, 26, 1, 0, 65, 128, 128, 2, 11, 18, 118, 97, 108,
-    32, 49, 48, 32, 104, 105, 118, 97, 108, 32, 49, 48, 32, 108, 111,
-];
+/// The name of the module, identifies e.g. its persistent storage.
+pub const MODULE_NAME: &str = "main";
+
+/// The compressed image of the module, produced by `build.rs` from `modules/main.wasm`
+/// or the module `WASM_MODULE` points to. It is decompressed into the heap when loaded.
+pub const WASM_IMAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/module.img"));
+
+/// The name of the module that takes over once a module crashed too often.
+pub const SAFE_MODE_NAME: &str = "safe-mode";
+
+/// The compressed image of the safe mode module, produced by `build.rs` from
+/// `modules/safe_mode.wasm` or the module `SAFE_MODE_MODULE` points to.
+pub const SAFE_MODE_IMAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/safe_mode.img"));
+
+/// The raw Ed25519 public key that signed modules are verified against, copied by `build.rs`
+/// from `keys/module_signing.pub` or the key `MODULE_SIGNING_KEY` points to.
+pub const MODULE_SIGNING_KEY: &[u8; 32] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/module_signing.pub"));
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// The type for the handles that are given out for a channel.
+pub(crate) type ChannelHandle = u32;
+
+/// The maximum number of channels a single runtime can have open at once.
+pub(crate) const MAX_CHANNELS: u32 = 8;
+
+/// The number of messages a channel can hold before senders block.
+pub(crate) const CHANNEL_CAPACITY: usize = 8;
+
+/// The maximum size of a single message in bytes.
+pub(crate) const MAX_MESSAGE_LEN: usize = 256;
+
+/// The reasons sending or receiving a message can fail.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ChannelError {
+    /// The channel was full or empty for the whole timeout.
+    Timeout,
+    /// The message doesn't fit, either into the channel or into the receive buffer.
+    /// Holds the length of the message.
+    TooLarge(usize),
+}
+
+/// All channels of the device, identified by their name. Channels are never
+/// removed, so that messages are not lost while no module has them open.
+static REGISTRY: Mutex<Vec<(String, Arc<Channel>)>> = Mutex::new(Vec::new());
+
+/// A bounded queue of messages, shared by all modules that opened it by its name.
+#[derive(Default)]
+pub(crate) struct Channel {
+    messages: Mutex<VecDeque<Vec<u8>>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl Channel {
+    /// Returns the channel with the given name, creating it if necessary.
+    fn by_name(name: &str) -> Arc<Channel> {
+        let mut registry = REGISTRY.lock().unwrap();
+        match registry.iter().find(|(existing, _)| existing == name) {
+            Some((_, channel)) => channel.clone(),
+            None => {
+                let channel = Arc::new(Channel::default());
+                registry.push((name.into(), channel.clone()));
+                channel
+            }
+        }
+    }
+
+    /// Queues a message, waits up to `timeout` for space if the channel is full.
+    pub(crate) fn send(&self, message: &[u8], timeout: Duration) -> Result<(), ChannelError> {
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(ChannelError::TooLarge(message.len()));
+        }
+
+        let (mut messages, _) = self
+            .not_full
+            .wait_timeout_while(self.messages.lock().unwrap(), timeout, |messages| {
+                messages.len() >= CHANNEL_CAPACITY
+            })
+            .unwrap();
+        if messages.len() >= CHANNEL_CAPACITY {
+            return Err(ChannelError::Timeout);
+        }
+        messages.push_back(message.to_vec());
+        self.not_empty.notify_one();
+
+        Ok(())
+    }
+
+    /// Takes the oldest message, waits up to `timeout` for one if the channel is empty.
+    /// A message longer than `cap` stays in the channel.
+    pub(crate) fn recv(&self, cap: usize, timeout: Duration) -> Result<Vec<u8>, ChannelError> {
+        let (mut messages, _) = self
+            .not_empty
+            .wait_timeout_while(self.messages.lock().unwrap(), timeout, |messages| {
+                messages.is_empty()
+            })
+            .unwrap();
+        match messages.front() {
+            None => return Err(ChannelError::Timeout),
+            Some(message) if message.len() > cap => {
+                return Err(ChannelError::TooLarge(message.len()))
+            }
+            Some(_) => (),
+        }
+        let message = messages.pop_front().unwrap();
+        self.not_full.notify_one();
+
+        Ok(message)
+    }
+}
+
+/// The channels a runtime opened.
+#[derive(Default)]
+pub(crate) struct Channels {
+    open: HashMap<ChannelHandle, Arc<Channel>>,
+}
+
+impl Channels {
+    /// Opens the channel with the given name and returns its handle. Returns `None`
+    /// if the runtime has too many channels open.
+    pub(crate) fn open(&mut self, name: &str) -> Option<ChannelHandle> {
+        let handle = (0..MAX_CHANNELS).find(|handle| !self.open.contains_key(handle))?;
+        self.open.insert(handle, Channel::by_name(name));
+
+        Some(handle)
+    }
+
+    /// Returns the channel behind a handle.
+    pub(crate) fn get(&self, handle: ChannelHandle) -> Option<Arc<Channel>> {
+        self.open.get(&handle).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the registry is shared by all tests, every test uses channels of its own
+
+    #[test]
+    fn messages_are_received_in_order() {
+        let mut sender = Channels::default();
+        let mut receiver = Channels::default();
+        let handle = sender.open("test-order").unwrap();
+        let tx = sender.get(handle).unwrap();
+        let handle = receiver.open("test-order").unwrap();
+        let rx = receiver.get(handle).unwrap();
+
+        tx.send(b"first", Duration::ZERO).unwrap();
+        tx.send(b"second", Duration::ZERO).unwrap();
+        assert_eq!(rx.recv(16, Duration::ZERO).unwrap(), b"first");
+        assert_eq!(rx.recv(16, Duration::ZERO).unwrap(), b"second");
+        assert_eq!(rx.recv(16, Duration::ZERO), Err(ChannelError::Timeout));
+    }
+
+    #[test]
+    fn a_full_channel_times_out() {
+        let channel = Channel::by_name("test-full");
+        for _ in 0..CHANNEL_CAPACITY {
+            channel.send(b"x", Duration::ZERO).unwrap();
+        }
+        assert_eq!(
+            channel.send(b"x", Duration::from_millis(10)),
+            Err(ChannelError::Timeout)
+        );
+    }
+
+    #[test]
+    fn messages_that_dont_fit_are_rejected() {
+        let channel = Channel::by_name("test-large");
+        let message = [0; MAX_MESSAGE_LEN + 1];
+        assert_eq!(
+            channel.send(&message, Duration::ZERO),
+            Err(ChannelError::TooLarge(MAX_MESSAGE_LEN + 1))
+        );
+
+        // a message that doesn't fit into the buffer stays in the channel
+        channel.send(b"hello", Duration::ZERO).unwrap();
+        assert_eq!(
+            channel.recv(4, Duration::ZERO),
+            Err(ChannelError::TooLarge(5))
+        );
+        assert_eq!(channel.recv(5, Duration::ZERO).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn a_runtime_opens_a_limited_number_of_channels() {
+        let mut channels = Channels::default();
+        for handle in 0..MAX_CHANNELS {
+            assert_eq!(channels.open("test-limit"), Some(handle));
+        }
+        assert_eq!(channels.open("test-limit"), None);
+        assert!(channels.get(MAX_CHANNELS).is_none());
+    }
+}
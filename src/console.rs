@@ -0,0 +1,70 @@
+use core::ptr;
+
+use esp_idf_sys::{
+    esp, esp_task_wdt_reset, uart_driver_delete, uart_driver_install, uart_port_t, uart_read_bytes,
+    EspError, TickType_t, UART_NUM_0,
+};
+
+use crate::debugger::Transport;
+
+/// The UART of the console, which also carries the log output.
+const CONSOLE_UART: uart_port_t = UART_NUM_0 as uart_port_t;
+
+/// The size of the receive buffer of the console UART.
+const RX_BUFFER_SIZE: i32 = 256;
+
+/// How long a waiting read gives up the core, before the watchdog is fed.
+const WAIT_TICKS: TickType_t = esp_idf_sys::configTICK_RATE_HZ as TickType_t;
+
+/// Connects the debugger to a client over the console UART. The lines of the debugger are
+/// prefixed with `debug`, so that the client can tell them apart from the log output.
+pub(crate) struct ConsoleTransport {
+    line: Vec<u8>,
+}
+
+impl ConsoleTransport {
+    /// Installs the driver of the console UART, so that it can be read.
+    pub(crate) fn open() -> Result<Self, EspError> {
+        esp!(unsafe {
+            uart_driver_install(CONSOLE_UART, RX_BUFFER_SIZE, 0, 0, ptr::null_mut(), 0)
+        })?;
+
+        Ok(Self { line: Vec::new() })
+    }
+}
+
+impl Transport for ConsoleTransport {
+    fn read_line(&mut self, wait: bool) -> Option<String> {
+        loop {
+            let mut byte = 0_u8;
+            let ticks = if wait { WAIT_TICKS } else { 0 };
+            let read =
+                unsafe { uart_read_bytes(CONSOLE_UART, &mut byte as *mut u8 as *mut _, 1, ticks) };
+
+            match read {
+                1 if byte == b'\n' => {
+                    let line = String::from_utf8_lossy(&self.line).trim().to_string();
+                    self.line.clear();
+                    return Some(line);
+                }
+                1 => self.line.push(byte),
+                // the task is halted on purpose, the watchdog must not reset the device
+                _ if wait => unsafe {
+                    esp_task_wdt_reset();
+                },
+                _ => return None,
+            }
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        println!("debug {}", line);
+    }
+}
+
+/// Uninstalls the driver, so that a restarted module can open the console again.
+impl Drop for ConsoleTransport {
+    fn drop(&mut self) {
+        unsafe { uart_driver_delete(CONSOLE_UART) };
+    }
+}
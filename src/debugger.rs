@@ -0,0 +1,321 @@
+use std::collections::HashSet;
+
+use wasmi::{MemoryRef, ModuleRef, RuntimeValue};
+
+use crate::dump::{self, Symbols};
+use crate::imports::{self, HOST_FUNCTIONS};
+use crate::trap::FunctionNames;
+
+/// The most bytes of memory that are sent in one reply.
+const MAX_MEMORY_READ: u32 = 1024;
+
+/// How many function entries and host calls pass between two checks for commands
+/// that arrived while the module runs, like `pause`.
+const POLL_INTERVAL: u32 = 1024;
+
+/// A connection to a debugger client that exchanges lines of text.
+pub(crate) trait Transport {
+    /// Returns the next line sent by the client. Waits for it if `wait` is set,
+    /// otherwise returns `None` if there is none yet.
+    fn read_line(&mut self, wait: bool) -> Option<String>;
+
+    fn write_line(&mut self, line: &str);
+}
+
+/// Debugs a module over a line based protocol. The module halts on breakpoints, which are
+/// set on the entry of a guest function or on the call of a host function. While it is
+/// halted, the client can read its call stack, memory and globals and step to the next
+/// function entry or host call.
+///
+/// Every command is a line, every command is answered by a line starting with `ok` or
+/// `err`. Once the module halts, the debugger sends `halted <reason>`:
+///
+/// ```text
+/// continue | step | pause | detach
+/// break <function index or name> | break-host <host function> | clear <function>
+/// backtrace | memory <offset> <len> | global <index or exported name>
+/// dump [<offset> <len>] | logs
+/// ```
+///
+/// `dump` sends a hexdump of the memory, or the report of [`dump::report`] without
+/// arguments, as lines starting with `| ` before its `ok`. `logs` sends the recent log
+/// lines the same way, if they are kept, see [`Debugger::with_logs`].
+///
+/// The interpreter doesn't expose its frames, so there is no command for the locals of a
+/// function.
+pub(crate) struct Debugger {
+    transport: Box<dyn Transport>,
+    instance: ModuleRef,
+    memory: Option<MemoryRef>,
+    names: FunctionNames,
+    symbols: Symbols,
+    breakpoints: HashSet<u32>,
+    host_breakpoints: HashSet<usize>,
+    // halt on the next function entry or host call
+    stepping: bool,
+    detached: bool,
+    until_poll: u32,
+    logs: Option<fn() -> Vec<String>>,
+}
+
+/// How a command resumes the module.
+enum Resume {
+    Continue,
+    Step,
+    Pause,
+}
+
+impl Debugger {
+    /// Attaches to a module. The module halts on the first function it enters, so that the
+    /// client can set breakpoints before it runs.
+    pub(crate) fn new(
+        transport: Box<dyn Transport>,
+        instance: ModuleRef,
+        memory: Option<MemoryRef>,
+        names: FunctionNames,
+        symbols: Symbols,
+    ) -> Self {
+        Self {
+            transport,
+            instance,
+            memory,
+            names,
+            symbols,
+            breakpoints: HashSet::new(),
+            host_breakpoints: HashSet::new(),
+            stepping: true,
+            detached: false,
+            until_poll: POLL_INTERVAL,
+            logs: None,
+        }
+    }
+
+    /// Answers `logs` with the lines `logs` returns, the oldest first.
+    #[allow(dead_code)] // the simulator doesn't keep log lines
+    pub(crate) fn with_logs(mut self, logs: fn() -> Vec<String>) -> Self {
+        self.logs = Some(logs);
+        self
+    }
+
+    /// Called once the module entered the function with the given index, which is already
+    /// part of the call stack.
+    pub(crate) fn on_enter(&mut self, func_index: u32, call_stack: &[u32]) {
+        if self.detached {
+            return;
+        }
+
+        if self.stepping || self.breakpoints.contains(&func_index) || self.pause_requested() {
+            let reason = format!("entered {}", self.function(func_index));
+            self.halt(&reason, call_stack);
+        }
+    }
+
+    /// Called before the module calls into the host function with the given index.
+    pub(crate) fn on_host_call(&mut self, index: usize, args: &[RuntimeValue], call_stack: &[u32]) {
+        if self.detached {
+            return;
+        }
+
+        if self.stepping || self.host_breakpoints.contains(&index) || self.pause_requested() {
+            let args: Vec<String> = args.iter().map(|arg| format!("{:?}", arg)).collect();
+            let reason = format!("calling {}({})", imports::name_of(index), args.join(", "));
+            self.halt(&reason, call_stack);
+        }
+    }
+
+    /// Called once the module trapped. The module halts, so that its state can be inspected
+    /// before it is gone.
+    pub(crate) fn on_trap(&mut self, reason: &str, call_stack: &[u32]) {
+        if !self.detached {
+            self.halt(&format!("trapped: {}", reason), call_stack);
+        }
+    }
+
+    /// Handles the commands that arrived while the module runs, returns true if one of them
+    /// asked the module to halt.
+    fn pause_requested(&mut self) -> bool {
+        self.until_poll -= 1;
+        if self.until_poll > 0 {
+            return false;
+        }
+        self.until_poll = POLL_INTERVAL;
+
+        let mut halt = false;
+        while let Some(command) = self.transport.read_line(false) {
+            // the call stack is only known while the module is halted
+            match self.execute(&command, &[]) {
+                Some(Resume::Pause) => halt = true,
+                Some(Resume::Step) => self.stepping = true,
+                Some(Resume::Continue) | None => (),
+            }
+        }
+        halt
+    }
+
+    /// Handles commands until the client continues the module.
+    fn halt(&mut self, reason: &str, call_stack: &[u32]) {
+        self.stepping = false;
+        self.transport.write_line(&format!("halted {}", reason));
+
+        loop {
+            let command = match self.transport.read_line(true) {
+                Some(command) => command,
+                // the client is gone, let the module run
+                None => {
+                    self.detached = true;
+                    return;
+                }
+            };
+            match self.execute(&command, call_stack) {
+                Some(Resume::Continue) => return,
+                Some(Resume::Step) => {
+                    self.stepping = true;
+                    return;
+                }
+                Some(Resume::Pause) | None => (),
+            }
+        }
+    }
+
+    /// Executes a command and answers it. Returns how the module is resumed, if the
+    /// command resumes it at all.
+    fn execute(&mut self, command: &str, call_stack: &[u32]) -> Option<Resume> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let (reply, resume) = match words[..] {
+            ["continue"] => (Ok(String::new()), Some(Resume::Continue)),
+            ["step"] => (Ok(String::new()), Some(Resume::Step)),
+            ["pause"] => (Ok(String::new()), Some(Resume::Pause)),
+            ["detach"] => {
+                self.detached = true;
+                (Ok(String::new()), Some(Resume::Continue))
+            }
+            [command, ref args @ ..] => (self.inspect(command, args, call_stack), None),
+            [] => (Err("empty command".into()), None),
+        };
+
+        match reply {
+            Ok(reply) if reply.is_empty() => self.transport.write_line("ok"),
+            Ok(reply) => self.transport.write_line(&format!("ok {}", reply)),
+            Err(err) => self.transport.write_line(&format!("err {}", err)),
+        }
+        resume
+    }
+
+    /// Executes the commands that don't resume the module.
+    fn inspect(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        call_stack: &[u32],
+    ) -> Result<String, String> {
+        match (command, args) {
+            ("break", [function]) => {
+                let index = self.resolve(function)?;
+                self.breakpoints.insert(index);
+                Ok(self.function(index))
+            }
+            ("break-host", [name]) => {
+                let function = HOST_FUNCTIONS
+                    .iter()
+                    .find(|function| function.name == *name)
+                    .ok_or_else(|| format!("unknown host function {}", name))?;
+                self.host_breakpoints.insert(function.index);
+                Ok(function.name.into())
+            }
+            ("clear", [function]) => {
+                let host = HOST_FUNCTIONS.iter().find(|host| host.name == *function);
+                if let Some(host) = host.filter(|host| self.host_breakpoints.remove(&host.index)) {
+                    return Ok(host.name.into());
+                }
+                let index = self.resolve(function)?;
+                match self.breakpoints.remove(&index) {
+                    true => Ok(self.function(index)),
+                    false => Err(format!("no breakpoint at {}", self.function(index))),
+                }
+            }
+            ("backtrace", []) => {
+                let frames: Vec<String> = call_stack
+                    .iter()
+                    .rev()
+                    .map(|&index| self.function(index))
+                    .collect();
+                Ok(frames.join(", "))
+            }
+            ("memory", [offset, len]) => {
+                let offset = parse_u32(offset)?;
+                let len = parse_u32(len)?.min(MAX_MEMORY_READ);
+                let bytes = self
+                    .memory()?
+                    .get(offset, len as usize)
+                    .map_err(|_| format!("{} bytes at {} are out of bounds", len, offset))?;
+                Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+            }
+            ("global", [global]) => {
+                let global = match parse_u32(global) {
+                    Ok(index) => self.instance.globals().get(index as usize).cloned(),
+                    Err(_) => self
+                        .instance
+                        .export_by_name(global)
+                        .and_then(|export| export.as_global().cloned()),
+                }
+                .ok_or_else(|| format!("unknown global {}", global))?;
+                Ok(format!("{:?}", global.get()))
+            }
+            ("dump", []) => {
+                let report = dump::report(&self.instance, self.memory.as_ref(), &self.symbols);
+                self.send_lines(&report);
+                Ok(String::new())
+            }
+            ("dump", [offset, len]) => {
+                let offset = parse_u32(offset)?;
+                let len = parse_u32(len)?.min(MAX_MEMORY_READ);
+                let dump = dump::hexdump(self.memory()?, offset, len, &self.symbols);
+                self.send_lines(&dump);
+                Ok(String::new())
+            }
+            ("logs", []) => {
+                let logs = self.logs.ok_or("the log lines are not kept")?;
+                self.send_lines(&logs().join("\n"));
+                Ok(String::new())
+            }
+            _ => Err(format!("unknown command {}", command)),
+        }
+    }
+
+    fn memory(&self) -> Result<&MemoryRef, String> {
+        self.memory
+            .as_ref()
+            .ok_or_else(|| "the module has no memory the host can access".into())
+    }
+
+    /// Sends a multi-line reply, each line prefixed with `| ` so that it can't be taken
+    /// for the final `ok` or `err`.
+    fn send_lines(&mut self, text: &str) {
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            self.transport.write_line(&format!("| {}", line));
+        }
+    }
+
+    /// Finds a function by its index or name.
+    fn resolve(&self, function: &str) -> Result<u32, String> {
+        parse_u32(function)
+            .ok()
+            .or_else(|| self.names.find(function))
+            .ok_or_else(|| format!("unknown function {}", function))
+    }
+
+    fn function(&self, index: u32) -> String {
+        match self.names.get(index) {
+            Some(name) => format!("{} ({})", index, name),
+            None => index.to_string(),
+        }
+    }
+}
+
+fn parse_u32(number: &str) -> Result<u32, String> {
+    match number.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => number.parse(),
+    }
+    .map_err(|_| format!("invalid number {}", number))
+}
@@ -0,0 +1,260 @@
+use core::fmt::Write;
+use std::collections::HashMap;
+
+use parity_wasm::elements::{self, Instruction, Internal};
+use wasmi::memory_units::Bytes;
+use wasmi::{MemoryRef, ModuleRef, RuntimeValue};
+
+use crate::trap;
+
+/// The ids of the subsections of the name section that hold the global and data segment names.
+const GLOBAL_NAMES: u8 = 7;
+const DATA_SEGMENT_NAMES: u8 = 9;
+
+/// The name LLVM gives the global that holds the stack pointer.
+const STACK_POINTER: &str = "__stack_pointer";
+
+/// The number of bytes printed per line of a hexdump.
+const BYTES_PER_LINE: u32 = 16;
+
+/// The number of bytes above the stack pointer that are part of a report, where the
+/// frames of the innermost functions are.
+const STACK_BYTES: u32 = 256;
+
+/// The names of the globals and data segments of a module, from its name section and
+/// exports. Compilers export `__data_end` and `__heap_base`, the stack pointer is only
+/// named in the name section. DWARF sections are not read.
+#[derive(Clone, Default)]
+pub(crate) struct Symbols {
+    globals: HashMap<u32, String>,
+    /// The offset, length and name of every active data segment.
+    segments: Vec<(u32, u32, String)>,
+}
+
+impl Symbols {
+    pub(crate) fn from_wasm(wasm: &[u8]) -> Self {
+        let mut globals = trap::name_map(wasm, GLOBAL_NAMES);
+        let segment_names = trap::name_map(wasm, DATA_SEGMENT_NAMES);
+        let mut segments = Vec::new();
+
+        if let Ok(module) = elements::deserialize_buffer::<elements::Module>(wasm) {
+            for export in module
+                .export_section()
+                .map_or(&[][..], |section| section.entries())
+            {
+                if let Internal::Global(index) = export.internal() {
+                    globals
+                        .entry(*index)
+                        .or_insert_with(|| export.field().into());
+                }
+            }
+
+            let entries = module
+                .data_section()
+                .map_or(&[][..], |section| section.entries());
+            for (index, segment) in entries.iter().enumerate() {
+                let offset = match segment.offset().as_ref().map(|init| init.code()) {
+                    Some([Instruction::I32Const(offset), Instruction::End]) => *offset as u32,
+                    _ => continue,
+                };
+                let name = segment_names
+                    .get(&(index as u32))
+                    .cloned()
+                    .unwrap_or_else(|| format!("data[{}]", index));
+                segments.push((offset, segment.value().len() as u32, name));
+            }
+        }
+
+        Self { globals, segments }
+    }
+
+    /// Returns the first data segment that overlaps the bytes from `start` to `end`, with
+    /// the offset of the first overlapping byte into it.
+    fn segment_in(&self, start: u32, end: u32) -> Option<(&str, u32)> {
+        self.segments
+            .iter()
+            .find(|(offset, len, _)| *offset < end && start < offset.saturating_add(*len))
+            .map(|(offset, _, name)| (name.as_str(), start.saturating_sub(*offset)))
+    }
+
+    /// Returns the index of the global that holds the stack pointer. Without names, it is
+    /// assumed to be the first global, which is where LLVM puts it.
+    fn stack_pointer(&self) -> u32 {
+        self.globals
+            .iter()
+            .find(|(_, name)| *name == STACK_POINTER)
+            .map_or(0, |(&index, _)| index)
+    }
+}
+
+/// Returns a hexdump of `len` bytes of the memory at `offset`, each line annotated with
+/// the data segment it overlaps. The part out of bounds of the memory is left out.
+pub(crate) fn hexdump(memory: &MemoryRef, offset: u32, len: u32, symbols: &Symbols) -> String {
+    let size = Bytes::from(memory.current_size()).0 as u32;
+    let end = offset.saturating_add(len).min(size);
+    let mut dump = String::new();
+
+    for line in (offset..end).step_by(BYTES_PER_LINE as usize) {
+        let bytes = memory
+            .get(line, (end - line).min(BYTES_PER_LINE) as usize)
+            .unwrap_or_default();
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+
+        let _ = write!(
+            dump,
+            "\n{:#010x}  {:<47}  |{:<16}|",
+            line,
+            hex.join(" "),
+            ascii
+        );
+        if let Some((segment, offset)) = symbols.segment_in(line, line + bytes.len() as u32) {
+            let _ = write!(dump, "  {}+{:#x}", segment, offset);
+        }
+    }
+
+    dump
+}
+
+/// Returns a report of the memory of a module: its size, the values of its globals, its
+/// data segments and the top of its stack. Without a memory, only the globals are left.
+pub(crate) fn report(
+    instance: &ModuleRef,
+    memory: Option<&MemoryRef>,
+    symbols: &Symbols,
+) -> String {
+    let mut report = match memory {
+        Some(memory) => format!(
+            "memory: {} pages ({} bytes), grown from {} pages, at most {}",
+            memory.current_size().0,
+            Bytes::from(memory.current_size()).0,
+            memory.initial().0,
+            memory
+                .maximum()
+                .map_or("none".into(), |pages| pages.0.to_string())
+        ),
+        None => "memory: none the host can access".into(),
+    };
+
+    report.push_str("\nglobals:");
+    for (index, global) in instance.globals().iter().enumerate() {
+        let name = symbols
+            .globals
+            .get(&(index as u32))
+            .map_or("", String::as_str);
+        let _ = write!(report, "\n  {} {} = {:?}", index, name, global.get());
+    }
+
+    if !symbols.segments.is_empty() {
+        report.push_str("\ndata segments:");
+        for (offset, len, name) in &symbols.segments {
+            let _ = write!(
+                report,
+                "\n  {} {:#010x}..{:#010x}",
+                name,
+                offset,
+                offset.saturating_add(*len)
+            );
+        }
+    }
+
+    let stack_pointer = instance
+        .globals()
+        .get(symbols.stack_pointer() as usize)
+        .filter(|global| global.is_mutable())
+        .map(|global| global.get());
+    if let (Some(memory), Some(RuntimeValue::I32(stack_pointer))) = (memory, stack_pointer) {
+        let _ = write!(report, "\nstack at {:#010x}:", stack_pointer);
+        report.push_str(&hexdump(memory, stack_pointer as u32, STACK_BYTES, symbols));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmi::memory_units::Pages;
+    use wasmi::MemoryInstance;
+
+    fn memory() -> MemoryRef {
+        MemoryInstance::alloc(Pages(1), None).unwrap()
+    }
+
+    /// Returns a module without any sections but a name section, which holds the names of
+    /// the globals.
+    fn named_globals(names: &[(u8, &str)]) -> Vec<u8> {
+        let mut subsection = vec![names.len() as u8];
+        for (index, name) in names {
+            subsection.extend([*index, name.len() as u8]);
+            subsection.extend(name.as_bytes());
+        }
+        let mut section = b"\x04name".to_vec();
+        section.extend([GLOBAL_NAMES, subsection.len() as u8]);
+        section.extend(subsection);
+
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        wasm.extend([0, section.len() as u8]);
+        wasm.extend(section);
+        wasm
+    }
+
+    #[test]
+    fn a_line_is_cut_at_the_end_of_the_memory() {
+        let dump = hexdump(&memory(), 0xfff8, 32, &Symbols::default());
+        assert_eq!(
+            dump,
+            "\n0x0000fff8  00 00 00 00 00 00 00 00                          |........        |"
+        );
+        assert_eq!(hexdump(&memory(), 0x10000, 16, &Symbols::default()), "");
+    }
+
+    #[test]
+    fn a_line_names_the_first_segment_it_overlaps() {
+        let symbols = Symbols {
+            segments: vec![(8, 12, "table".into()), (40, 4, "name".into())],
+            ..Default::default()
+        };
+        let dump = hexdump(&memory(), 16, 32, &symbols);
+        let zeros = "00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  |................|";
+        assert_eq!(
+            dump,
+            format!(
+                "\n0x00000010  {}  table+0x8\n0x00000020  {}  name+0x0",
+                zeros, zeros
+            )
+        );
+    }
+
+    #[test]
+    fn bytes_that_are_not_printable_are_dots() {
+        let memory = memory();
+        memory
+            .set(0, &[0x41, 0x7f, 0xe9, 0x20, 0x7e, 0x00])
+            .unwrap();
+        let dump = hexdump(&memory, 0, 6, &Symbols::default());
+        assert_eq!(
+            dump,
+            "\n0x00000000  41 7f e9 20 7e 00                                |A.. ~.          |"
+        );
+    }
+
+    #[test]
+    fn the_stack_pointer_is_found_by_its_name() {
+        let wasm = named_globals(&[(0, "counter"), (2, STACK_POINTER)]);
+        assert_eq!(Symbols::from_wasm(&wasm).stack_pointer(), 2);
+    }
+
+    #[test]
+    fn without_a_name_the_stack_pointer_is_the_first_global() {
+        let wasm = named_globals(&[(1, "counter")]);
+        assert_eq!(Symbols::from_wasm(&wasm).stack_pointer(), 0);
+        assert_eq!(Symbols::from_wasm(b"\0asm\x01\0\0\0").stack_pointer(), 0);
+    }
+}
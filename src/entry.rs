@@ -0,0 +1,445 @@
+use core::fmt;
+use std::borrow::Cow;
+use std::time::Duration;
+
+use wasmi::{ModuleRef, RuntimeValue, ValueType};
+
+/// The entry point of the modules written before it was configurable. Like the C `start`,
+/// it returns an error code.
+pub(crate) const START: Entry = Entry::Call(Call::with_error_codes("start", &[]));
+
+/// An exported function of a module that the host calls, with its arguments.
+#[derive(Clone, Debug)]
+pub(crate) struct Call {
+    pub(crate) export: Cow<'static, str>,
+    pub(crate) args: Cow<'static, [RuntimeValue]>,
+    /// Whether an integer result other than zero is an error code, like the `int` C
+    /// functions return, that fails the module. Otherwise the result is a plain value.
+    pub(crate) error_codes: bool,
+}
+
+impl Call {
+    /// Calls the export with the arguments, which have to match its parameters. Its result
+    /// is a plain value.
+    pub(crate) const fn new(export: &'static str, args: &'static [RuntimeValue]) -> Self {
+        Self {
+            export: Cow::Borrowed(export),
+            args: Cow::Borrowed(args),
+            error_codes: false,
+        }
+    }
+
+    /// Calls the export like [`Call::new`], an integer result other than zero is an error code.
+    pub(crate) const fn with_error_codes(
+        export: &'static str,
+        args: &'static [RuntimeValue],
+    ) -> Self {
+        let mut call = Self::new(export, args);
+        call.error_codes = true;
+        call
+    }
+
+    /// Parses an export and its arguments, separated by whitespace. Every argument is
+    /// prefixed with its type, e.g. `blink i32:8 f32:0.5`. The result is a plain value,
+    /// unless the call starts with `checked`, e.g. `checked start`.
+    #[allow(dead_code)] // used by tools/simulator
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let mut words = text.split_whitespace().peekable();
+        let error_codes = words.next_if_eq(&"checked").is_some();
+        let export = words
+            .next()
+            .ok_or_else(|| String::from("the call names no export"))?;
+        let args = words.map(parse_arg).collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            export: Cow::Owned(export.into()),
+            args: Cow::Owned(args),
+            error_codes,
+        })
+    }
+
+    /// Checks that the module exports the function and that the arguments match its
+    /// parameters, before anything of it runs.
+    fn check(&self, instance: &ModuleRef) -> Result<(), String> {
+        let func = instance
+            .export_by_name(&self.export)
+            .and_then(|export| export.as_func().cloned())
+            .ok_or_else(|| format!("the module exports no function {}", self.export))?;
+
+        let params = func.signature().params();
+        let args: Vec<ValueType> = self.args.iter().map(RuntimeValue::value_type).collect();
+        if params != args.as_slice() {
+            return Err(format!(
+                "{} takes {:?}, but is called with {:?}",
+                self.export, params, args
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self.args.iter().map(describe).collect();
+        write!(f, "{}({})", self.export, args.join(", "))
+    }
+}
+
+/// Describes an argument or result like it is parsed, prefixed with its type.
+pub(crate) fn describe(value: &RuntimeValue) -> String {
+    match value {
+        RuntimeValue::I32(value) => format!("i32:{}", value),
+        RuntimeValue::I64(value) => format!("i64:{}", value),
+        RuntimeValue::F32(value) => format!("f32:{}", value.to_float()),
+        RuntimeValue::F64(value) => format!("f64:{}", value.to_float()),
+    }
+}
+
+/// Parses an argument prefixed with its type, e.g. `i32:-1`.
+fn parse_arg(text: &str) -> Result<RuntimeValue, String> {
+    let invalid = || format!("invalid argument: {}", text);
+    let (kind, value) = text.split_once(':').ok_or_else(invalid)?;
+
+    match kind {
+        "i32" => value
+            .parse::<i32>()
+            .map(RuntimeValue::I32)
+            .map_err(|_| invalid()),
+        "i64" => value
+            .parse::<i64>()
+            .map(RuntimeValue::I64)
+            .map_err(|_| invalid()),
+        "f32" => value
+            .parse::<f32>()
+            .map(|value| RuntimeValue::F32(value.into()))
+            .map_err(|_| invalid()),
+        "f64" => value
+            .parse::<f64>()
+            .map(|value| RuntimeValue::F64(value.into()))
+            .map_err(|_| invalid()),
+        _ => Err(invalid()),
+    }
+}
+
+/// The functions the host calls to run a module, like `setup` and `loop` of Arduino. Their
+/// results are error codes when they are called [`Call::with_error_codes`].
+#[derive(Clone, Debug)]
+pub(crate) struct Lifecycle {
+    /// Called once, before `run`.
+    pub(crate) init: Option<Call>,
+    /// Called again and again, until it returns an error code or ran `iterations` times.
+    pub(crate) run: Call,
+    /// Called once `run` returned an error code or ran `iterations` times. It is not called
+    /// once the module trapped, its state can't be trusted anymore.
+    pub(crate) deinit: Option<Call>,
+    /// How long the host waits between two calls of `run`, other tasks run meanwhile.
+    pub(crate) interval: Duration,
+    /// The number of calls of `run` after which the module is finished, if any.
+    pub(crate) iterations: Option<u32>,
+}
+
+/// How the host runs a module.
+#[derive(Clone, Debug)]
+pub(crate) enum Entry {
+    /// Calls the export once, the module is finished once it returned.
+    Call(Call),
+    /// Drives the module through its lifecycle.
+    Lifecycle(Lifecycle),
+}
+
+impl Entry {
+    /// Parses an entry point: either a call, e.g. `start`, `checked start` or `blink i32:8`,
+    /// or a lifecycle of the init, run and deinit exports, e.g. `lifecycle setup loop -`
+    /// without deinit. The exports of a lifecycle return error codes.
+    #[allow(dead_code)] // used by tools/simulator
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let checked = |export: &str| Call {
+            export: Cow::Owned(export.into()),
+            args: Cow::Borrowed(&[]),
+            error_codes: true,
+        };
+        let optional = |export: &str| match export {
+            "-" => None,
+            export => Some(checked(export)),
+        };
+
+        match words[..] {
+            ["lifecycle", init, run, deinit] => Ok(Entry::Lifecycle(Lifecycle {
+                init: optional(init),
+                run: checked(run),
+                deinit: optional(deinit),
+                interval: Duration::ZERO,
+                iterations: None,
+            })),
+            ["lifecycle", ..] => Err(format!("invalid lifecycle: {}", text)),
+            _ => Call::parse(text).map(Entry::Call),
+        }
+    }
+
+    /// Checks that the module exports every function of the entry point, with the
+    /// parameters they are called with.
+    pub(crate) fn check(&self, instance: &ModuleRef) -> Result<(), String> {
+        match self {
+            Entry::Call(call) => call.check(instance),
+            Entry::Lifecycle(lifecycle) => {
+                let optional = lifecycle.init.iter().chain(lifecycle.deinit.iter());
+                for call in optional.chain([&lifecycle.run]) {
+                    call.check(instance)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Default for Entry {
+    /// Calls `start` without arguments.
+    fn default() -> Self {
+        START
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Call(call) => write!(f, "{}", call),
+            Entry::Lifecycle(lifecycle) => {
+                if let Some(init) = &lifecycle.init {
+                    write!(f, "{}, then ", init)?;
+                }
+                write!(f, "{} in a loop", lifecycle.run)?;
+                if let Some(deinit) = &lifecycle.deinit {
+                    write!(f, ", then {}", deinit)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Why the entry point of a module stopped early.
+#[derive(Debug)]
+pub(crate) enum Failure {
+    /// The module trapped, or an export couldn't be called.
+    Error(wasmi::Error),
+    /// An export returned an error code.
+    ErrorCode { export: String, code: i64 },
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Error(err) => write!(f, "{}", err),
+            Failure::ErrorCode { export, code } => {
+                write!(f, "{} returned the error code {}", export, code)
+            }
+        }
+    }
+}
+
+/// Calls the exports of a module for [`run`].
+pub(crate) trait Invoker {
+    /// Calls the export with its arguments, returns its result.
+    fn invoke(&mut self, call: &Call) -> Result<Option<RuntimeValue>, wasmi::Error>;
+
+    /// Waits between two calls of `run` of a lifecycle. Returns false if the module is
+    /// stopped right away, without deinit.
+    fn pause(&mut self, interval: Duration) -> bool;
+}
+
+/// Returns the error code in the result of an export: every integer but zero, like the
+/// `int` C functions return. Floats and functions without a result never fail.
+pub(crate) fn error_code(result: Option<RuntimeValue>) -> Option<i64> {
+    match result {
+        Some(RuntimeValue::I32(code)) if code != 0 => Some(code as i64),
+        Some(RuntimeValue::I64(code)) if code != 0 => Some(code),
+        _ => None,
+    }
+}
+
+/// Runs the module from its entry point. Returns the result of a single call, or why the
+/// module stopped early.
+pub(crate) fn run(
+    entry: &Entry,
+    invoker: &mut dyn Invoker,
+) -> Result<Option<RuntimeValue>, Failure> {
+    match entry {
+        Entry::Call(call) => call_checked(call, invoker),
+        Entry::Lifecycle(lifecycle) => {
+            if let Some(init) = &lifecycle.init {
+                call_checked(init, invoker)?;
+            }
+
+            let mut iterations = 0;
+            let result = loop {
+                if let Err(failure) = call_checked(&lifecycle.run, invoker) {
+                    break Err(failure);
+                }
+                iterations += 1;
+                if Some(iterations) == lifecycle.iterations {
+                    break Ok(None);
+                }
+                if !invoker.pause(lifecycle.interval) {
+                    return Ok(None);
+                }
+            };
+
+            // a module that trapped is not called again
+            if let Err(Failure::Error(err)) = result {
+                return Err(Failure::Error(err));
+            }
+            if let Some(deinit) = &lifecycle.deinit {
+                call_checked(deinit, invoker)?;
+            }
+            result
+        }
+    }
+}
+
+/// Calls the export and turns the error code it returned into a failure, if its results
+/// are error codes.
+fn call_checked(call: &Call, invoker: &mut dyn Invoker) -> Result<Option<RuntimeValue>, Failure> {
+    let result = invoker.invoke(call).map_err(Failure::Error)?;
+    match error_code(result).filter(|_| call.error_codes) {
+        Some(code) => Err(Failure::ErrorCode {
+            export: call.export.to_string(),
+            code,
+        }),
+        None => Ok(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_are_parsed_with_their_type() {
+        assert_eq!(parse_arg("i32:-1"), Ok(RuntimeValue::I32(-1)));
+        assert_eq!(
+            parse_arg("i64:-9000000000"),
+            Ok(RuntimeValue::I64(-9_000_000_000))
+        );
+        assert_eq!(parse_arg("f32:0.5"), Ok(RuntimeValue::F32(0.5.into())));
+        assert_eq!(
+            parse_arg("f64:-1e300"),
+            Ok(RuntimeValue::F64((-1e300).into()))
+        );
+        assert!(matches!(parse_arg("f32:NaN"), Ok(RuntimeValue::F32(value)) if value.is_nan()));
+
+        for invalid in ["8", "i32:", "i32:2147483648", "i32:0.5", "u8:1", "f64:one"] {
+            assert_eq!(
+                parse_arg(invalid),
+                Err(format!("invalid argument: {}", invalid))
+            );
+        }
+    }
+
+    #[test]
+    fn a_call_names_its_export_and_arguments() {
+        let call = Call::parse("  blink i32:8   f32:0.5 ").unwrap();
+        assert_eq!(call.export, "blink");
+        assert_eq!(
+            *call.args,
+            [RuntimeValue::I32(8), RuntimeValue::F32(0.5.into())]
+        );
+        assert_eq!(call.to_string(), "blink(i32:8, f32:0.5)");
+        assert!(!call.error_codes);
+
+        let call = Call::parse("checked blink i32:8").unwrap();
+        assert_eq!(call.export, "blink");
+        assert!(call.error_codes);
+
+        assert!(Call::parse("").is_err());
+        assert!(Call::parse("blink 8").is_err());
+    }
+
+    #[test]
+    fn entries_are_calls_or_lifecycles() {
+        match Entry::parse("start").unwrap() {
+            Entry::Call(call) => assert!(call.export == "start" && call.args.is_empty()),
+            entry => panic!("unexpected entry {}", entry),
+        }
+
+        let entry = Entry::parse("lifecycle setup loop -").unwrap();
+        match &entry {
+            Entry::Lifecycle(lifecycle) => {
+                assert_eq!(lifecycle.init.as_ref().unwrap().export, "setup");
+                assert_eq!(lifecycle.run.export, "loop");
+                assert!(lifecycle.run.error_codes);
+                assert!(lifecycle.deinit.is_none());
+                assert_eq!(lifecycle.iterations, None);
+            }
+            entry => panic!("unexpected entry {}", entry),
+        }
+        assert_eq!(entry.to_string(), "setup(), then loop() in a loop");
+
+        assert_eq!(
+            Entry::parse("lifecycle setup loop").unwrap_err(),
+            "invalid lifecycle: lifecycle setup loop"
+        );
+        assert_eq!(Entry::default().to_string(), "start()");
+    }
+
+    /// Answers every call with the same result and counts the calls and pauses.
+    struct Answer {
+        result: Option<RuntimeValue>,
+        calls: Vec<String>,
+        pauses: u32,
+    }
+
+    impl Invoker for Answer {
+        fn invoke(&mut self, call: &Call) -> Result<Option<RuntimeValue>, wasmi::Error> {
+            self.calls.push(call.export.to_string());
+            Ok(self.result)
+        }
+
+        fn pause(&mut self, _interval: Duration) -> bool {
+            self.pauses += 1;
+            self.pauses < 3
+        }
+    }
+
+    fn answer(result: RuntimeValue) -> Answer {
+        Answer {
+            result: Some(result),
+            calls: Vec::new(),
+            pauses: 0,
+        }
+    }
+
+    #[test]
+    fn only_checked_calls_fail_with_error_codes() {
+        let plain = Entry::parse("add i32:2 i32:3").unwrap();
+        assert!(matches!(
+            run(&plain, &mut answer(RuntimeValue::I32(5))),
+            Ok(Some(RuntimeValue::I32(5)))
+        ));
+
+        let checked = Entry::parse("checked add i32:2 i32:3").unwrap();
+        let failure = run(&checked, &mut answer(RuntimeValue::I32(5))).unwrap_err();
+        assert_eq!(failure.to_string(), "add returned the error code 5");
+        assert!(matches!(
+            run(&START, &mut answer(RuntimeValue::I64(0))),
+            Ok(Some(RuntimeValue::I64(0)))
+        ));
+    }
+
+    #[test]
+    fn a_lifecycle_loops_until_it_is_stopped_or_fails() {
+        let entry = Entry::parse("lifecycle setup loop deinit").unwrap();
+
+        // the invoker stops the loop on the third pause
+        let mut invoker = answer(RuntimeValue::I32(0));
+        assert!(matches!(run(&entry, &mut invoker), Ok(None)));
+        assert_eq!(invoker.calls, ["setup", "loop", "loop", "loop"]);
+
+        // an error code of setup stops the module right away
+        let mut invoker = answer(RuntimeValue::I32(-2));
+        let failure = run(&entry, &mut invoker).unwrap_err();
+        assert_eq!(failure.to_string(), "setup returned the error code -2");
+        assert_eq!(invoker.calls, ["setup"]);
+    }
+}
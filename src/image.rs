@@ -0,0 +1,107 @@
+use std::borrow::Cow;
+
+/// The magic bytes of a compressed module image. A plain module starts with `\0asm` instead.
+const MAGIC: &[u8; 4] = b"WLZ4";
+
+/// The largest module that gets decompressed, so that a corrupt image can't exhaust the heap.
+pub(crate) const MAX_MODULE_SIZE: usize = 256 * 1024;
+
+/// Compresses a module into an image: the magic bytes, the size of the module as
+/// little endian `u32` and the module as LZ4 block.
+#[allow(dead_code)] // used by build.rs and tools/module-image
+pub(crate) fn compress(wasm: &[u8]) -> Vec<u8> {
+    let mut image = MAGIC.to_vec();
+    image.extend_from_slice(&(wasm.len() as u32).to_le_bytes());
+    image.append(&mut lz4_flex::block::compress(wasm));
+    image
+}
+
+/// Returns the module stored in an image. Compressed images are decompressed into
+/// the heap, plain modules are returned as they are.
+pub(crate) fn decompress(image: &[u8]) -> Result<Cow<'_, [u8]>, String> {
+    let compressed = match image.strip_prefix(MAGIC) {
+        Some(compressed) => compressed,
+        None => return Ok(Cow::Borrowed(image)),
+    };
+
+    let size = compressed
+        .get(..4)
+        .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+        .ok_or("the module image is truncated")?;
+    if size > MAX_MODULE_SIZE {
+        return Err(format!(
+            "the module is too large ({} bytes, at most {} are supported)",
+            size, MAX_MODULE_SIZE
+        ));
+    }
+
+    let wasm = lz4_flex::block::decompress(&compressed[4..], size)
+        .map_err(|err| format!("could not decompress the module: {}", err))?;
+    if wasm.len() != size {
+        return Err("the module image is corrupt".into());
+    }
+
+    Ok(Cow::Owned(wasm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A module of `size` bytes that doesn't compress to nothing.
+    fn module(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn an_empty_module_round_trips() {
+        let image = compress(&[]);
+        assert_eq!(&image[..4], MAGIC);
+        assert!(decompress(&image).unwrap().is_empty());
+    }
+
+    #[test]
+    fn the_largest_module_round_trips() {
+        let wasm = module(MAX_MODULE_SIZE);
+        assert_eq!(*decompress(&compress(&wasm)).unwrap(), *wasm);
+    }
+
+    #[test]
+    fn a_module_over_the_size_limit_is_rejected() {
+        let image = compress(&module(MAX_MODULE_SIZE + 1));
+        let err = decompress(&image).unwrap_err();
+        assert!(err.contains("too large"), "{}", err);
+    }
+
+    #[test]
+    fn a_truncated_header_is_rejected() {
+        let image = compress(&module(64));
+        for len in 4..8 {
+            assert_eq!(
+                decompress(&image[..len]).unwrap_err(),
+                "the module image is truncated"
+            );
+        }
+    }
+
+    #[test]
+    fn a_corrupt_image_is_rejected() {
+        let wasm = module(1024);
+        let mut image = compress(&wasm);
+
+        // a size that doesn't match the compressed module
+        image[4..8].copy_from_slice(&2048_u32.to_le_bytes());
+        assert!(decompress(&image).is_err());
+
+        // a compressed module that is cut off
+        image[4..8].copy_from_slice(&1024_u32.to_le_bytes());
+        assert!(decompress(&image[..image.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn an_image_without_the_magic_is_not_decompressed() {
+        let mut image = compress(&module(64));
+        image[0] = b'X';
+        assert!(matches!(decompress(&image), Ok(Cow::Borrowed(plain)) if plain == &image[..]));
+    }
+}
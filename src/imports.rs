@@ -0,0 +1,370 @@
+use log::info;
+use wasmi::{
+    FuncInstance, MemoryDescriptor, MemoryRef, ModuleImportResolver, Signature, ValueType,
+};
+
+use crate::manifest::Manifest;
+use crate::memory::HostMemory;
+use crate::metering::{CONSUME_FUEL, ENTER_FUNCTION, LEAVE_FUNCTION, MEMORY_GROW};
+
+/// Internal index of the functions.
+pub(crate) const UART_WRITE_INDEX: usize = 0;
+pub(crate) const UART_READ_INDEX: usize = 1;
+pub(crate) const UART_INIT_INDEX: usize = 2;
+pub(crate) const PRINT_INDEX: usize = 3;
+pub(crate) const GPIO_WRITE_INDEX: usize = 4;
+pub(crate) const GPIO_READ_INDEX: usize = 5;
+pub(crate) const GPIO_INIT_INDEX: usize = 6;
+pub(crate) const GPIO_DEINIT_INDEX: usize = 7;
+pub(crate) const DELAY_MS_INDEX: usize = 8;
+pub(crate) const TIME_NOW_US_INDEX: usize = 9;
+pub(crate) const UPTIME_MS_INDEX: usize = 10;
+pub(crate) const TIMER_START_INDEX: usize = 11;
+pub(crate) const TIMER_CANCEL_INDEX: usize = 12;
+pub(crate) const TIMER_POLL_INDEX: usize = 13;
+pub(crate) const DELAY_US_INDEX: usize = 14;
+pub(crate) const KV_GET_INDEX: usize = 15;
+pub(crate) const KV_SET_INDEX: usize = 16;
+pub(crate) const KV_DELETE_INDEX: usize = 17;
+pub(crate) const KV_LIST_INDEX: usize = 18;
+pub(crate) const RANDOM_FILL_INDEX: usize = 19;
+pub(crate) const CONSUME_FUEL_INDEX: usize = 20;
+pub(crate) const CHAN_OPEN_INDEX: usize = 21;
+pub(crate) const CHAN_SEND_INDEX: usize = 22;
+pub(crate) const CHAN_RECV_INDEX: usize = 23;
+// every import that is denied by the manifest of the module resolves to this index
+pub(crate) const PERMISSION_DENIED_INDEX: usize = 24;
+pub(crate) const ENTER_FUNCTION_INDEX: usize = 25;
+pub(crate) const LEAVE_FUNCTION_INDEX: usize = 26;
+pub(crate) const REPORT_PANIC_INDEX: usize = 27;
+pub(crate) const LOG_INDEX: usize = 28;
+pub(crate) const LOG_MAX_LEVEL_INDEX: usize = 29;
+pub(crate) const PROFILE_MARK_INDEX: usize = 30;
+pub(crate) const MEMORY_GROW_INDEX: usize = 31;
+
+/// A function of the host that modules can import.
+pub(crate) struct HostFunction {
+    pub(crate) name: &'static str,
+    /// The index the function is dispatched by in `Runtime::invoke_index`.
+    pub(crate) index: usize,
+    params: &'static [ValueType],
+    result: Option<ValueType>,
+}
+
+/// All functions of the host a module can import from `env`.
+pub(crate) const HOST_FUNCTIONS: &[HostFunction] = &[
+    HostFunction {
+        name: "uart_init",
+        index: UART_INIT_INDEX,
+        params: &[
+            ValueType::I32, // handle
+            ValueType::I32, // tx port
+            ValueType::I32, // tx pin
+            ValueType::I32, // rx port
+            ValueType::I32, // rx pin
+            ValueType::I32, // cts port ptr
+            ValueType::I32, // cts pin ptr
+            ValueType::I32, // rts port ptr
+            ValueType::I32, // rts pin ptr
+        ],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "uart_write",
+        index: UART_WRITE_INDEX,
+        params: &[ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "uart_read",
+        index: UART_READ_INDEX,
+        params: &[ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "print",
+        index: PRINT_INDEX,
+        params: &[ValueType::I32, ValueType::I32],
+        result: None,
+    },
+    HostFunction {
+        name: "gpio_read",
+        index: GPIO_READ_INDEX,
+        params: &[ValueType::I32, ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "gpio_write",
+        index: GPIO_WRITE_INDEX,
+        params: &[ValueType::I32, ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "gpio_init",
+        index: GPIO_INIT_INDEX,
+        params: &[ValueType::I32, ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "gpio_deinit",
+        index: GPIO_DEINIT_INDEX,
+        params: &[ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "delay_ms",
+        index: DELAY_MS_INDEX,
+        params: &[ValueType::I32],
+        result: None,
+    },
+    HostFunction {
+        name: "delay_us",
+        index: DELAY_US_INDEX,
+        params: &[ValueType::I32],
+        result: None,
+    },
+    HostFunction {
+        name: "kv_get",
+        index: KV_GET_INDEX,
+        params: &[
+            ValueType::I32, // key ptr
+            ValueType::I32, // key len
+            ValueType::I32, // buffer ptr
+            ValueType::I32, // buffer capacity
+            ValueType::I32, // value len ptr
+        ],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "kv_set",
+        index: KV_SET_INDEX,
+        params: &[
+            ValueType::I32, // key ptr
+            ValueType::I32, // key len
+            ValueType::I32, // value ptr
+            ValueType::I32, // value len
+        ],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "kv_delete",
+        index: KV_DELETE_INDEX,
+        params: &[ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "kv_list",
+        index: KV_LIST_INDEX,
+        params: &[ValueType::I32, ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "random_fill",
+        index: RANDOM_FILL_INDEX,
+        params: &[ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "chan_open",
+        index: CHAN_OPEN_INDEX,
+        params: &[ValueType::I32, ValueType::I32, ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "chan_send",
+        index: CHAN_SEND_INDEX,
+        params: &[
+            ValueType::I32, // handle
+            ValueType::I32, // message ptr
+            ValueType::I32, // message len
+            ValueType::I64, // timeout in us
+        ],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "chan_recv",
+        index: CHAN_RECV_INDEX,
+        params: &[
+            ValueType::I32, // handle
+            ValueType::I32, // buffer ptr
+            ValueType::I32, // buffer capacity
+            ValueType::I64, // timeout in us
+            ValueType::I32, // message len ptr
+        ],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "report_panic",
+        index: REPORT_PANIC_INDEX,
+        params: &[
+            ValueType::I32, // message ptr
+            ValueType::I32, // message len
+            ValueType::I32, // file ptr
+            ValueType::I32, // file len
+            ValueType::I32, // line
+        ],
+        result: None,
+    },
+    HostFunction {
+        name: "log",
+        index: LOG_INDEX,
+        params: &[
+            ValueType::I32, // level
+            ValueType::I32, // target ptr
+            ValueType::I32, // target len
+            ValueType::I32, // message ptr
+            ValueType::I32, // message len
+        ],
+        result: None,
+    },
+    HostFunction {
+        name: "log_max_level",
+        index: LOG_MAX_LEVEL_INDEX,
+        params: &[],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "profile_mark",
+        index: PROFILE_MARK_INDEX,
+        params: &[ValueType::I32],
+        result: None,
+    },
+    HostFunction {
+        name: "time_now_us",
+        index: TIME_NOW_US_INDEX,
+        params: &[],
+        result: Some(ValueType::I64),
+    },
+    HostFunction {
+        name: "uptime_ms",
+        index: UPTIME_MS_INDEX,
+        params: &[],
+        result: Some(ValueType::I64),
+    },
+    HostFunction {
+        name: "timer_start",
+        index: TIMER_START_INDEX,
+        params: &[
+            ValueType::I64, // period in us
+            ValueType::I32, // periodic
+            ValueType::I32, // callback index
+            ValueType::I32, // handle ptr
+        ],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "timer_cancel",
+        index: TIMER_CANCEL_INDEX,
+        params: &[ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+    HostFunction {
+        name: "timer_poll",
+        index: TIMER_POLL_INDEX,
+        params: &[ValueType::I64],
+        result: Some(ValueType::I32),
+    },
+];
+
+/// The functions of the host the instrumentation injects into every module, which the
+/// module can't import itself.
+pub(crate) const INSTRUMENTATION_FUNCTIONS: &[HostFunction] = &[
+    HostFunction {
+        name: CONSUME_FUEL,
+        index: CONSUME_FUEL_INDEX,
+        params: &[ValueType::I32],
+        result: None,
+    },
+    HostFunction {
+        name: ENTER_FUNCTION,
+        index: ENTER_FUNCTION_INDEX,
+        params: &[ValueType::I32],
+        result: None,
+    },
+    HostFunction {
+        name: LEAVE_FUNCTION,
+        index: LEAVE_FUNCTION_INDEX,
+        params: &[],
+        result: None,
+    },
+    HostFunction {
+        name: MEMORY_GROW,
+        index: MEMORY_GROW_INDEX,
+        params: &[ValueType::I32],
+        result: Some(ValueType::I32),
+    },
+];
+
+/// Returns the name of the host function with the given index.
+pub(crate) fn name_of(index: usize) -> &'static str {
+    match index {
+        PERMISSION_DENIED_INDEX => "<denied>",
+        _ => HOST_FUNCTIONS
+            .iter()
+            .chain(INSTRUMENTATION_FUNCTIONS)
+            .find(|function| function.index == index)
+            .map_or("<unknown>", |function| function.name),
+    }
+}
+
+/// Resolves the functions the instrumentation imports from `__instrumentation`.
+pub(crate) struct InstrumentationResolver;
+
+impl ModuleImportResolver for InstrumentationResolver {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        _signature: &wasmi::Signature,
+    ) -> Result<wasmi::FuncRef, wasmi::Error> {
+        let function = INSTRUMENTATION_FUNCTIONS
+            .iter()
+            .find(|function| function.name == field_name)
+            .ok_or_else(|| wasmi::Error::Function(format!("unknown function {}", field_name)))?;
+        let signature = Signature::new(function.params, function.result);
+
+        Ok(FuncInstance::alloc_host(signature, function.index))
+    }
+}
+
+/// Resolves external functions on the host system. Functions that are not granted by
+/// the manifest of the module still resolve, but always fail with `PERMISSION_DENIED`.
+/// An imported memory is allocated by `memory`.
+pub(crate) struct UartModuleImportResolver<'a> {
+    manifest: &'a Manifest,
+    memory: &'a HostMemory,
+}
+
+impl<'a> UartModuleImportResolver<'a> {
+    pub(crate) fn new(manifest: &'a Manifest, memory: &'a HostMemory) -> Self {
+        Self { manifest, memory }
+    }
+}
+
+impl<'a> ModuleImportResolver for UartModuleImportResolver<'a> {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        _signature: &wasmi::Signature,
+    ) -> Result<wasmi::FuncRef, wasmi::Error> {
+        let function = HOST_FUNCTIONS
+            .iter()
+            .find(|function| function.name == field_name)
+            .ok_or_else(|| wasmi::Error::Function(format!("unknown function {}", field_name)))?;
+        let signature = Signature::new(function.params, function.result);
+
+        if !self.manifest.allows_import(field_name) {
+            info!("Denied the import of {}", field_name);
+            return Ok(FuncInstance::alloc_host(signature, PERMISSION_DENIED_INDEX));
+        }
+
+        Ok(FuncInstance::alloc_host(signature, function.index))
+    }
+
+    fn resolve_memory(
+        &self,
+        _field_name: &str,
+        descriptor: &MemoryDescriptor,
+    ) -> Result<MemoryRef, wasmi::Error> {
+        self.memory.resolve(descriptor)
+    }
+}
@@ -1,15 +1,194 @@
-use log::{Metadata, Level, Record};
+use std::collections::VecDeque;
+use std::sync::Mutex;
 
-pub(crate) struct SimpleLogger;
+use log::{LevelFilter, Metadata, Record, SetLoggerError};
 
-impl log::Log for SimpleLogger {
+/// The prefix of the targets the modules log to, followed by the name of the module.
+pub(crate) const GUEST_TARGET: &str = "guest";
+
+/// A target of the runtime, with the most verbose level that is compiled in for it.
+/// Records above it are removed at compile time, use [`log_to`] to log to a target.
+pub(crate) struct Target {
+    pub(crate) name: &'static str,
+    pub(crate) static_max_level: LevelFilter,
+}
+
+/// Initialization of the pins at debug, every read and write at trace.
+pub(crate) const GPIO: Target = Target {
+    name: "runtime::gpio",
+    static_max_level: LevelFilter::Debug,
+};
+
+/// Initialization of the UART at debug, every read and write at trace.
+pub(crate) const UART: Target = Target {
+    name: "runtime::uart",
+    static_max_level: LevelFilter::Debug,
+};
+
+/// Every delay of a module at trace.
+pub(crate) const DELAY: Target = Target {
+    name: "runtime::delay",
+    static_max_level: LevelFilter::Debug,
+};
+
+/// Every growth of the memory of a module at debug, growth beyond its limit at warn.
+pub(crate) const MEMORY: Target = Target {
+    name: "runtime::memory",
+    static_max_level: LevelFilter::Debug,
+};
+
+/// Logs to a target of the runtime, unless the level is compiled out for the target:
+///
+/// ```ignore
+/// log_to!(GPIO, Trace, "Setting gpio {} to {}", pin, value);
+/// ```
+macro_rules! log_to {
+    ($target:expr, $lvl:ident, $($arg:tt)+) => {
+        if log::Level::$lvl <= $target.static_max_level {
+            log::log!(target: $target.name, log::Level::$lvl, $($arg)+);
+        }
+    };
+}
+pub(crate) use log_to;
+
+/// The levels of the targets that can be changed at runtime.
+struct Filters {
+    /// The level of the targets without a filter.
+    default: LevelFilter,
+    /// The levels per target, a filter applies to the target and everything below it.
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filters {
+    /// Returns the level of the most specific filter that applies to the target.
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// The most verbose of all levels, records above it are dropped by the `log` facade.
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// The recent log lines, the oldest are dropped once the buffer is full.
+struct RingBuffer {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+/// Prints every record with a monotonic timestamp, its level and target. The levels
+/// can be changed per target while the firmware runs.
+pub(crate) struct Logger {
+    filters: Mutex<Filters>,
+    buffer: Mutex<Option<RingBuffer>>,
+}
+
+static LOGGER: Logger = Logger {
+    filters: Mutex::new(Filters {
+        default: LevelFilter::Info,
+        targets: Vec::new(),
+    }),
+    buffer: Mutex::new(None),
+};
+
+/// Installs the logger, with the level of all targets without a filter.
+pub(crate) fn init(default: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_logger(&LOGGER)?;
+    LOGGER.filters.lock().unwrap().default = default;
+    update_max_level();
+    Ok(())
+}
+
+/// Changes the level of the target and everything below it, e.g. `guest` for all
+/// modules, `guest::main` for a single module or `runtime::gpio`.
+pub(crate) fn set_level(target: &str, level: LevelFilter) {
+    let mut filters = LOGGER.filters.lock().unwrap();
+    match filters
+        .targets
+        .iter_mut()
+        .find(|(prefix, _)| prefix == target)
+    {
+        Some((_, filter)) => *filter = level,
+        None => filters.targets.push((target.into(), level)),
+    }
+    drop(filters);
+    update_max_level();
+}
+
+/// Returns the most verbose level that is logged for the target.
+pub(crate) fn max_level_for(target: &str) -> LevelFilter {
+    LOGGER
+        .filters
+        .lock()
+        .unwrap()
+        .level_for(target)
+        .min(log::max_level())
+}
+
+/// Keeps the last `capacity` log lines, so that they can be retrieved later.
+pub(crate) fn enable_buffer(capacity: usize) {
+    *LOGGER.buffer.lock().unwrap() = Some(RingBuffer {
+        lines: VecDeque::with_capacity(capacity),
+        capacity,
+    });
+}
+
+/// Returns the buffered log lines, the oldest first. The debugger sends them on `logs`.
+pub(crate) fn buffered() -> Vec<String> {
+    LOGGER
+        .buffer
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|buffer| buffer.lines.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn update_max_level() {
+    log::set_max_level(LOGGER.filters.lock().unwrap().max_level());
+}
+
+impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
+        metadata.level() <= self.filters.lock().unwrap().level_for(metadata.target())
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            println!("{} - {}", record.level(), record.args());
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // the timer of the ESP is monotonic and starts at boot
+        let micros = unsafe { esp_idf_sys::esp_timer_get_time() };
+        let line = format!(
+            "[{:>5}.{:06}] {:<5} {}: {}",
+            micros / 1_000_000,
+            micros % 1_000_000,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        println!("{}", line);
+
+        if let Some(buffer) = self.buffer.lock().unwrap().as_mut() {
+            if buffer.lines.len() >= buffer.capacity {
+                buffer.lines.pop_front();
+            }
+            if buffer.capacity > 0 {
+                buffer.lines.push_back(line);
+            }
         }
     }
 
@@ -1,55 +1,181 @@
 // Necessary, so that the `app_main` symbol exported by the `binstart` feature of esp-if-sys is linked
 use esp_idf_sys;
 
+use std::thread;
+use std::time::{Duration, Instant};
+
 use log::{info, LevelFilter};
-use logging::SimpleLogger;
-use wasmi::{ImportsBuilder, Module, ModuleInstance, StackRecycler};
 
 mod bytes;
+mod channel;
+mod console;
+mod debugger;
+mod dump;
+mod entry;
+mod image;
+mod imports;
 mod logging;
+mod manifest;
+mod memory;
+mod metering;
+mod peripherals;
+mod preemption;
+mod profiler;
+mod restart;
 mod runtime;
+mod scheduler;
+mod sections;
+mod signature;
+mod stack;
+mod stats;
+mod storage;
+mod supervisor;
+mod timer;
+mod trace;
+mod trap;
+
+use bytes::{MODULE_NAME, SAFE_MODE_IMAGE, SAFE_MODE_NAME, WASM_IMAGE};
+use metering::FuelConfig;
+use preemption::PreemptionConfig;
+use restart::RestartPolicy;
+use signature::SignaturePolicy;
+use stack::StackLimits;
+use supervisor::{ModuleSpec, Supervisor};
+
+/// The fuel budget of the module. A module that executes more than about a million
+/// instructions per second, or a million without sleeping, runs out of fuel, instead of
+/// blocking the device.
+const FUEL: FuelConfig = FuelConfig {
+    limit: 1_000_000,
+    refill_interval: Some(Duration::from_secs(1)),
+};
+
+/// How often the module gives up the core, so that other tasks can run and the watchdogs get fed.
+const PREEMPTION: PreemptionConfig = PreemptionConfig {
+    instructions: 500_000,
+    host_calls: 10_000,
+    busy_wait: Duration::from_millis(100),
+};
+
+/// The capabilities of the example module: the LED on pin 8, the input on pin 10
+/// and the UART over pins 2 and 3.
+const MANIFEST: &str = "gpio 2 3 8 10\nuart";
+
+/// The most memory a module may have, 128 KiB or two pages.
+const MAX_MEMORY: u32 = 128 * 1024;
+
+/// The stacks of a module: 32 KiB of value stack, allocated when it starts, and at most
+/// 1024 functions running nested. Deeply recursive modules need more.
+const STACK: StackLimits = StackLimits {
+    value_stack: 32 * 1024,
+    call_depth: 1024,
+};
+
+/// Failed modules are restarted after a second at first, and after at most a minute.
+const RESTART: RestartPolicy = RestartPolicy::Backoff {
+    initial: Duration::from_secs(1),
+    max: Duration::from_secs(60),
+};
+
+/// The modules that are run, each in its own task.
+static MODULES: [ModuleSpec; 1] = [ModuleSpec {
+    name: MODULE_NAME,
+    image: WASM_IMAGE,
+    manifest: Some(MANIFEST),
+    entry: entry::START,
+    max_memory: MAX_MEMORY,
+    stack: STACK,
+    fuel: FUEL,
+    preemption: PREEMPTION,
+    profile: None,
+    restart: RESTART,
+    debug: false,
+    dump_on_trap: true,
+    trace: false,
+}];
+
+/// The module that takes over once a module crashed too often. It is granted nothing
+/// and not restarted, it only reports that the device is in safe mode.
+static SAFE_MODE: ModuleSpec = ModuleSpec {
+    name: SAFE_MODE_NAME,
+    image: SAFE_MODE_IMAGE,
+    manifest: None,
+    entry: entry::START,
+    max_memory: MAX_MEMORY,
+    stack: STACK,
+    fuel: FUEL,
+    preemption: PREEMPTION,
+    profile: None,
+    restart: RestartPolicy::Never,
+    debug: false,
+    dump_on_trap: true,
+    trace: false,
+};
+
+/// The number of crashes in a row, also across reboots, after which a module is
+/// replaced by the safe mode module.
+const SAFE_MODE_AFTER: u32 = 5;
 
-use bytes::WASM_BYTES;
-use runtime::{Runtime, UartModuleImportResolver};
+/// Unsigned modules are only logged for now, set this to `Reject` once all modules are signed.
+const SIGNATURE_POLICY: SignaturePolicy = SignaturePolicy::Warn;
 
-static LOGGER: SimpleLogger = SimpleLogger;
+/// The level of everything that is logged, unless there is a filter for its target.
+const LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// The levels of single targets, e.g. `runtime::gpio` or `guest::main`. They can be changed
+/// while the firmware runs with `logging::set_level`. The hot paths of the runtime log at
+/// trace, which is compiled out in `src/logging.rs`.
+const LOG_FILTERS: [(&str, LevelFilter); 3] = [
+    ("guest", LevelFilter::Info),
+    ("runtime::gpio", LevelFilter::Info),
+    ("runtime::uart", LevelFilter::Info),
+];
+
+/// The number of recent log lines that are kept in memory, a debugger reads them with `logs`.
+const LOG_BUFFER: usize = 64;
+
+/// How often the status of the modules is logged.
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the stats and profiles of the modules are logged.
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
 
 fn main() {
     // Temporary. Will disappear once ESP-IDF 4.4 is released, but for now it is necessary to call this function once,
     // or else some patches to the runtime implemented by esp-idf-sys might not link properly.
     esp_idf_sys::link_patches();
-    log::set_logger(&LOGGER)
-        .map(|()| log::set_max_level(LevelFilter::Info))
-        .unwrap();
+    logging::init(LOG_LEVEL).unwrap();
+    for (target, level) in LOG_FILTERS {
+        logging::set_level(target, level);
+    }
+    logging::enable_buffer(LOG_BUFFER);
 
     info!("Hello, riscv!");
 
-    let module = Module::from_buffer(&WASM_BYTES).unwrap();
-    info!("Module loaded successfully!");
-
-    // instantiate a module and pass it the import resolver
-    let main = ModuleInstance::new(
-        &module,
-        &ImportsBuilder::new().with_resolver("env", &UartModuleImportResolver),
-    );
+    if let Err(err) = storage::init() {
+        info!("Could not initialize NVS, storage is unavailable: {}", err);
+    }
 
-    match main {
-        Err(err) => {
-            info!("Error: {}", err);
+    let mut supervisor =
+        Supervisor::new(SIGNATURE_POLICY).with_safe_mode(&SAFE_MODE, SAFE_MODE_AFTER);
+    for spec in MODULES.iter() {
+        if let Err(err) = supervisor.spawn(spec) {
+            info!("Could not start module {}: {}", spec.name, err);
         }
-        Ok(main) => {
-            let main = main.assert_no_start();
-
-            // fetch the memory of the module (needed for the write and read buffer)
-            let memory_export = main.export_by_name("memory").unwrap();
-            let memory = memory_export.as_memory().unwrap();
-
-            let mut runtime = Runtime::new(memory);
-            let mut stack_rec = StackRecycler::with_limits(84 * 1024, 84 * 1024);
+    }
 
-            info!("Calling the start method!");
-            let result = main.invoke_export_with_stack("start", &[], &mut runtime, &mut stack_rec);
-            info!("All went well? {:#?}", result.is_ok());
+    let mut last_stats = Instant::now();
+    while !supervisor.all_done() {
+        thread::sleep(STATUS_INTERVAL);
+        supervisor.reset_stable_crashes();
+        supervisor.log_status();
+        if last_stats.elapsed() >= STATS_INTERVAL {
+            supervisor.dump_stats();
+            supervisor.dump_profiles();
+            last_stats = Instant::now();
         }
     }
+    supervisor.dump_stats();
+    supervisor.dump_profiles();
+    info!("All modules finished");
 }
@@ -0,0 +1,228 @@
+use crate::sections;
+
+/// The name of the custom section that holds the manifest of a module.
+pub(crate) const MANIFEST_SECTION: &str = "capabilities";
+
+/// The error code returned to a module for a request outside of its manifest.
+pub(crate) const PERMISSION_DENIED: i32 = -2;
+
+/// The capabilities a module is granted. A manifest is a text with one capability per line,
+/// either embedded into the module as a custom section named `capabilities` or passed along
+/// with the module. Empty lines and lines starting with `#` are ignored:
+///
+/// ```text
+/// # the pins the module may initialize, including the pins of a UART
+/// gpio 2 3 8 10
+/// # the UART controller
+/// uart
+/// # the persistent key-value storage
+/// storage
+/// # the names of the channels the module may open
+/// channel sensor-data commands
+/// # the most bytes of memory the module may use, at most the limit of the firmware
+/// memory 65536
+/// # the most bytes of value stack and nested functions, at most the limits of the firmware
+/// stack 16384 256
+/// ```
+///
+/// A module without a manifest is granted nothing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    pins: Vec<u32>,
+    uart: bool,
+    storage: bool,
+    channels: Vec<String>,
+    max_memory: Option<u32>,
+    stack_limits: Option<(u32, u32)>,
+}
+
+impl Manifest {
+    /// Parses the text of a manifest.
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let mut manifest = Manifest::default();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let capability = words.next().unwrap_or_default();
+            let args: Vec<&str> = words.collect();
+            match (capability, args.is_empty()) {
+                ("gpio", false) => {
+                    for pin in args {
+                        let pin = pin
+                            .parse()
+                            .map_err(|_| format!("invalid pin in manifest: {}", pin))?;
+                        manifest.pins.push(pin);
+                    }
+                }
+                ("uart", true) => manifest.uart = true,
+                ("storage", true) => manifest.storage = true,
+                ("channel", false) => manifest.channels.extend(args.into_iter().map(String::from)),
+                ("memory", false) if args.len() == 1 => {
+                    let bytes = args[0]
+                        .parse()
+                        .map_err(|_| format!("invalid memory size in manifest: {}", args[0]))?;
+                    manifest.max_memory = Some(bytes);
+                }
+                ("stack", false) if args.len() == 2 => {
+                    let value_stack = args[0]
+                        .parse()
+                        .map_err(|_| format!("invalid stack size in manifest: {}", args[0]))?;
+                    let call_depth = args[1]
+                        .parse()
+                        .map_err(|_| format!("invalid call depth in manifest: {}", args[1]))?;
+                    manifest.stack_limits = Some((value_stack, call_depth));
+                }
+                _ => return Err(format!("invalid line in manifest: {}", line)),
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Reads the manifest embedded into a module. Returns `None` if there is none.
+    pub(crate) fn from_wasm(wasm: &[u8]) -> Result<Option<Self>, String> {
+        match sections::custom_section(wasm, MANIFEST_SECTION) {
+            Some(payload) => {
+                let text = core::str::from_utf8(payload)
+                    .map_err(|_| String::from("the manifest is not valid UTF-8"))?;
+                Self::parse(text).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if the module may import the host function with the given name. Host
+    /// functions of peripherals or services that are not granted at all are denied.
+    pub(crate) fn allows_import(&self, name: &str) -> bool {
+        if name.starts_with("gpio_") {
+            !self.pins.is_empty()
+        } else if name.starts_with("uart_") {
+            self.uart
+        } else if name.starts_with("kv_") {
+            self.storage
+        } else if name.starts_with("chan_") {
+            !self.channels.is_empty()
+        } else {
+            true
+        }
+    }
+
+    /// Returns true if the module may initialize the pin.
+    pub(crate) fn allows_pin(&self, pin: u32) -> bool {
+        self.pins.contains(&pin)
+    }
+
+    /// Returns true if the module may open a UART connection.
+    pub(crate) fn allows_uart(&self) -> bool {
+        self.uart
+    }
+
+    /// Returns true if the module may use its persistent storage.
+    pub(crate) fn allows_storage(&self) -> bool {
+        self.storage
+    }
+
+    /// Returns the most bytes of memory the module asks for, if it limits itself.
+    pub(crate) fn max_memory(&self) -> Option<u32> {
+        self.max_memory
+    }
+
+    /// Returns the bytes of value stack and the call depth the module asks for, if it
+    /// limits itself.
+    pub(crate) fn stack_limits(&self) -> Option<(u32, u32)> {
+        self.stack_limits
+    }
+
+    /// Returns true if the module may open the channel with the given name.
+    pub(crate) fn allows_channel(&self, name: &str) -> bool {
+        self.channels.iter().any(|channel| channel == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty module with the manifest as custom section.
+    fn module_with_manifest(manifest: &[u8]) -> Vec<u8> {
+        let mut payload = vec![MANIFEST_SECTION.len() as u8];
+        payload.extend_from_slice(MANIFEST_SECTION.as_bytes());
+        payload.extend_from_slice(manifest);
+
+        let mut wasm = b"\0asm\x01\0\0\0\0".to_vec();
+        wasm.push(payload.len() as u8);
+        wasm.append(&mut payload);
+        wasm
+    }
+
+    #[test]
+    fn every_capability_is_parsed() {
+        let manifest = Manifest::parse(
+            "
+            # a comment
+            gpio 2 3
+            gpio 8
+            uart
+            storage
+            channel sensor-data commands
+            memory 65536
+            stack 16384 256
+            ",
+        )
+        .unwrap();
+
+        assert!(manifest.allows_pin(2) && manifest.allows_pin(8) && !manifest.allows_pin(4));
+        assert!(manifest.allows_uart());
+        assert!(manifest.allows_storage());
+        assert!(manifest.allows_channel("commands") && !manifest.allows_channel("command"));
+        assert_eq!(manifest.max_memory(), Some(65536));
+        assert_eq!(manifest.stack_limits(), Some((16384, 256)));
+    }
+
+    #[test]
+    fn an_empty_manifest_grants_nothing() {
+        let manifest = Manifest::parse("").unwrap();
+        assert_eq!(manifest, Manifest::default());
+        for import in ["gpio_write", "uart_init", "kv_get", "chan_open"] {
+            assert!(!manifest.allows_import(import), "{}", import);
+        }
+        assert!(manifest.allows_import("delay_ms"));
+        assert_eq!(manifest.max_memory(), None);
+        assert_eq!(manifest.stack_limits(), None);
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        for (text, err) in [
+            ("gpio", "invalid line in manifest: gpio"),
+            ("gpio 2 x", "invalid pin in manifest: x"),
+            ("uart 1", "invalid line in manifest: uart 1"),
+            ("memory 1 2", "invalid line in manifest: memory 1 2"),
+            ("memory -1", "invalid memory size in manifest: -1"),
+            ("stack 1024", "invalid line in manifest: stack 1024"),
+            ("stack 1024 deep", "invalid call depth in manifest: deep"),
+            ("network", "invalid line in manifest: network"),
+        ] {
+            assert_eq!(Manifest::parse(text).unwrap_err(), err);
+        }
+    }
+
+    #[test]
+    fn the_manifest_is_read_from_the_module() {
+        let wasm = module_with_manifest(b"storage\n");
+        assert!(Manifest::from_wasm(&wasm)
+            .unwrap()
+            .unwrap()
+            .allows_storage());
+
+        assert_eq!(Manifest::from_wasm(b"\0asm\x01\0\0\0"), Ok(None));
+        assert_eq!(
+            Manifest::from_wasm(&module_with_manifest(b"\xff")).unwrap_err(),
+            "the manifest is not valid UTF-8"
+        );
+    }
+}
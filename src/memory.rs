@@ -0,0 +1,186 @@
+use core::cell::RefCell;
+
+use parity_wasm::elements::{self, External, Internal, ResizableLimits};
+use wasmi::memory_units::Pages;
+use wasmi::{MemoryDescriptor, MemoryInstance, MemoryRef, ModuleRef, LINEAR_MEMORY_PAGE_SIZE};
+
+use crate::manifest::Manifest;
+use crate::metering::MEMORY_EXPORT;
+
+/// The most pages a 32 bit memory can have.
+pub(crate) const MAX_PAGES: Pages = Pages(65536);
+
+/// Where the memory of a module comes from.
+pub(crate) enum MemoryKind {
+    /// The module defines its memory and exports it under this name.
+    Exported(String),
+    /// The module defines its memory, but doesn't export it. The instrumentation exports it
+    /// for the host.
+    Private,
+    /// The module imports its memory from the host.
+    Imported,
+    /// The module has no memory at all.
+    None,
+}
+
+/// The memory a module declares: where it comes from and how large it may be.
+pub(crate) struct DeclaredMemory {
+    pub(crate) kind: MemoryKind,
+    pub(crate) initial: Pages,
+    /// The maximum the module declares, e.g. with `--max-memory`. The host doesn't rely on
+    /// it, a module can't grow its memory beyond the limit of the host either way.
+    pub(crate) maximum: Option<Pages>,
+}
+
+impl DeclaredMemory {
+    /// Finds out where the memory of the module comes from, it doesn't have to be
+    /// exported as `memory`.
+    pub(crate) fn of(wasm: &[u8]) -> Result<Self, String> {
+        let module: elements::Module =
+            elements::deserialize_buffer(wasm).map_err(|err: elements::Error| err.to_string())?;
+
+        let imported = module
+            .import_section()
+            .map_or(&[][..], |section| section.entries())
+            .iter()
+            .find_map(|import| match import.external() {
+                External::Memory(memory) => Some(*memory.limits()),
+                _ => None,
+            });
+        let defined = module
+            .memory_section()
+            .and_then(|section| section.entries().first())
+            .map(|memory| *memory.limits());
+        let export = module
+            .export_section()
+            .map_or(&[][..], |section| section.entries())
+            .iter()
+            .find(|export| matches!(export.internal(), Internal::Memory(0)));
+
+        let (kind, limits) = match (imported, defined, export) {
+            (Some(limits), _, _) => (MemoryKind::Imported, limits),
+            (None, Some(limits), Some(export)) => {
+                (MemoryKind::Exported(export.field().into()), limits)
+            }
+            (None, Some(limits), None) => (MemoryKind::Private, limits),
+            (None, None, _) => (MemoryKind::None, ResizableLimits::new(0, Some(0))),
+        };
+
+        Ok(Self {
+            kind,
+            initial: Pages(limits.initial() as usize),
+            maximum: limits.maximum().map(|maximum| Pages(maximum as usize)),
+        })
+    }
+
+    /// Checks the memory against the limit of the host before the module is instantiated,
+    /// a module that needs more memory from the start is rejected.
+    pub(crate) fn check(&self, limit: Pages) -> Result<(), String> {
+        if self.initial > limit {
+            return Err(format!(
+                "the module needs {} pages of memory, but it may have at most {}",
+                self.initial.0, limit.0
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the module declares that its memory may grow beyond the limit,
+    /// which the host doesn't allow.
+    pub(crate) fn exceeds(&self, limit: Pages) -> bool {
+        self.maximum.unwrap_or(MAX_PAGES) > limit
+    }
+}
+
+/// Returns the most pages of memory a module may have: the limit of the firmware in bytes,
+/// if any, lowered by the manifest of the module. Partial pages are rounded down.
+pub(crate) fn limit(max_memory: Option<u32>, manifest: &Manifest) -> Pages {
+    match max_memory.into_iter().chain(manifest.max_memory()).min() {
+        Some(bytes) => Pages(bytes as usize / LINEAR_MEMORY_PAGE_SIZE.0),
+        None => MAX_PAGES,
+    }
+}
+
+/// Grows the memory by `pages` on behalf of `memory.grow`, unless it would exceed the limit
+/// of the host or the maximum of the memory. Returns the previous size, like `memory.grow`.
+pub(crate) fn grow(memory: &MemoryRef, pages: u32, limit: Pages) -> Result<Pages, String> {
+    let size = memory.current_size();
+    if size.0.saturating_add(pages as usize) > limit.0 {
+        return Err(format!(
+            "{} more pages exceed the limit of {} pages",
+            pages, limit.0
+        ));
+    }
+
+    memory
+        .grow(Pages(pages as usize))
+        .map_err(|err| err.to_string())
+}
+
+/// Allocates the memory of a module that imports it. The host sets its maximum, a
+/// module can't grow it beyond the limit even if it declares a higher maximum or none.
+pub(crate) struct HostMemory {
+    limit: Pages,
+    memory: RefCell<Option<MemoryRef>>,
+}
+
+impl HostMemory {
+    /// Allows the memory to grow to at most `limit`.
+    pub(crate) fn new(limit: Pages) -> Self {
+        Self {
+            limit,
+            memory: RefCell::new(None),
+        }
+    }
+
+    /// Allocates the memory for the import described by `descriptor`.
+    pub(crate) fn resolve(&self, descriptor: &MemoryDescriptor) -> Result<MemoryRef, wasmi::Error> {
+        let initial = Pages(descriptor.initial() as usize);
+        if initial > self.limit {
+            return Err(wasmi::Error::Instantiation(format!(
+                "the module imports a memory of {} pages, but the host allows at most {}",
+                initial.0, self.limit.0
+            )));
+        }
+
+        let maximum = descriptor.maximum().map_or(self.limit, |maximum| {
+            self.limit.min(Pages(maximum as usize))
+        });
+        let memory = MemoryInstance::alloc(initial, Some(maximum))?;
+        *self.memory.borrow_mut() = Some(memory.clone());
+
+        Ok(memory)
+    }
+
+    /// Returns the memory, once a module imported it.
+    pub(crate) fn get(&self) -> Option<MemoryRef> {
+        self.memory.borrow().clone()
+    }
+}
+
+impl Default for HostMemory {
+    /// Allows the memory to grow as far as a module declares.
+    fn default() -> Self {
+        Self::new(MAX_PAGES)
+    }
+}
+
+/// Returns the memory of an instance that the host reads and writes on behalf of the
+/// module, if the host can access it.
+pub(crate) fn find(
+    instance: &ModuleRef,
+    declared: &DeclaredMemory,
+    host_memory: &HostMemory,
+) -> Option<MemoryRef> {
+    match &declared.kind {
+        MemoryKind::Exported(name) => instance
+            .export_by_name(name)
+            .and_then(|export| export.as_memory().cloned()),
+        MemoryKind::Private => instance
+            .export_by_name(MEMORY_EXPORT)
+            .and_then(|export| export.as_memory().cloned()),
+        MemoryKind::Imported => host_memory.get(),
+        MemoryKind::None => None,
+    }
+}
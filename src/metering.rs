@@ -0,0 +1,626 @@
+use core::fmt;
+use std::time::{Duration, Instant};
+
+use parity_wasm::elements::{
+    self, BlockType, ExportEntry, ExportSection, External, FunctionType, ImportCountType,
+    ImportEntry, ImportSection, IndexMap, Instruction, Internal, Section, Type, ValueType,
+};
+use wasmi::HostError;
+
+use crate::trap::FunctionNames;
+
+/// The module the injected imports are imported from. A module may not import from it
+/// itself, so that only the instrumentation calls these functions.
+pub(crate) const INSTRUMENTATION: &str = "__instrumentation";
+
+/// The name of the import that gets injected into every module to charge fuel.
+pub(crate) const CONSUME_FUEL: &str = "__consume_fuel";
+
+/// The names of the imports that get injected into every module to track its call stack.
+pub(crate) const ENTER_FUNCTION: &str = "__enter_function";
+pub(crate) const LEAVE_FUNCTION: &str = "__leave_function";
+
+/// The name of the import that replaces `memory.grow`, so that the host enforces its limit.
+pub(crate) const MEMORY_GROW: &str = "__memory_grow";
+
+/// The name a memory is exported under if the module doesn't export it itself, the host
+/// needs to access it to grow it.
+pub(crate) const MEMORY_EXPORT: &str = "__memory";
+
+/// Configures how much fuel a module may spend. One unit of fuel roughly
+/// corresponds to one executed instruction.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FuelConfig {
+    /// The fuel available to a single invocation.
+    pub(crate) limit: u64,
+    /// If set, the fuel is refilled to `limit` once it ran out, given that the module
+    /// slept since the last refill and at least this much time passed. Long-running
+    /// event-loop guests, that sleep most of the time, can run forever this way, while
+    /// a guest that spends `limit` within the interval or spins without sleeping still
+    /// traps.
+    pub(crate) refill_interval: Option<Duration>,
+}
+
+/// The trap that is raised once a module ran out of fuel.
+#[derive(Debug)]
+pub(crate) struct OutOfFuel;
+
+impl fmt::Display for OutOfFuel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the module ran out of fuel")
+    }
+}
+
+impl HostError for OutOfFuel {}
+
+/// The fuel budget of a module.
+pub(crate) struct Fuel {
+    config: FuelConfig,
+    remaining: u64,
+    last_refill: Instant,
+    // a spinning module must not be refilled just because time passed
+    slept: bool,
+}
+
+impl Fuel {
+    /// Creates a full budget.
+    pub(crate) fn new(config: FuelConfig) -> Self {
+        Self {
+            config,
+            remaining: config.limit,
+            last_refill: Instant::now(),
+            slept: false,
+        }
+    }
+
+    /// Refills the budget, used before each invocation.
+    pub(crate) fn reset(&mut self) {
+        self.remaining = self.config.limit;
+        self.last_refill = Instant::now();
+        self.slept = false;
+    }
+
+    /// Notes that the module slept, which allows the next refill.
+    pub(crate) fn on_sleep(&mut self) {
+        self.slept = true;
+    }
+
+    /// Charges `amount` units of fuel.
+    pub(crate) fn consume(&mut self, amount: u64) -> Result<(), OutOfFuel> {
+        if self.remaining < amount {
+            match self.config.refill_interval {
+                Some(interval) if self.slept && self.last_refill.elapsed() >= interval => {
+                    self.reset()
+                }
+                _ => return Err(OutOfFuel),
+            }
+        }
+
+        // the limit might be lower than a single charge
+        self.remaining = self.remaining.checked_sub(amount).ok_or(OutOfFuel)?;
+        Ok(())
+    }
+}
+
+/// What a module is instrumented for, beyond the host enforcing the limit of its memory.
+/// The host functions of what is left out are never called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Instrumentation {
+    /// Charges fuel for the instructions of the module. The charges also count the
+    /// instructions for the preemption and the profiler.
+    pub(crate) fuel: bool,
+    /// Tracks the call stack of the module, which the stack limits, the profiler and the
+    /// debugger need. Without it, the backtrace of a trap is empty.
+    pub(crate) calls: bool,
+}
+
+/// Parses a module and injects what it is instrumented for into it. Returns the module
+/// together with the names of its functions, as found in the name section.
+pub(crate) fn load_instrumented(
+    bytes: &[u8],
+    instrumentation: Instrumentation,
+) -> Result<(wasmi::Module, FunctionNames), wasmi::Error> {
+    let module: elements::Module = elements::deserialize_buffer(bytes)
+        .map_err(|err: elements::Error| wasmi::Error::Validation(err.to_string()))?;
+    let names = FunctionNames::from_wasm(bytes);
+
+    let module = instrument(module, instrumentation)
+        .map_err(|err| wasmi::Error::Validation(err.to_string()))?;
+
+    Ok((wasmi::Module::from_parity_wasm_module(module)?, names))
+}
+
+/// Adds the import `__memory_grow(i32) -> i32` to the module, `__consume_fuel(i32)` for fuel
+/// and `__enter_function(i32)` and `__leave_function()` to track calls, all of them from
+/// [`INSTRUMENTATION`]. A module that imports from there itself is rejected.
+///
+/// Every `memory.grow` is replaced by a call to `__memory_grow`, which takes and returns the
+/// same values. The host grows the memory only as far as it allows.
+///
+/// The fuel import is called at the start of every straight-line block of code, with the number
+/// of instructions in the block. Since every loop iteration starts a new block, a module can't run
+/// without charging fuel.
+///
+/// Every function calls `__enter_function` with its index (in the uninstrumented module) once it
+/// is entered, and `__leave_function` before it returns. The host keeps a shadow call stack this
+/// way, which still holds all functions that were running once the module traps.
+pub(crate) fn instrument(
+    module: elements::Module,
+    instrumentation: Instrumentation,
+) -> Result<elements::Module, elements::Error> {
+    // the function names are kept up to date, so parse them first
+    let mut module = module.parse_names().unwrap_or_else(|(_, module)| module);
+    let imports = module
+        .import_section()
+        .map_or(&[][..], |section| section.entries());
+    if imports
+        .iter()
+        .any(|import| import.module() == INSTRUMENTATION)
+    {
+        return Err(elements::Error::Other(
+            "the module imports a function of the instrumentation",
+        ));
+    }
+
+    // the imports are appended to the other function imports, so all functions defined
+    // in the module move up by the number of injected imports
+    let imported_funcs = module.import_count(ImportCountType::Function) as u32;
+    let mut injected = Vec::new();
+    let mut inject = |module: &mut elements::Module, name: &str, func_type| {
+        let ty = type_index(module, func_type);
+        injected.push(ImportEntry::new(
+            INSTRUMENTATION.into(),
+            name.into(),
+            External::Function(ty),
+        ));
+        imported_funcs + injected.len() as u32 - 1
+    };
+    let grow_func = inject(
+        &mut module,
+        MEMORY_GROW,
+        FunctionType::new(vec![ValueType::I32], vec![ValueType::I32]),
+    );
+    let fuel_func = instrumentation.fuel.then(|| {
+        let func_type = FunctionType::new(vec![ValueType::I32], vec![]);
+        inject(&mut module, CONSUME_FUEL, func_type)
+    });
+    let call_funcs = instrumentation.calls.then(|| {
+        let enter_type = FunctionType::new(vec![ValueType::I32], vec![]);
+        let enter_func = inject(&mut module, ENTER_FUNCTION, enter_type);
+        let leave_type = FunctionType::new(vec![], vec![]);
+        (enter_func, inject(&mut module, LEAVE_FUNCTION, leave_type))
+    });
+
+    let amount = injected.len() as u32;
+    match module.import_section_mut() {
+        Some(imports) => imports.entries_mut().extend(injected),
+        None => module.insert_section(Section::Import(ImportSection::with_entries(injected)))?,
+    }
+    shift_function_indices(&mut module, imported_funcs, amount);
+    export_memory(&mut module)?;
+
+    let block_types = result_block_types(&module)?;
+    if let Some(code) = module.code_section_mut() {
+        for (index, body) in code.bodies_mut().iter_mut().enumerate() {
+            let func_index = imported_funcs + index as u32;
+            let block_type = *block_types
+                .get(index)
+                .ok_or(elements::Error::Other("a function body has no type"))?;
+
+            let instructions = body.code_mut().elements_mut();
+            for instruction in instructions.iter_mut() {
+                if let Instruction::GrowMemory(_) = instruction {
+                    *instruction = Instruction::Call(grow_func);
+                }
+            }
+            let mut code = core::mem::take(instructions);
+            if let Some((enter_func, leave_func)) = call_funcs {
+                code = track_calls(code, func_index, block_type, enter_func, leave_func);
+            }
+            if let Some(fuel_func) = fuel_func {
+                code = charge_fuel(code, fuel_func);
+            }
+            *instructions = code;
+        }
+    }
+
+    Ok(module)
+}
+
+/// Exports the memory the module defines as [`MEMORY_EXPORT`], unless it is exported already.
+fn export_memory(module: &mut elements::Module) -> Result<(), elements::Error> {
+    let defines_memory = module
+        .memory_section()
+        .map_or(0, |section| section.entries().len())
+        > 0;
+    let exports_memory = module
+        .export_section()
+        .map_or(&[][..], |section| section.entries())
+        .iter()
+        .any(|export| matches!(export.internal(), Internal::Memory(_)));
+    if !defines_memory || exports_memory {
+        return Ok(());
+    }
+
+    let export = ExportEntry::new(MEMORY_EXPORT.into(), Internal::Memory(0));
+    match module.export_section_mut() {
+        Some(exports) => exports.entries_mut().push(export),
+        None => {
+            module.insert_section(Section::Export(ExportSection::with_entries(vec![export])))?
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the index of the given function type, adding it if necessary.
+fn type_index(module: &mut elements::Module, func_type: FunctionType) -> u32 {
+    let func_type = Type::Function(func_type);
+
+    if module.type_section_mut().is_none() {
+        // a module without types has no other sections that would need to come first
+        let _ = module.insert_section(Section::Type(Default::default()));
+    }
+    let types = module.type_section_mut().unwrap().types_mut();
+
+    match types.iter().position(|ty| *ty == func_type) {
+        Some(index) => index as u32,
+        None => {
+            types.push(func_type);
+            (types.len() - 1) as u32
+        }
+    }
+}
+
+/// Returns the results of every function defined in the module, as block type.
+fn result_block_types(module: &elements::Module) -> Result<Vec<BlockType>, elements::Error> {
+    let types = module
+        .type_section()
+        .map(|types| types.types())
+        .unwrap_or_default();
+    let funcs = module
+        .function_section()
+        .map(|funcs| funcs.entries())
+        .unwrap_or_default();
+
+    funcs
+        .iter()
+        .map(|func| match types.get(func.type_ref() as usize) {
+            Some(Type::Function(func_type)) => match func_type.results() {
+                [] => Ok(BlockType::NoResult),
+                [result] => Ok(BlockType::Value(*result)),
+                _ => Err(elements::Error::Other(
+                    "functions with multiple results are not supported",
+                )),
+            },
+            None => Err(elements::Error::Other(
+                "a function refers to a missing type",
+            )),
+        })
+        .collect()
+}
+
+/// Increments all references to functions with an index of at least `from` by `amount`.
+fn shift_function_indices(module: &mut elements::Module, from: u32, amount: u32) {
+    let shift = |index: &mut u32| {
+        if *index >= from {
+            *index += amount;
+        }
+    };
+
+    if let Some(code) = module.code_section_mut() {
+        for body in code.bodies_mut() {
+            for instruction in body.code_mut().elements_mut() {
+                if let Instruction::Call(index) = instruction {
+                    shift(index);
+                }
+            }
+        }
+    }
+
+    if let Some(exports) = module.export_section_mut() {
+        for export in exports.entries_mut() {
+            if let Internal::Function(index) = export.internal_mut() {
+                shift(index);
+            }
+        }
+    }
+
+    if let Some(elements) = module.elements_section_mut() {
+        for segment in elements.entries_mut() {
+            segment.members_mut().iter_mut().for_each(shift);
+        }
+    }
+
+    if let Some(mut start) = module.start_section() {
+        shift(&mut start);
+        module.set_start_section(start);
+    }
+
+    if let Some(names) = module
+        .names_section_mut()
+        .and_then(|names| names.functions_mut().as_mut())
+    {
+        let old = core::mem::replace(names.names_mut(), IndexMap::with_capacity(0));
+        for (mut index, name) in old {
+            shift(&mut index);
+            names.names_mut().insert(index, name);
+        }
+    }
+}
+
+/// Makes the code of a function call `enter_func` once it is entered and `leave_func` before
+/// it returns. The body is wrapped into a block, so that branches to the outermost label of the
+/// function, which return from it, leave it through the call as well.
+fn track_calls(
+    code: Vec<Instruction>,
+    func_index: u32,
+    block_type: BlockType,
+    enter_func: u32,
+    leave_func: u32,
+) -> Vec<Instruction> {
+    let mut tracked = Vec::with_capacity(code.len() + 8);
+    tracked.push(Instruction::I32Const(func_index as i32));
+    tracked.push(Instruction::Call(enter_func));
+    tracked.push(Instruction::Block(block_type));
+
+    for instruction in code {
+        if instruction == Instruction::Return {
+            tracked.push(Instruction::Call(leave_func));
+        }
+        tracked.push(instruction);
+    }
+
+    // the `End` of the function closes the wrapping block now
+    tracked.push(Instruction::Call(leave_func));
+    tracked.push(Instruction::End);
+    tracked
+}
+
+/// Splits the code of a function into straight-line blocks and prepends a fuel charge to each.
+fn charge_fuel(code: Vec<Instruction>, fuel_func: u32) -> Vec<Instruction> {
+    let mut instrumented = Vec::with_capacity(code.len() * 2);
+    let mut block = Vec::new();
+
+    for instruction in code {
+        // control flow can only leave or enter a block after these
+        let ends_block = matches!(
+            instruction,
+            Instruction::Block(_)
+                | Instruction::Loop(_)
+                | Instruction::If(_)
+                | Instruction::Else
+                | Instruction::End
+                | Instruction::Br(_)
+                | Instruction::BrIf(_)
+                | Instruction::BrTable(_)
+                | Instruction::Return
+                | Instruction::Unreachable
+        );
+        block.push(instruction);
+
+        if ends_block {
+            instrumented.push(Instruction::I32Const(block.len() as i32));
+            instrumented.push(Instruction::Call(fuel_func));
+            instrumented.append(&mut block);
+        }
+    }
+
+    // a function body always ends with `End`, so nothing should be left
+    instrumented.append(&mut block);
+    instrumented
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parity_wasm::elements::{
+        CodeSection, ElementSection, ElementSegment, Func, FuncBody, FunctionNameSubsection,
+        FunctionSection, InitExpr, Instructions, NameSection, TableSection, TableType, TypeSection,
+    };
+    use Instruction::*;
+
+    /// Returns a module that imports `env.host` and defines the functions `one` and `two`,
+    /// which refer to each other and to the import from everywhere a function can be
+    /// referred to.
+    fn module() -> elements::Module {
+        let body = |code: Vec<Instruction>| FuncBody::new(Vec::new(), Instructions::new(code));
+        let mut names = FunctionNameSubsection::default();
+        names.names_mut().insert(1, "one".into());
+        names.names_mut().insert(2, "two".into());
+
+        elements::Module::new(vec![
+            Section::Type(TypeSection::with_types(vec![Type::Function(
+                FunctionType::new(vec![], vec![]),
+            )])),
+            Section::Import(ImportSection::with_entries(vec![ImportEntry::new(
+                "env".into(),
+                "host".into(),
+                External::Function(0),
+            )])),
+            Section::Function(FunctionSection::with_entries(vec![
+                Func::new(0),
+                Func::new(0),
+            ])),
+            Section::Table(TableSection::with_entries(vec![TableType::new(2, None)])),
+            Section::Export(ExportSection::with_entries(vec![ExportEntry::new(
+                "run".into(),
+                Internal::Function(2),
+            )])),
+            Section::Start(1),
+            Section::Element(ElementSection::with_entries(vec![ElementSegment::new(
+                0,
+                Some(InitExpr::new(vec![I32Const(0), End])),
+                vec![1, 2],
+            )])),
+            Section::Code(CodeSection::with_bodies(vec![
+                body(vec![Call(0), Call(2), End]),
+                body(vec![Call(1), End]),
+            ])),
+            Section::Name(NameSection::new(None, Some(names), None)),
+        ])
+    }
+
+    fn charge(amount: i32) -> [Instruction; 2] {
+        [I32Const(amount), Call(9)]
+    }
+
+    #[test]
+    fn functions_move_up_by_the_injected_imports() {
+        let everything = Instrumentation {
+            fuel: true,
+            calls: true,
+        };
+        let module = instrument(module(), everything).unwrap();
+
+        // the import stays where it is, `one` and `two` move from 1 and 2 to 5 and 6
+        let code = module.code_section().unwrap().bodies();
+        let calls = |index: usize| -> Vec<u32> {
+            code[index]
+                .code()
+                .elements()
+                .iter()
+                .filter_map(|instruction| match instruction {
+                    // the calls of the injected imports at 1 to 4 are left out
+                    Call(index) if !(1..=4).contains(index) => Some(*index),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(calls(0), [0, 6]);
+        assert_eq!(calls(1), [5]);
+
+        let exports = module.export_section().unwrap().entries();
+        assert_eq!(exports[0].internal(), &Internal::Function(6));
+        assert_eq!(module.start_section(), Some(5));
+        let elements = module.elements_section().unwrap().entries();
+        assert_eq!(elements[0].members(), [5, 6]);
+        let names = module.names_section().unwrap().functions().unwrap().names();
+        assert_eq!(names.get(5).map(String::as_str), Some("one"));
+        assert_eq!(names.get(6).map(String::as_str), Some("two"));
+        assert_eq!(names.get(1), None);
+    }
+
+    #[test]
+    fn only_what_is_needed_is_injected() {
+        let grow_only = Instrumentation {
+            fuel: false,
+            calls: false,
+        };
+        let grown = instrument(module(), grow_only).unwrap();
+        let imports = grown.import_section().unwrap().entries();
+        let fields: Vec<&str> = imports.iter().map(ImportEntry::field).collect();
+        assert_eq!(fields, ["host", MEMORY_GROW]);
+        let code = grown.code_section().unwrap().bodies();
+        assert_eq!(code[0].code().elements(), [Call(0), Call(3), End]);
+
+        let fuel_only = Instrumentation {
+            fuel: true,
+            calls: false,
+        };
+        let fueled = instrument(module(), fuel_only).unwrap();
+        let imports = fueled.import_section().unwrap().entries();
+        let fields: Vec<&str> = imports.iter().map(ImportEntry::field).collect();
+        assert_eq!(fields, ["host", MEMORY_GROW, CONSUME_FUEL]);
+        let code = fueled.code_section().unwrap().bodies();
+        assert_eq!(
+            code[1].code().elements(),
+            [I32Const(2), Call(2), Call(3), End]
+        );
+    }
+
+    #[test]
+    fn a_module_can_not_import_the_instrumentation() {
+        let mut module = module();
+        let import = ImportEntry::new(
+            INSTRUMENTATION.into(),
+            CONSUME_FUEL.into(),
+            External::Function(0),
+        );
+        module
+            .import_section_mut()
+            .unwrap()
+            .entries_mut()
+            .push(import);
+
+        let nothing = Instrumentation {
+            fuel: false,
+            calls: false,
+        };
+        assert!(instrument(module, nothing).is_err());
+    }
+
+    #[test]
+    fn a_function_is_left_before_every_return_and_at_its_end() {
+        let code = vec![
+            Block(BlockType::NoResult),
+            I32Const(0),
+            BrIf(1),
+            Return,
+            End,
+            End,
+        ];
+
+        // `br_if 1` returned from the function, now it leaves the wrapping block, after
+        // which the function is left as well
+        assert_eq!(
+            track_calls(code, 3, BlockType::NoResult, 7, 8),
+            [
+                I32Const(3),
+                Call(7),
+                Block(BlockType::NoResult),
+                Block(BlockType::NoResult),
+                I32Const(0),
+                BrIf(1),
+                Call(8),
+                Return,
+                End,
+                End,
+                Call(8),
+                End,
+            ]
+        );
+    }
+
+    #[test]
+    fn fuel_is_charged_after_every_control_instruction() {
+        let code = vec![
+            I32Const(1),
+            If(BlockType::NoResult),
+            Nop,
+            Else,
+            Nop,
+            Unreachable,
+            End,
+            Loop(BlockType::NoResult),
+            I32Const(0),
+            BrIf(0),
+            End,
+            Return,
+            End,
+        ];
+
+        let expected = [
+            &charge(2)[..],
+            &[I32Const(1), If(BlockType::NoResult)],
+            &charge(2),
+            &[Nop, Else],
+            &charge(2),
+            &[Nop, Unreachable],
+            &charge(1),
+            &[End],
+            &charge(1),
+            &[Loop(BlockType::NoResult)],
+            &charge(2),
+            &[I32Const(0), BrIf(0)],
+            &charge(1),
+            &[End],
+            &charge(1),
+            &[Return],
+            &charge(1),
+            &[End],
+        ]
+        .concat();
+        assert_eq!(charge_fuel(code, 9), expected);
+    }
+}
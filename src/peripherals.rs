@@ -0,0 +1,119 @@
+use std::sync::Mutex;
+
+/// A peripheral that can only be used by one runtime at a time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Peripheral {
+    /// A Gpio pin, identified by its number.
+    Gpio(u32),
+    /// The UART1 controller.
+    Uart1,
+}
+
+/// The peripherals claimed by all runtimes on the device.
+static CLAIMED: Mutex<Vec<Peripheral>> = Mutex::new(Vec::new());
+
+/// The peripherals claimed by a single runtime. Arbitrates the shared peripherals
+/// between runtimes, all claims are released once the runtime is dropped.
+#[derive(Default)]
+pub(crate) struct Claims {
+    owned: Vec<Peripheral>,
+    // the pins claimed for the UART, the Gpio functions of the module may not touch them
+    uart_pins: Vec<u32>,
+}
+
+impl Claims {
+    /// Claims a peripheral. Returns false if it is owned by another runtime.
+    pub(crate) fn claim(&mut self, peripheral: Peripheral) -> bool {
+        if self.owns(peripheral) {
+            return true;
+        }
+
+        let mut claimed = CLAIMED.lock().unwrap();
+        if claimed.contains(&peripheral) {
+            return false;
+        }
+        claimed.push(peripheral);
+        self.owned.push(peripheral);
+
+        true
+    }
+
+    /// Claims all given peripherals, or none of them if one is owned, also by this runtime.
+    fn claim_all(&mut self, peripherals: &[Peripheral]) -> bool {
+        if peripherals.iter().any(|&peripheral| self.owns(peripheral)) {
+            return false;
+        }
+        for (claimed, &peripheral) in peripherals.iter().enumerate() {
+            if !self.claim(peripheral) {
+                peripherals[..claimed]
+                    .iter()
+                    .for_each(|&peripheral| self.release(peripheral));
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Claims a pin for Gpio. Returns false if it is owned by another runtime or used by the
+    /// UART of this one, claiming a pin this runtime uses as Gpio again succeeds.
+    pub(crate) fn claim_gpio(&mut self, pin: u32) -> bool {
+        !self.uart_pins.contains(&pin) && self.claim(Peripheral::Gpio(pin))
+    }
+
+    /// Releases a pin claimed for Gpio. A pin of the UART stays claimed.
+    pub(crate) fn release_gpio(&mut self, pin: u32) {
+        if !self.uart_pins.contains(&pin) {
+            self.release(Peripheral::Gpio(pin));
+        }
+    }
+
+    /// Claims the UART1 controller together with its pins, or nothing if one of them is
+    /// owned, also by this runtime: a pin it uses as Gpio can't be handed to the UART.
+    pub(crate) fn claim_uart(&mut self, pins: &[u32]) -> bool {
+        let peripherals: Vec<Peripheral> = pins
+            .iter()
+            .map(|&pin| Peripheral::Gpio(pin))
+            .chain([Peripheral::Uart1])
+            .collect();
+        if !self.claim_all(&peripherals) {
+            return false;
+        }
+        self.uart_pins.extend_from_slice(pins);
+
+        true
+    }
+
+    /// Releases the UART1 controller and its pins, if the connection couldn't be set up.
+    pub(crate) fn release_uart(&mut self) {
+        for pin in std::mem::take(&mut self.uart_pins) {
+            self.release(Peripheral::Gpio(pin));
+        }
+        self.release(Peripheral::Uart1);
+    }
+
+    /// Releases a peripheral, so that other runtimes can claim it.
+    pub(crate) fn release(&mut self, peripheral: Peripheral) {
+        if let Some(pos) = self.owned.iter().position(|&owned| owned == peripheral) {
+            self.owned.swap_remove(pos);
+            CLAIMED
+                .lock()
+                .unwrap()
+                .retain(|&claimed| claimed != peripheral);
+        }
+    }
+
+    /// Returns true if this runtime owns the peripheral.
+    pub(crate) fn owns(&self, peripheral: Peripheral) -> bool {
+        self.owned.contains(&peripheral)
+    }
+}
+
+impl Drop for Claims {
+    fn drop(&mut self) {
+        CLAIMED
+            .lock()
+            .unwrap()
+            .retain(|claimed| !self.owned.contains(claimed));
+    }
+}
@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+/// Configures how often a running module gives up the core.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PreemptionConfig {
+    /// Yield after this many executed instructions (requires fuel metering).
+    pub(crate) instructions: u64,
+    /// Yield after this many calls into the host.
+    pub(crate) host_calls: u32,
+    /// Yield after busy waiting this long in delays shorter than a tick, which keep the
+    /// core.
+    pub(crate) busy_wait: Duration,
+}
+
+/// What the runtime does to give up the core.
+pub(crate) trait YieldHook {
+    /// Called whenever the module ran for a while.
+    fn yield_now(&mut self);
+
+    /// Called after the module slept, at least once per second of sleep. The
+    /// core was given up during the sleep already.
+    fn slept(&mut self) {}
+}
+
+/// Counts the work a module did since it last yielded and yields once a limit is reached.
+pub(crate) struct Preemption {
+    config: PreemptionConfig,
+    hook: Box<dyn YieldHook>,
+    instructions: u64,
+    host_calls: u32,
+    busy_wait: Duration,
+}
+
+impl Preemption {
+    pub(crate) fn new(config: PreemptionConfig, hook: Box<dyn YieldHook>) -> Self {
+        Self {
+            config,
+            hook,
+            instructions: 0,
+            host_calls: 0,
+            busy_wait: Duration::ZERO,
+        }
+    }
+
+    /// Accounts for instructions the module is about to execute.
+    pub(crate) fn on_instructions(&mut self, count: u64) {
+        self.instructions += count;
+        if self.instructions >= self.config.instructions {
+            self.yield_now();
+        }
+    }
+
+    /// Accounts for a call into the host.
+    pub(crate) fn on_host_call(&mut self) {
+        self.host_calls += 1;
+        if self.host_calls >= self.config.host_calls {
+            self.yield_now();
+        }
+    }
+
+    /// Accounts for a delay of the module that was busy waited, without giving up the core.
+    pub(crate) fn on_busy_wait(&mut self, duration: Duration) {
+        self.busy_wait += duration;
+        if self.busy_wait >= self.config.busy_wait {
+            self.yield_now();
+        }
+    }
+
+    /// Accounts for a sleep of the module, which gave up the core already.
+    pub(crate) fn on_sleep(&mut self) {
+        self.restart();
+        self.hook.slept();
+    }
+
+    fn yield_now(&mut self) {
+        self.restart();
+        self.hook.yield_now();
+    }
+
+    /// Starts counting the work since the module gave up the core anew.
+    fn restart(&mut self) {
+        self.instructions = 0;
+        self.host_calls = 0;
+        self.busy_wait = Duration::ZERO;
+    }
+}
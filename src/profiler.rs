@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::imports;
+use crate::trap::FunctionNames;
+
+/// Configures how often the call stack of a module is sampled. A limit of 0 disables
+/// sampling by that measure.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ProfileConfig {
+    /// Take a sample after this many executed instructions (requires fuel metering).
+    pub(crate) instructions: u64,
+    /// Take a sample on every this many calls into the host, with the host function
+    /// as the innermost frame.
+    pub(crate) host_calls: u32,
+}
+
+/// The sampled call stacks of a module, with how often each was seen.
+#[derive(Default)]
+pub(crate) struct Profile {
+    names: FunctionNames,
+    // the function indices of a stack, the outermost first, and the host function it called
+    samples: HashMap<(Vec<u32>, Option<usize>), u64>,
+}
+
+impl Profile {
+    /// Names the functions of the sampled module, the names are looked up once the
+    /// profile is written.
+    pub(crate) fn set_names(&mut self, names: FunctionNames) {
+        self.names = names;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    fn sample(&mut self, call_stack: &[u32], host_function: Option<usize>, count: u64) {
+        if call_stack.is_empty() {
+            return;
+        }
+
+        *self
+            .samples
+            .entry((call_stack.to_vec(), host_function))
+            .or_default() += count;
+    }
+
+    /// Returns the samples as collapsed stacks, one line per stack, as read by
+    /// flamegraph tools: `start;main;blink;delay_ms 12`.
+    pub(crate) fn collapsed(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .samples
+            .iter()
+            .map(|((stack, host_function), count)| {
+                let mut frames: Vec<String> = stack
+                    .iter()
+                    .map(|&index| match self.names.get(index) {
+                        Some(name) => name.replace(';', ":"),
+                        None => format!("function_{}", index),
+                    })
+                    .collect();
+                frames.extend(host_function.map(|index| imports::name_of(index).into()));
+                format!("{} {}", frames.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+
+        lines
+    }
+}
+
+/// Samples the call stack of a module into a shared [`Profile`].
+pub(crate) struct Sampler {
+    config: ProfileConfig,
+    profile: Arc<Mutex<Profile>>,
+    instructions: u64,
+    host_calls: u32,
+}
+
+impl Sampler {
+    pub(crate) fn new(config: ProfileConfig, profile: Arc<Mutex<Profile>>) -> Self {
+        Self {
+            config,
+            profile,
+            instructions: 0,
+            host_calls: 0,
+        }
+    }
+
+    /// Accounts for instructions the module is about to execute in the innermost
+    /// function of the call stack. A long block of instructions counts as several samples.
+    pub(crate) fn on_instructions(&mut self, count: u64, call_stack: &[u32]) {
+        if self.config.instructions == 0 {
+            return;
+        }
+
+        self.instructions += count;
+        let samples = self.instructions / self.config.instructions;
+        if samples > 0 {
+            self.instructions %= self.config.instructions;
+            self.profile
+                .lock()
+                .unwrap()
+                .sample(call_stack, None, samples);
+        }
+    }
+
+    /// Accounts for a call into the host function with the given index.
+    pub(crate) fn on_host_call(&mut self, index: usize, call_stack: &[u32]) {
+        if self.config.host_calls == 0 {
+            return;
+        }
+
+        self.host_calls += 1;
+        if self.host_calls >= self.config.host_calls {
+            self.host_calls = 0;
+            self.profile
+                .lock()
+                .unwrap()
+                .sample(call_stack, Some(index), 1);
+        }
+    }
+}
+
+/// Prints the profile to the console, each line prefixed with `profile <module>`, after a
+/// line `profile <module> start`. The profile can be restored from a log of the console
+/// with [`from_console`].
+pub(crate) fn print(module: &str, profile: &Profile) {
+    println!("profile {} start", module);
+    for line in profile.collapsed() {
+        println!("profile {} {}", module, line);
+    }
+}
+
+/// Restores the last printed profile of a module from a log of the console, as collapsed
+/// stacks. Lines of other modules and of the logger are skipped.
+#[allow(dead_code)] // used by tools/simulator
+pub(crate) fn from_console(log: &str, module: &str) -> Result<Vec<String>, String> {
+    let prefix = format!("profile {} ", module);
+    let mut profile = None;
+    for line in log.lines() {
+        let stack = match line.find(&prefix) {
+            Some(start) => line[start + prefix.len()..].trim(),
+            None => continue,
+        };
+        match (stack, profile.as_mut()) {
+            ("start", _) => profile = Some(Vec::new()),
+            (stack, Some(profile)) => profile.push(stack.to_string()),
+            (_, None) => (),
+        }
+    }
+
+    profile.ok_or_else(|| format!("the log contains no profile of module {}", module))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_last_profile_of_a_module_is_restored() {
+        let log = "\
+profile blink start
+profile blink start;main 3
+I (120) supervisor: module blink stopped
+profile button start
+profile button start;poll 1
+I (130) wasm: profile blink start
+I (130) wasm: profile blink start;main;delay_ms 2
+profile button start;poll;gpio_read 4
+I (140) wasm: profile blink start;main 5
+";
+
+        assert_eq!(
+            from_console(log, "blink").unwrap(),
+            ["start;main;delay_ms 2", "start;main 5"]
+        );
+        assert_eq!(
+            from_console(log, "button").unwrap(),
+            ["start;poll 1", "start;poll;gpio_read 4"]
+        );
+        assert!(from_console(log, "sensor").is_err());
+    }
+}
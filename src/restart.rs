@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::storage::{self, Storage};
+
+/// The NVS namespace the crash counters are stored in.
+const CRASH_NAMESPACE: &str = "crashes";
+
+/// How long a module has to run until its crash counter is reset.
+pub(crate) const STABLE_PERIOD: Duration = Duration::from_secs(60);
+
+/// What the supervisor does once a module failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RestartPolicy {
+    /// The module stays failed.
+    Never,
+    /// The module is restarted after the delay, no matter how often it fails.
+    Always(Duration),
+    /// The module is restarted after a delay that starts at `initial` and doubles
+    /// with every restart, up to `max`.
+    Backoff { initial: Duration, max: Duration },
+    /// The module is restarted after the delay, but at most `restarts` times.
+    Limited { restarts: u32, delay: Duration },
+}
+
+impl RestartPolicy {
+    /// Returns how long to wait before restarting a module that was restarted `restarts`
+    /// times already, or `None` if it is not restarted anymore.
+    pub(crate) fn delay(&self, restarts: u32) -> Option<Duration> {
+        match *self {
+            RestartPolicy::Never => None,
+            RestartPolicy::Always(delay) => Some(delay),
+            RestartPolicy::Backoff { initial, max } => {
+                let factor = 2_u32.saturating_pow(restarts);
+                Some(initial.saturating_mul(factor).min(max))
+            }
+            RestartPolicy::Limited { restarts: max, .. } if restarts >= max => None,
+            RestartPolicy::Limited { delay, .. } => Some(delay),
+        }
+    }
+}
+
+/// The crashes of a module in a row, counted for one image of the module.
+struct Crashes {
+    /// The hash of the image, a new image of the module starts over.
+    image: u64,
+    count: u32,
+    /// The count that is stored in NVS, if it is known.
+    persisted: Option<u32>,
+}
+
+/// Counts how often each module crashed in a row, persisted in NVS so that the count
+/// survives a reboot. A start counts as a crash until the module finished or ran for
+/// [`STABLE_PERIOD`], so that a module that resets the whole device is counted as well.
+/// The count belongs to the image of the module, a module that was fixed and flashed
+/// again starts over. Counts above the limit are only kept in memory, so that a module
+/// that keeps crashing doesn't wear out the flash.
+pub(crate) struct CrashCounter {
+    // without NVS, the crashes are only counted until the next reboot
+    storage: Option<Storage>,
+    crashes: HashMap<&'static str, Crashes>,
+    running_since: HashMap<&'static str, Instant>,
+    limit: u32,
+}
+
+impl CrashCounter {
+    /// Opens the crash counters stored in NVS. Nothing but resets is persisted until a
+    /// limit is set with [`CrashCounter::persist_up_to`].
+    pub(crate) fn open() -> Self {
+        let storage = Storage::open_system(CRASH_NAMESPACE)
+            .map_err(|err| info!("Crashes are not persisted: {}", err))
+            .ok();
+
+        Self {
+            storage,
+            crashes: HashMap::new(),
+            running_since: HashMap::new(),
+            limit: 0,
+        }
+    }
+
+    /// Persists counts up to `limit`, the number of crashes that is decided on after a
+    /// reboot. Higher counts are stored as `limit`.
+    pub(crate) fn persist_up_to(&mut self, limit: u32) {
+        self.limit = limit;
+    }
+
+    /// Returns how often the module with this image crashed in a row.
+    pub(crate) fn get(&mut self, module: &'static str, image: &[u8]) -> u32 {
+        if let Some(crashes) = self.crashes.get(module) {
+            return crashes.count;
+        }
+
+        let hash = fnv1a(image);
+        let persisted = self
+            .storage
+            .as_ref()
+            .and_then(|storage| storage.get(&Self::key(module)).ok().flatten())
+            .and_then(|value| <[u8; 12]>::try_from(value).ok());
+        let crashes = match persisted {
+            Some(value) if value[..8] == hash.to_le_bytes() => {
+                let count = u32::from_le_bytes(value[8..].try_into().unwrap());
+                Crashes {
+                    image: hash,
+                    count,
+                    persisted: Some(count),
+                }
+            }
+            // crashes of another image of the module don't count
+            _ => Crashes {
+                image: hash,
+                count: 0,
+                persisted: None,
+            },
+        };
+        let count = crashes.count;
+        self.crashes.insert(module, crashes);
+        count
+    }
+
+    /// Counts the start of a module as a crash, until it is known that it didn't crash.
+    pub(crate) fn started(&mut self, module: &'static str, image: &[u8]) {
+        let count = self.get(module, image) + 1;
+        self.set(module, count);
+        self.running_since.insert(module, Instant::now());
+    }
+
+    /// Records that the module stopped running. A module that finished didn't crash, a
+    /// module that crashed after running stably only crashed once in a row.
+    pub(crate) fn stopped(&mut self, module: &'static str, crashed: bool) {
+        let stable = self
+            .running_since
+            .remove(module)
+            .is_some_and(|since| since.elapsed() >= STABLE_PERIOD);
+        match (crashed, stable) {
+            (false, _) => self.set(module, 0),
+            (true, true) => self.set(module, 1),
+            (true, false) => {}
+        }
+    }
+
+    /// Resets the counter of every module that runs for at least [`STABLE_PERIOD`].
+    pub(crate) fn reset_stable(&mut self) {
+        let stable: Vec<&'static str> = self
+            .running_since
+            .iter()
+            .filter(|(_, since)| since.elapsed() >= STABLE_PERIOD)
+            .map(|(&module, _)| module)
+            .collect();
+
+        for module in stable {
+            self.set(module, 0);
+        }
+    }
+
+    /// Sets the count of a module that was read with [`CrashCounter::get`] before. NVS is
+    /// only written if the persisted count changes.
+    fn set(&mut self, module: &'static str, count: u32) {
+        let limit = self.limit;
+        let crashes = match self.crashes.get_mut(module) {
+            Some(crashes) => crashes,
+            None => return,
+        };
+        crashes.count = count;
+
+        let persisted = count.min(limit);
+        if crashes.persisted == Some(persisted) {
+            return;
+        }
+        let storage = match self.storage.as_mut() {
+            Some(storage) => storage,
+            None => return,
+        };
+
+        let mut value = crashes.image.to_le_bytes().to_vec();
+        value.extend_from_slice(&persisted.to_le_bytes());
+        match storage.set(&Self::key(module), &value) {
+            Ok(()) => crashes.persisted = Some(persisted),
+            Err(err) => info!(
+                "Could not persist the crashes of module {}: {}",
+                module, err
+            ),
+        }
+    }
+
+    /// The counters are stored under the namespace of each module, which is short
+    /// enough for a key.
+    fn key(module: &str) -> String {
+        storage::namespace_of(module).into_string().unwrap()
+    }
+}
+
+/// The 64 bit FNV-1a hash of the bytes, which tells the images of a module apart.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
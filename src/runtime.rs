@@ -25,13 +25,44 @@ use esp_idf_hal::serial::config::Config;
 use esp_idf_hal::serial::Pins;
 use esp_idf_hal::serial::Serial;
 use esp_idf_hal::serial::UART1;
-use esp_idf_sys::EspError;
-use log::info;
+use esp_idf_sys::{
+    EspError, ESP_ERR_INVALID_SIZE, ESP_ERR_NO_MEM, ESP_ERR_NVS_INVALID_LENGTH,
+    ESP_ERR_NVS_NOT_FOUND, ESP_ERR_TIMEOUT,
+};
+use log::{info, Level, Record};
 use std::collections::HashMap;
-use wasmi::MemoryRef;
-use wasmi::{
-    Externals, FuncInstance, ModuleImportResolver, RuntimeValue, Signature, TrapKind, ValueType,
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use wasmi::memory_units::{Bytes, Pages};
+use wasmi::{Externals, FuncInstance, FuncRef, RuntimeValue, StackRecycler, Trap, TrapKind};
+use wasmi::{MemoryRef, TableRef};
+
+use crate::channel::{ChannelError, ChannelHandle, Channels, MAX_MESSAGE_LEN};
+use crate::debugger::Debugger;
+use crate::imports::{
+    CHAN_OPEN_INDEX, CHAN_RECV_INDEX, CHAN_SEND_INDEX, CONSUME_FUEL_INDEX, DELAY_MS_INDEX,
+    DELAY_US_INDEX, ENTER_FUNCTION_INDEX, GPIO_DEINIT_INDEX, GPIO_INIT_INDEX, GPIO_READ_INDEX,
+    GPIO_WRITE_INDEX, KV_DELETE_INDEX, KV_GET_INDEX, KV_LIST_INDEX, KV_SET_INDEX,
+    LEAVE_FUNCTION_INDEX, LOG_INDEX, LOG_MAX_LEVEL_INDEX, MEMORY_GROW_INDEX,
+    PERMISSION_DENIED_INDEX, PRINT_INDEX, PROFILE_MARK_INDEX, RANDOM_FILL_INDEX,
+    REPORT_PANIC_INDEX, TIMER_CANCEL_INDEX, TIMER_POLL_INDEX, TIMER_START_INDEX, TIME_NOW_US_INDEX,
+    UART_INIT_INDEX, UART_READ_INDEX, UART_WRITE_INDEX, UPTIME_MS_INDEX,
 };
+use crate::logging::{self, log_to, DELAY, GPIO, GUEST_TARGET, MEMORY, UART};
+use crate::manifest::{Manifest, PERMISSION_DENIED};
+use crate::memory;
+use crate::metering::{Fuel, FuelConfig};
+use crate::peripherals::Claims;
+use crate::preemption::{Preemption, PreemptionConfig, YieldHook};
+use crate::profiler::{Profile, ProfileConfig, Sampler};
+use crate::scheduler::{Scheduler, TimerHandle};
+use crate::stack::ShadowStack;
+use crate::stats::Stats;
+use crate::storage::Storage;
+use crate::timer::Timers;
+use crate::trace::{Event, Recorder, TracedMemory};
+use crate::trap::{self, GuestPanic};
 
 use esp_idf_hal::prelude::*;
 
@@ -74,7 +105,10 @@ type UartHandle = u8;
 type ErrorCode = i32;
 
 /// Convenience trait for creating trait objects, statisfies both serial read and write.
-trait ReadAndWrite: serial::Read<u8, Error = EspError> + serial::Write<u8, Error = EspError> {}
+trait ReadAndWrite: serial::Read<u8, Error = EspError> + serial::Write<u8, Error = EspError> {
+    /// Uninstalls the driver, so that the UART can be initialized again.
+    fn release(self: Box<Self>) -> Result<(), EspError>;
+}
 
 /// Tell the compiler that the trait is implemented for esp_idf_hals Serial connection type.
 impl<
@@ -84,29 +118,265 @@ impl<
         RTS: esp_idf_hal::gpio::OutputPin,
     > ReadAndWrite for Serial<UART1, TX, RX, CTS, RTS>
 {
+    fn release(self: Box<Self>) -> Result<(), EspError> {
+        (*self).release().map(|_| ())
+    }
 }
 
+/// The duration of a FreeRTOS tick in microseconds. Shorter delays are busy waited.
+const TICK_PERIOD_US: u64 = 1_000_000 / esp_idf_sys::configTICK_RATE_HZ as u64;
+
+/// Limits of the stacks used when calling back into the guest, e.g. for timers.
+const CALLBACK_STACK_LIMIT: usize = 8 * 1024;
+
 /// Runtime that handles call to the host machine. Holds information about the current UART connection
 /// that is exposed to the WASM module, the memory region the WASM module operates in and the Gpio pins
 /// that are being used.
 pub(crate) struct Runtime<'a> {
-    memory: &'a MemoryRef,
+    memory: TracedMemory<'a>,
+    // the most pages the memory may grow to
+    memory_limit: Pages,
+    table: Option<TableRef>,
+    // the target the module logs to, `guest::<name>`
+    log_target: String,
     handle_count: u8,
     uart_connections: HashMap<UartHandle, Box<dyn ReadAndWrite>>,
     gpio_input_mapping: HashMap<RuntimePin, Box<dyn InputPin<Error = EspError>>>,
     gpio_output_mapping: HashMap<RuntimePin, Box<dyn OutputPin<Error = EspError>>>,
+    manifest: Manifest,
+    claims: Claims,
+    timers: Timers,
+    channels: Channels,
+    storage: Option<Storage>,
+    fuel: Option<Fuel>,
+    preemption: Option<Preemption>,
+    sampler: Option<Sampler>,
+    debugger: Option<Debugger>,
+    stats: Arc<Mutex<Stats>>,
+    call_stack: ShadowStack,
+    // the deepest the call stack got, only passed on to the stats once it grows
+    deepest_call: usize,
+    // taken while a callback runs, so that callbacks are never nested
+    callback_stack: Option<StackRecycler>,
 }
 
 impl<'a> Runtime<'a> {
-    /// Creates an instance with a reference to the instances memory.
-    pub(crate) fn new(memory: &'a MemoryRef) -> Self {
+    /// Creates an instance with a reference to the instances memory and, if exported,
+    /// its function table. Without a memory, every host function that reads or writes
+    /// memory fails. The table is needed to call back into the guest.
+    pub(crate) fn new(memory: Option<&'a MemoryRef>, table: Option<TableRef>) -> Self {
         Self {
-            memory,
+            memory: TracedMemory::new(memory),
+            memory_limit: memory::MAX_PAGES,
+            table,
+            log_target: GUEST_TARGET.into(),
             handle_count: 1,
             uart_connections: Default::default(),
             gpio_input_mapping: HashMap::new(),
             gpio_output_mapping: HashMap::new(),
+            manifest: Manifest::default(),
+            claims: Claims::default(),
+            timers: Timers::default(),
+            channels: Channels::default(),
+            storage: None,
+            fuel: None,
+            preemption: None,
+            sampler: None,
+            debugger: None,
+            stats: Default::default(),
+            call_stack: ShadowStack::default(),
+            deepest_call: 0,
+            callback_stack: Some(StackRecycler::with_limits(
+                CALLBACK_STACK_LIMIT,
+                CALLBACK_STACK_LIMIT,
+            )),
+        }
+    }
+
+    /// Names the module, the name is part of the target of its log records.
+    pub(crate) fn with_name(mut self, name: &str) -> Self {
+        self.log_target = format!("{}::{}", GUEST_TARGET, name);
+        self
+    }
+
+    /// Records every call into the host, with the memory it accessed, into the sink.
+    /// The trace can be replayed with `tools/simulator`.
+    pub(crate) fn with_trace(mut self, sink: Box<dyn Write>) -> Self {
+        self.memory.start_recording(Recorder::new(sink));
+        self
+    }
+
+    /// Grants the module the capabilities of its manifest. Without it, the module
+    /// can't use any pins or open channels.
+    pub(crate) fn with_manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = manifest;
+        self
+    }
+
+    /// Gives the module access to its persistent storage. Without it, all
+    /// key-value operations fail.
+    pub(crate) fn with_storage(mut self, storage: Storage) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Limits the fuel the module may spend. Without a limit, the module can run forever.
+    pub(crate) fn with_fuel(mut self, config: FuelConfig) -> Self {
+        self.fuel = Some(Fuel::new(config));
+        self
+    }
+
+    /// Refills the fuel of the module, needs to be called before each invocation.
+    pub(crate) fn refuel(&mut self) {
+        if let Some(fuel) = self.fuel.as_mut() {
+            fuel.reset();
+        }
+    }
+
+    /// Makes the module give up the core regularly, by calling `hook`.
+    pub(crate) fn with_preemption(
+        mut self,
+        config: PreemptionConfig,
+        hook: Box<dyn YieldHook>,
+    ) -> Self {
+        self.preemption = Some(Preemption::new(config, hook));
+        self
+    }
+
+    /// Samples the call stack of the module into `profile`, which can be read while the
+    /// module runs.
+    pub(crate) fn with_profiler(
+        mut self,
+        config: ProfileConfig,
+        profile: Arc<Mutex<Profile>>,
+    ) -> Self {
+        self.sampler = Some(Sampler::new(config, profile));
+        self
+    }
+
+    /// Tracks the calls of the module on `call_stack`, which enforces its call depth. The
+    /// module needs to be instrumented to track calls.
+    pub(crate) fn with_call_stack(mut self, call_stack: ShadowStack) -> Self {
+        self.call_stack = call_stack;
+        self
+    }
+
+    /// Halts the module on the breakpoints of the debugger.
+    pub(crate) fn with_debugger(mut self, debugger: Debugger) -> Self {
+        self.debugger = Some(debugger);
+        self
+    }
+
+    /// Records in the trace that the module trapped, so that a replay that traps where the
+    /// module didn't, or doesn't trap where it did, diverges.
+    pub(crate) fn record_trap(&self, trap: &Trap) {
+        self.memory.record(|| Event::Trap(trap::describe(trap)));
+    }
+
+    /// Records in the trace that the host paused between two calls of `run` of a lifecycle.
+    pub(crate) fn record_pause(&self) {
+        self.memory.record(|| Event::Pause);
+    }
+
+    /// Lets the debugger inspect the module after it trapped, before its state is gone.
+    pub(crate) fn debug_trap(&mut self, reason: &str) {
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.on_trap(reason, self.call_stack.frames());
+        }
+    }
+
+    /// Collects the stats of the module into `stats`, which can be read while the module runs.
+    pub(crate) fn with_stats(mut self, stats: Arc<Mutex<Stats>>) -> Self {
+        if let Some(memory) = self.memory.untraced() {
+            let size = Bytes::from(memory.current_size()).0;
+            stats.lock().unwrap().memory_size(size);
         }
+        self.stats = stats;
+        self
+    }
+
+    /// Limits how far the module can grow its memory with `memory.grow`.
+    pub(crate) fn with_memory_limit(mut self, limit: Pages) -> Self {
+        self.memory_limit = limit;
+        self
+    }
+
+    /// Returns the functions the module is in. After a trap, these are the functions that
+    /// were running when the module trapped.
+    pub(crate) fn call_stack(&self) -> &ShadowStack {
+        &self.call_stack
+    }
+
+    /// Charges fuel for the instructions the module is about to execute.
+    fn consume_fuel(&mut self, amount: u32) -> Result<(), Trap> {
+        if let Some(preemption) = self.preemption.as_mut() {
+            preemption.on_instructions(amount as u64);
+        }
+        if let Some(sampler) = self.sampler.as_mut() {
+            sampler.on_instructions(amount as u64, self.call_stack.frames());
+        }
+
+        match self.fuel.as_mut() {
+            Some(fuel) => fuel
+                .consume(amount as u64)
+                .map_err(|err| Trap::new(TrapKind::Host(Box::new(err)))),
+            None => Ok(()),
+        }
+    }
+
+    /// Grows the memory by `pages` for `memory.grow`, up to the limit of the module.
+    /// Returns the previous size in pages, or -1 if the memory can't grow.
+    fn grow_memory(&mut self, pages: u32) -> i32 {
+        let memory = match self.memory.untraced() {
+            Some(memory) => memory,
+            None => return -1,
+        };
+
+        match memory::grow(memory, pages, self.memory_limit) {
+            Ok(previous) => {
+                let size = Bytes::from(memory.current_size()).0;
+                log_to!(
+                    MEMORY,
+                    Debug,
+                    "Grew the memory by {} pages to {} bytes",
+                    pages,
+                    size
+                );
+                self.stats.lock().unwrap().memory_size(size);
+                previous.0 as i32
+            }
+            Err(err) => {
+                log_to!(
+                    MEMORY,
+                    Warn,
+                    "Could not grow the memory by {} pages: {}",
+                    pages,
+                    err
+                );
+                self.stats.lock().unwrap().failed_grow();
+                -1
+            }
+        }
+    }
+
+    /// Reads an UTF-8 string from the memory of the module.
+    fn read_str(&self, offset: u32, len: u32) -> Option<String> {
+        let bytes = self.memory.get(offset, len as usize).ok()?;
+        String::from_utf8(bytes).ok()
+    }
+
+    /// Writes `bytes` to a buffer of the module with capacity `cap` and the number of
+    /// bytes to `len_ptr`. The length is always written, so that the module can retry
+    /// with a bigger buffer if `cap` is too small.
+    fn write_buffer(&self, bytes: &[u8], buf: u32, cap: u32, len_ptr: u32) -> ErrorCode {
+        if self.memory.set_value(len_ptr, bytes.len() as u32).is_err() {
+            return 1;
+        }
+        if bytes.len() > cap as usize {
+            return ESP_ERR_NVS_INVALID_LENGTH as ErrorCode;
+        }
+
+        self.memory.set(buf, bytes).map_or(1, |_| 0)
     }
 
     /// Inititalize a new uart connection over the given pins.
@@ -120,46 +390,32 @@ impl<'a> Runtime<'a> {
         rts: Option<RuntimePin>,
         handle: u32,
     ) -> ErrorCode {
+        // the flow control pins are optional, their numbers are in the memory of the module
+        let cts = cts.map(|(_, pin_ptr)| self.memory.get_value::<u32>(pin_ptr).unwrap_or(0));
+        let rts = rts.map(|(_, pin_ptr)| self.memory.get_value::<u32>(pin_ptr).unwrap_or(0));
+        let pins: Vec<u32> = [Some(tx.1), Some(rx.1), cts, rts]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        if !self.manifest.allows_uart() || !pins.iter().all(|&pin| self.manifest.allows_pin(pin)) {
+            return PERMISSION_DENIED;
+        }
         // for the moment: allow only one uart connection per runtime
         if self.uart_connections.len() > 0 {
             return -1;
         }
-        let peripherals = Peripherals::take().unwrap();
-        // Setup the pins
-        let tx_pin = match Self::get_output_pin_by_nr(tx.1) {
-            Ok(p) => p,
-            Err(err) => return err.code(),
-        };
-
-        let rx_pin = match Self::get_input_pin_by_nr(rx.1) {
-            Ok(p) => p,
-            Err(err) => return err.code(),
-        };
-
-        let cts_pin = cts
-            .map(|(_, pin_ptr)| self.memory.get_value(pin_ptr).unwrap_or(0))
-            .map(|pin| Self::get_input_pin_by_nr(pin).ok())
-            .unwrap_or_default();
-
-        let rts_pin = rts
-            .map(|(_, pin_ptr)| self.memory.get_value(pin_ptr).unwrap_or(0))
-            .map(|pin| Self::get_output_pin_by_nr(pin).ok())
-            .unwrap_or_default();
-
-        let pins = Pins {
-            tx: tx_pin,
-            rx: rx_pin,
-            cts: cts_pin,
-            rts: rts_pin,
-        };
-
-        // create a config
-        let config = Config::default().baudrate(Hertz(115_200));
+        // the controller and the pins might be in use by another module, or as Gpio by this one
+        if !self.claims.claim_uart(&pins) {
+            return -1;
+        }
 
-        // initialize a serial connection over the defined pins
-        let serial = match Serial::new(peripherals.uart1, pins, config) {
-            Ok(ser) => ser,
-            Err(err) => return err.code(),
+        let serial = match Self::open_serial(tx.1, rx.1, cts, rts) {
+            Ok(serial) => serial,
+            Err(err) => {
+                self.claims.release_uart();
+                return err.code();
+            }
         };
 
         // save the handle so that the WASM code can acess it
@@ -168,15 +424,38 @@ impl<'a> Runtime<'a> {
             .set_value(handle, self.handle_count)
             .map_or(1, |_| 0);
 
-        // save the connection as a trait object
-        self.uart_connections
-            .insert(self.handle_count, Box::new(serial));
+        self.uart_connections.insert(self.handle_count, serial);
 
         self.handle_count += 1;
 
         res
     }
 
+    /// Sets up the pins and opens a serial connection over UART1, which has to be claimed.
+    fn open_serial(
+        tx: u32,
+        rx: u32,
+        cts: Option<u32>,
+        rts: Option<u32>,
+    ) -> Result<Box<dyn ReadAndWrite>, EspError> {
+        let pins = Pins {
+            tx: Self::get_output_pin_by_nr(tx)?,
+            rx: Self::get_input_pin_by_nr(rx)?,
+            cts: cts.and_then(|pin| Self::get_input_pin_by_nr(pin).ok()),
+            rts: rts.and_then(|pin| Self::get_output_pin_by_nr(pin).ok()),
+        };
+
+        // create a config
+        let config = Config::default().baudrate(Hertz(115_200));
+
+        // initialize a serial connection over the defined pins, UART1 is exclusively ours
+        let uart1 = unsafe { UART1::new() };
+        let serial = Serial::new(uart1, pins, config)?;
+
+        // save the connection as a trait object
+        Ok(Box::new(serial))
+    }
+
     /// Initialize a pin as input pin and return it as a generic `GpioPin`.
     fn get_input_pin_by_nr(nr: u32) -> Result<GpioPin<Input>, EspError> {
         match nr {
@@ -243,12 +522,16 @@ impl<'a> Runtime<'a> {
         }
     }
 
-    /// Prints to the command line, helpful for debugging the WASM applications.
-    fn print(&mut self, offset: u32, len: usize) {
-        let bytes = self.memory.get(offset, len).unwrap();
+    /// Prints to the command line, helpful for debugging the WASM applications. There is
+    /// no error code to return, so a message out of bounds of the memory traps.
+    fn print(&mut self, offset: u32, len: usize) -> Result<(), Trap> {
+        let bytes = self
+            .memory
+            .get(offset, len)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
-        let msg = unsafe { core::str::from_utf8_unchecked(&bytes) };
-        println!("{}", msg);
+        println!("{}", String::from_utf8_lossy(&bytes));
+        Ok(())
     }
 
     /// Initialize a gpio pin as Input or output and safe it for later.
@@ -256,6 +539,16 @@ impl<'a> Runtime<'a> {
         if port != 0 {
             return -1;
         }
+        if !self.manifest.allows_pin(pin) {
+            return PERMISSION_DENIED;
+        }
+        // the pin might be in use by another module, or by the UART of this one
+        if !self.claims.claim_gpio(pin) {
+            return -1;
+        }
+        // a pin that is initialized again is configured anew, its driver is dropped first
+        self.gpio_input_mapping.remove(&(port, pin));
+        self.gpio_output_mapping.remove(&(port, pin));
         // reset the pin due to
         // https://github.com/esp-rs/esp-idf-hal/issues/9
         unsafe {
@@ -369,10 +662,15 @@ impl<'a> Runtime<'a> {
                 self.gpio_input_mapping,
                 self.gpio_output_mapping
             ),
-            _ => return -1,
+            _ => {
+                self.claims.release_gpio(pin);
+                return -1;
+            }
         }
 
-        info!(
+        log_to!(
+            GPIO,
+            Debug,
             "Initialized pin {} as {}",
             pin,
             if is_input { "Input" } else { "Output" }
@@ -381,11 +679,19 @@ impl<'a> Runtime<'a> {
         0
     }
 
-    /// Deinitializes a Gpio and frees the underlying ressources.
+    /// Deinitializes a Gpio and frees the underlying ressources. Only a pin the module
+    /// initialized as Gpio is released, not one of its UART.
     fn deinit_gpio(&mut self, port: u32, pin: u32) -> ErrorCode {
-        if let Some((_, gpio)) = self.gpio_output_mapping.remove_entry(&(port, pin)) {
-            drop(gpio);
+        if !self.manifest.allows_pin(pin) {
+            return PERMISSION_DENIED;
         }
+        // the drivers are dropped with the entries
+        let output = self.gpio_output_mapping.remove(&(port, pin));
+        let input = self.gpio_input_mapping.remove(&(port, pin));
+        if output.is_none() && input.is_none() {
+            return -1;
+        }
+        self.claims.release_gpio(pin);
         0
     }
 
@@ -394,7 +700,7 @@ impl<'a> Runtime<'a> {
     fn read_gpio(&mut self, port: u32, pin: u32, offset: u32) -> ErrorCode {
         match self.gpio_input_mapping.get(&(port, pin)) {
             Some(gpio) => {
-                info!("reading from pin {}", pin);
+                log_to!(GPIO, Trace, "Reading from pin {}", pin);
                 match gpio.is_high() {
                     Ok(value) => self.memory.set_value(offset, value as u8).map_or(1, |_| 0),
                     Err(_) => -1,
@@ -408,7 +714,7 @@ impl<'a> Runtime<'a> {
     fn write_gpio(&mut self, port: u32, pin: u32, value: u32) -> ErrorCode {
         match self.gpio_output_mapping.get_mut(&(port, pin)) {
             Some(gpio) => {
-                info!("Setting gpio {} to {}", pin, value);
+                log_to!(GPIO, Trace, "Setting gpio {} to {}", pin, value);
                 if value == 0 {
                     gpio.set_low()
                 } else {
@@ -420,24 +726,329 @@ impl<'a> Runtime<'a> {
         }
     }
 
-    /// Delay the execution.
-    fn delay_ms(&self, ms: u32) {
+    /// Delay the execution for the given milliseconds.
+    fn delay_ms(&mut self, ms: u32) -> Result<(), Trap> {
+        log_to!(DELAY, Trace, "Delaying for {} ms", ms);
+        self.delay(Duration::from_millis(ms as u64))
+    }
+
+    /// Delay the execution for the given microseconds.
+    fn delay_us(&mut self, us: u32) -> Result<(), Trap> {
+        log_to!(DELAY, Trace, "Delaying for {} us", us);
+        self.delay(Duration::from_micros(us as u64))
+    }
+
+    /// Stops a software timer and frees the underlying ressources.
+    fn timer_cancel(&mut self, handle: TimerHandle) -> ErrorCode {
+        if self.timers.cancel(handle) {
+            0
+        } else {
+            -1
+        }
+    }
+
+    /// Reads the value stored under a key into the given buffer.
+    fn kv_get(&mut self, key: (u32, u32), buf: u32, cap: u32, len_ptr: u32) -> ErrorCode {
+        let key = match self.read_str(key.0, key.1) {
+            Some(key) => key,
+            None => return -1,
+        };
+
+        match self.storage.as_ref().map(|storage| storage.get(&key)) {
+            Some(Ok(Some(value))) => self.write_buffer(&value, buf, cap, len_ptr),
+            Some(Ok(None)) => ESP_ERR_NVS_NOT_FOUND as ErrorCode,
+            Some(Err(err)) => err.code(),
+            None => -1,
+        }
+    }
+
+    /// Stores a value under a key, overwriting any previous value.
+    fn kv_set(&mut self, key: (u32, u32), value: (u32, u32)) -> ErrorCode {
+        let key = match self.read_str(key.0, key.1) {
+            Some(key) => key,
+            None => return -1,
+        };
+        let value = match self.memory.get(value.0, value.1 as usize) {
+            Ok(value) => value,
+            Err(_) => return 1,
+        };
+
+        match self
+            .storage
+            .as_mut()
+            .map(|storage| storage.set(&key, &value))
+        {
+            Some(Ok(())) => 0,
+            Some(Err(err)) => err.code(),
+            None => -1,
+        }
+    }
+
+    /// Removes a key together with its value.
+    fn kv_delete(&mut self, key: (u32, u32)) -> ErrorCode {
+        let key = match self.read_str(key.0, key.1) {
+            Some(key) => key,
+            None => return -1,
+        };
+
+        match self.storage.as_mut().map(|storage| storage.delete(&key)) {
+            Some(Ok(true)) => 0,
+            Some(Ok(false)) => ESP_ERR_NVS_NOT_FOUND as ErrorCode,
+            Some(Err(err)) => err.code(),
+            None => -1,
+        }
+    }
+
+    /// Writes all keys of the module into the given buffer, each key terminated by a NUL byte.
+    fn kv_list(&mut self, buf: u32, cap: u32, len_ptr: u32) -> ErrorCode {
+        let keys = match self.storage.as_ref() {
+            Some(storage) => storage.keys(),
+            None => return -1,
+        };
+
+        let mut bytes = Vec::new();
+        for key in keys {
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.push(0);
+        }
+
+        self.write_buffer(&bytes, buf, cap, len_ptr)
+    }
+
+    /// Fills a buffer of the module with random bytes from the hardware RNG.
+    fn random_fill(&mut self, offset: u32, len: u32) -> ErrorCode {
+        let filled = self.memory.fill(offset, len, |buf| unsafe {
+            esp_idf_sys::esp_fill_random(buf.as_mut_ptr() as *mut _, buf.len() as _)
+        });
+
+        if filled {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Opens the channel with the given name and writes its handle to the memory location
+    /// specified by the WASM code. Modules that open the same name share the channel.
+    fn chan_open(&mut self, name: (u32, u32), handle: u32) -> ErrorCode {
+        let name = match self.read_str(name.0, name.1) {
+            Some(name) => name,
+            None => return -1,
+        };
+        if !self.manifest.allows_channel(&name) {
+            return PERMISSION_DENIED;
+        }
+
+        match self.channels.open(&name) {
+            Some(channel) => self.memory.set_value(handle, channel).map_or(1, |_| 0),
+            None => ESP_ERR_NO_MEM as ErrorCode,
+        }
+    }
+
+    /// Sends a message over a channel, waits up to `timeout_us` microseconds
+    /// if the channel is full.
+    fn chan_send(
+        &mut self,
+        handle: ChannelHandle,
+        message: (u32, u32),
+        timeout_us: u64,
+    ) -> Result<ErrorCode, Trap> {
+        let channel = match self.channels.get(handle) {
+            Some(channel) => channel,
+            None => return Ok(-1),
+        };
+        if message.1 as usize > MAX_MESSAGE_LEN {
+            return Ok(ESP_ERR_INVALID_SIZE as ErrorCode);
+        }
+        let message = match self.memory.get(message.0, message.1 as usize) {
+            Ok(message) => message,
+            Err(_) => return Ok(1),
+        };
+
+        let res = self.wait_for_channel(timeout_us, |timeout| channel.send(&message, timeout))?;
+        Ok(match res {
+            Ok(()) => 0,
+            Err(err) => Self::channel_error_code(err),
+        })
+    }
+
+    /// Receives a message from a channel into the given buffer, waits up to `timeout_us`
+    /// microseconds if the channel is empty. If the buffer is too small, the length of the
+    /// message is written and the message stays in the channel.
+    fn chan_recv(
+        &mut self,
+        handle: ChannelHandle,
+        buf: u32,
+        cap: u32,
+        timeout_us: u64,
+        len_ptr: u32,
+    ) -> Result<ErrorCode, Trap> {
+        let channel = match self.channels.get(handle) {
+            Some(channel) => channel,
+            None => return Ok(-1),
+        };
+
+        let res =
+            self.wait_for_channel(timeout_us, |timeout| channel.recv(cap as usize, timeout))?;
+        Ok(match res {
+            Ok(message) => self.write_buffer(&message, buf, cap, len_ptr),
+            Err(ChannelError::TooLarge(len)) => {
+                if self.memory.set_value(len_ptr, len as u32).is_err() {
+                    return Ok(1);
+                }
+                ESP_ERR_INVALID_SIZE as ErrorCode
+            }
+            Err(err) => Self::channel_error_code(err),
+        })
+    }
+
+    /// Converts a channel error into the code returned to the module.
+    fn channel_error_code(err: ChannelError) -> ErrorCode {
+        match err {
+            ChannelError::Timeout => ESP_ERR_TIMEOUT as ErrorCode,
+            ChannelError::TooLarge(_) => ESP_ERR_INVALID_SIZE as ErrorCode,
+        }
+    }
+
+    /// Reads a string from the memory of the module, replacing invalid UTF-8. Out of
+    /// bounds strings are read as empty strings, a garbled message is better than none.
+    fn read_lossy(&self, (offset, len): (u32, u32)) -> String {
+        self.memory
+            .get(offset, len as usize)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Reports a panic of the module, which ends its execution with a trap.
+    fn report_panic(&mut self, message: (u32, u32), file: (u32, u32), line: u32) -> Trap {
+        Trap::new(TrapKind::Host(Box::new(GuestPanic {
+            message: self.read_lossy(message),
+            file: self.read_lossy(file),
+            line,
+        })))
+    }
+
+    /// Forwards a log record of the module to the logger. The target of the module is
+    /// prefixed with `guest::<name>`, records with an invalid level are dropped.
+    fn log(&mut self, level: u32, target: (u32, u32), message: (u32, u32)) {
+        let level = match Level::iter().find(|l| *l as u32 == level) {
+            Some(level) if level <= logging::max_level_for(&self.log_target) => level,
+            _ => return,
+        };
+
+        let target = match self.read_lossy(target) {
+            target if target.is_empty() => self.log_target.clone(),
+            target => format!("{}::{}", self.log_target, target),
+        };
+        log::logger().log(
+            &Record::builder()
+                .level(level)
+                .target(&target)
+                .args(format_args!("{}", self.read_lossy(message)))
+                .build(),
+        );
+    }
+
+    /// Returns the microseconds passed since boot. The underlying
+    /// `esp_timer` is monotonic and never wraps during the lifetime of a device.
+    fn time_now_us(&self) -> i64 {
+        unsafe { esp_idf_sys::esp_timer_get_time() }
+    }
+
+    /// Returns the milliseconds passed since boot.
+    fn uptime_ms(&self) -> i64 {
+        self.time_now_us() / 1_000
+    }
+}
+
+/// Waits on the device: sleeping gives up the core to FreeRTOS, or waits for the timers of the
+/// module while it has some, and busy waits keep the core.
+impl<'a> Scheduler for Runtime<'a> {
+    const TICK_PERIOD: Duration = Duration::from_micros(TICK_PERIOD_US);
+
+    fn now(&self) -> Duration {
+        Duration::from_micros(self.time_now_us() as u64)
+    }
+
+    fn busy_wait(&mut self, duration: Duration) {
         let mut ets = Ets;
-        info!("Delaying for {} ms", ms);
-        ets.delay_ms(ms);
+        ets.delay_us(duration.as_micros() as u32);
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        if self.timers.is_empty() {
+            let ticks = duration.as_micros() as u64 / TICK_PERIOD_US;
+            unsafe { esp_idf_sys::vTaskDelay(ticks as u32) };
+        } else {
+            self.timers.wait(duration);
+        }
+    }
+
+    fn preemption(&mut self) -> Option<&mut Preemption> {
+        self.preemption.as_mut()
+    }
+
+    fn fuel(&mut self) -> Option<&mut Fuel> {
+        self.fuel.as_mut()
+    }
+
+    fn has_timers(&self) -> bool {
+        !self.timers.is_empty()
+    }
+
+    fn create_timer(
+        &mut self,
+        period: Duration,
+        periodic: bool,
+        func_index: u32,
+    ) -> Result<TimerHandle, ErrorCode> {
+        self.timers
+            .start(period.as_micros() as u64, periodic, func_index)
+            .map_err(|err| err.code())
+    }
+
+    fn next_expired(&mut self) -> Option<(TimerHandle, u32)> {
+        self.timers.next_expired()
+    }
+
+    fn table(&self) -> Option<&TableRef> {
+        self.table.as_ref()
+    }
+
+    fn callback_stack(&mut self) -> &mut Option<StackRecycler> {
+        &mut self.callback_stack
+    }
+
+    /// Records the callback in the trace and its duration in the stats.
+    fn invoke_callback(
+        &mut self,
+        func_index: u32,
+        callback: &FuncRef,
+        args: &[RuntimeValue],
+        stack: &mut StackRecycler,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        self.memory.record(|| Event::Callback {
+            func_index,
+            args: args.to_vec(),
+        });
+        let started = Instant::now();
+        let result = FuncInstance::invoke_with_stack(callback, args, self, stack);
+        self.stats.lock().unwrap().add_callback(started.elapsed());
+        result
     }
 }
 
-/// Internal index of the functions.
-const UART_WRITE_INDEX: usize = 0;
-const UART_READ_INDEX: usize = 1;
-const UART_INIT_INDEX: usize = 2;
-const PRINT_INDEX: usize = 3;
-const GPIO_WRITE_INDEX: usize = 4;
-const GPIO_READ_INDEX: usize = 5;
-const GPIO_INIT_INDEX: usize = 6;
-const GPIO_DEINIT_INDEX: usize = 7;
-const DELAY_MS_INDEX: usize = 8;
+/// Releases the peripherals of the module, so that a restarted module can use them again.
+/// The pins are released together with the claims.
+impl<'a> Drop for Runtime<'a> {
+    fn drop(&mut self) {
+        for (_, connection) in self.uart_connections.drain() {
+            if let Err(err) = connection.release() {
+                info!("Could not release the UART: {}", err);
+            }
+        }
+    }
+}
 
 /// Needed for resolving the functions and call them from WASM.
 impl<'a> Externals for Runtime<'a> {
@@ -446,11 +1057,85 @@ impl<'a> Externals for Runtime<'a> {
         index: usize,
         args: wasmi::RuntimeArgs,
     ) -> Result<Option<wasmi::RuntimeValue>, wasmi::Trap> {
+        // charging fuel and tracking calls are the hottest paths, so they skip the timer dispatch
+        // below. They only depend on the module itself, so they are not recorded either.
+        match index {
+            CONSUME_FUEL_INDEX => {
+                let amount: u32 = args.nth(0);
+                self.consume_fuel(amount)?;
+
+                return Ok(None);
+            }
+            ENTER_FUNCTION_INDEX => {
+                let func_index: u32 = args.nth(0);
+                self.call_stack.enter(func_index)?;
+                let depth = self.call_stack.frames().len();
+                if depth > self.deepest_call {
+                    self.deepest_call = depth;
+                    self.stats.lock().unwrap().call_depth(self.deepest_call);
+                }
+                if let Some(debugger) = self.debugger.as_mut() {
+                    debugger.on_enter(func_index, self.call_stack.frames());
+                }
+
+                return Ok(None);
+            }
+            LEAVE_FUNCTION_INDEX => {
+                self.call_stack.leave();
+
+                return Ok(None);
+            }
+            MEMORY_GROW_INDEX => {
+                let pages: u32 = args.nth(0);
+                let result = self.grow_memory(pages);
+                self.memory.record(|| Event::Grow { pages, result });
+
+                return Ok(Some(RuntimeValue::I32(result)));
+            }
+            _ => (),
+        }
+
+        self.memory.record(|| Event::Call {
+            index: index as u32,
+            args: args.as_ref().to_vec(),
+        });
+        let call = self.stats.lock().unwrap().begin_host_call();
+        let result = self.call_host(index, args);
+        self.stats.lock().unwrap().end_host_call(index, call);
+        self.memory.record(|| match &result {
+            Ok(value) => Event::Return(*value),
+            Err(trap) => Event::Trap(trap::describe(trap)),
+        });
+
+        result
+    }
+}
+
+impl<'a> Runtime<'a> {
+    /// Calls the host function with the given index.
+    fn call_host(
+        &mut self,
+        index: usize,
+        args: wasmi::RuntimeArgs,
+    ) -> Result<Option<wasmi::RuntimeValue>, wasmi::Trap> {
+        if let Some(preemption) = self.preemption.as_mut() {
+            preemption.on_host_call();
+        }
+        if let Some(sampler) = self.sampler.as_mut() {
+            sampler.on_host_call(index, self.call_stack.frames());
+        }
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.on_host_call(index, args.as_ref(), self.call_stack.frames());
+        }
+
+        // every call into the host is a chance to deliver pending timer events
+        self.dispatch_timers()?;
+
         // find the right function and execute it
         // always make sure to fetch the right arguments
         match index {
             UART_WRITE_INDEX => {
-                info!("UART Write called!");
+                log_to!(UART, Trace, "UART Write called!");
                 let handle: u8 = args.nth(0);
                 let word: u8 = args.nth(1);
                 let result = self.uart_write(handle, word);
@@ -460,7 +1145,7 @@ impl<'a> Externals for Runtime<'a> {
             UART_READ_INDEX => {
                 let handle: u8 = args.nth(0);
                 let ptr: u32 = args.nth(1);
-                info!("UART Read called!");
+                log_to!(UART, Trace, "UART Read called!");
                 let result = self.uart_read(handle, ptr);
 
                 Ok(Some(RuntimeValue::I32(result as i32)))
@@ -471,7 +1156,7 @@ impl<'a> Externals for Runtime<'a> {
                 let tx_pin: u32 = args.nth(2);
                 let rx_port: u32 = args.nth(3);
                 let rx_pin: u32 = args.nth(4);
-                info!("Initializing uart");
+                log_to!(UART, Debug, "Initializing uart");
 
                 let result =
                     self.uart_init((tx_port, tx_pin), (rx_port, rx_pin), None, None, handle);
@@ -481,7 +1166,7 @@ impl<'a> Externals for Runtime<'a> {
             PRINT_INDEX => {
                 let offset: u32 = args.nth(0);
                 let len: i32 = args.nth(1);
-                self.print(offset, len as usize);
+                self.print(offset, len as usize)?;
                 Ok(None)
             }
             GPIO_READ_INDEX => {
@@ -504,10 +1189,123 @@ impl<'a> Externals for Runtime<'a> {
             DELAY_MS_INDEX => {
                 let ms: u32 = args.nth(0);
 
-                self.delay_ms(ms);
+                self.delay_ms(ms)?;
+
+                Ok(None)
+            }
+            DELAY_US_INDEX => {
+                let us: u32 = args.nth(0);
+
+                self.delay_us(us)?;
 
                 Ok(None)
             }
+            KV_GET_INDEX => {
+                let key_ptr: u32 = args.nth(0);
+                let key_len: u32 = args.nth(1);
+                let buf: u32 = args.nth(2);
+                let cap: u32 = args.nth(3);
+                let len_ptr: u32 = args.nth(4);
+
+                let res = self.kv_get((key_ptr, key_len), buf, cap, len_ptr);
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            KV_SET_INDEX => {
+                let key_ptr: u32 = args.nth(0);
+                let key_len: u32 = args.nth(1);
+                let value_ptr: u32 = args.nth(2);
+                let value_len: u32 = args.nth(3);
+
+                let res = self.kv_set((key_ptr, key_len), (value_ptr, value_len));
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            KV_DELETE_INDEX => {
+                let key_ptr: u32 = args.nth(0);
+                let key_len: u32 = args.nth(1);
+
+                let res = self.kv_delete((key_ptr, key_len));
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            KV_LIST_INDEX => {
+                let buf: u32 = args.nth(0);
+                let cap: u32 = args.nth(1);
+                let len_ptr: u32 = args.nth(2);
+
+                let res = self.kv_list(buf, cap, len_ptr);
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            RANDOM_FILL_INDEX => {
+                let ptr: u32 = args.nth(0);
+                let len: u32 = args.nth(1);
+
+                let res = self.random_fill(ptr, len);
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            CHAN_OPEN_INDEX => {
+                let name_ptr: u32 = args.nth(0);
+                let name_len: u32 = args.nth(1);
+                let handle: u32 = args.nth(2);
+
+                let res = self.chan_open((name_ptr, name_len), handle);
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            CHAN_SEND_INDEX => {
+                let handle: u32 = args.nth(0);
+                let ptr: u32 = args.nth(1);
+                let len: u32 = args.nth(2);
+                let timeout_us: u64 = args.nth(3);
+
+                let res = self.chan_send(handle, (ptr, len), timeout_us)?;
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            CHAN_RECV_INDEX => {
+                let handle: u32 = args.nth(0);
+                let buf: u32 = args.nth(1);
+                let cap: u32 = args.nth(2);
+                let timeout_us: u64 = args.nth(3);
+                let len_ptr: u32 = args.nth(4);
+
+                let res = self.chan_recv(handle, buf, cap, timeout_us, len_ptr)?;
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            TIME_NOW_US_INDEX => Ok(Some(RuntimeValue::I64(self.time_now_us()))),
+            UPTIME_MS_INDEX => Ok(Some(RuntimeValue::I64(self.uptime_ms()))),
+            TIMER_START_INDEX => {
+                let period_us: u64 = args.nth(0);
+                let periodic: i32 = args.nth(1);
+                let func_index: u32 = args.nth(2);
+                let handle: u32 = args.nth(3);
+
+                // the handle of the timer is written to the memory of the module
+                let res = match self.timer_start(period_us, periodic, func_index) {
+                    Ok(timer) => self.memory.set_value(handle, timer).map_or(1, |_| 0),
+                    Err(code) => code,
+                };
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            TIMER_CANCEL_INDEX => {
+                let handle: u32 = args.nth(0);
+
+                let res = self.timer_cancel(handle);
+
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            TIMER_POLL_INDEX => {
+                let timeout_us: u64 = args.nth(0);
+
+                self.timer_poll(timeout_us)?;
+
+                Ok(Some(RuntimeValue::I32(0)))
+            }
             GPIO_INIT_INDEX => {
                 let port: u32 = args.nth(0);
                 let pin: u32 = args.nth(1);
@@ -525,80 +1323,36 @@ impl<'a> Externals for Runtime<'a> {
 
                 Ok(Some(RuntimeValue::I32(res)))
             }
-            _ => Err(wasmi::Trap::new(TrapKind::UnexpectedSignature)),
-        }
-    }
-}
+            REPORT_PANIC_INDEX => {
+                let msg_ptr: u32 = args.nth(0);
+                let msg_len: u32 = args.nth(1);
+                let file_ptr: u32 = args.nth(2);
+                let file_len: u32 = args.nth(3);
+                let line: u32 = args.nth(4);
 
-/// Resolves external functions on the host system.
-pub(crate) struct UartModuleImportResolver;
-
-impl<'a> ModuleImportResolver for UartModuleImportResolver {
-    fn resolve_func(
-        &self,
-        field_name: &str,
-        _signature: &wasmi::Signature,
-    ) -> Result<wasmi::FuncRef, wasmi::Error> {
-        match field_name {
-            "uart_init" => Ok(FuncInstance::alloc_host(
-                Signature::new(
-                    &[
-                        ValueType::I32, // handle
-                        ValueType::I32, // tx port
-                        ValueType::I32, // tx pin
-                        ValueType::I32, // rx port
-                        ValueType::I32, // rx pin
-                        ValueType::I32, // cts port ptr
-                        ValueType::I32, // cts pin ptr
-                        ValueType::I32, // rts port ptr
-                        ValueType::I32, // rts pin ptr
-                    ][..],
-                    Some(ValueType::I32),
-                ),
-                UART_INIT_INDEX,
-            )),
-            "uart_write" => Ok(FuncInstance::alloc_host(
-                Signature::new(&[ValueType::I32, ValueType::I32][..], Some(ValueType::I32)),
-                UART_WRITE_INDEX,
-            )),
-            "uart_read" => Ok(FuncInstance::alloc_host(
-                Signature::new(&[ValueType::I32, ValueType::I32][..], Some(ValueType::I32)),
-                UART_READ_INDEX,
-            )),
-            "print" => Ok(FuncInstance::alloc_host(
-                Signature::new(&[ValueType::I32, ValueType::I32][..], None),
-                PRINT_INDEX,
-            )),
-            "gpio_read" => Ok(FuncInstance::alloc_host(
-                Signature::new(
-                    &[ValueType::I32, ValueType::I32, ValueType::I32][..],
-                    Some(ValueType::I32),
-                ),
-                GPIO_READ_INDEX,
-            )),
-            "gpio_write" => Ok(FuncInstance::alloc_host(
-                Signature::new(
-                    &[ValueType::I32, ValueType::I32, ValueType::I32][..],
-                    Some(ValueType::I32),
-                ),
-                GPIO_WRITE_INDEX,
-            )),
-            "gpio_init" => Ok(FuncInstance::alloc_host(
-                Signature::new(
-                    &[ValueType::I32, ValueType::I32, ValueType::I32][..],
-                    Some(ValueType::I32),
-                ),
-                GPIO_INIT_INDEX,
-            )),
-            "gpio_deinit" => Ok(FuncInstance::alloc_host(
-                Signature::new(&[ValueType::I32, ValueType::I32][..], Some(ValueType::I32)),
-                GPIO_DEINIT_INDEX,
-            )),
-            "delay_ms" => Ok(FuncInstance::alloc_host(
-                Signature::new(&[ValueType::I32][..], None),
-                DELAY_MS_INDEX,
-            )),
-            x => Err(wasmi::Error::Function(format!("unknown function {}", x))),
+                Err(self.report_panic((msg_ptr, msg_len), (file_ptr, file_len), line))
+            }
+            LOG_INDEX => {
+                let level: u32 = args.nth(0);
+                let target_ptr: u32 = args.nth(1);
+                let target_len: u32 = args.nth(2);
+                let msg_ptr: u32 = args.nth(3);
+                let msg_len: u32 = args.nth(4);
+
+                self.log(level, (target_ptr, target_len), (msg_ptr, msg_len));
+                Ok(None)
+            }
+            LOG_MAX_LEVEL_INDEX => Ok(Some(RuntimeValue::I32(logging::max_level_for(
+                &self.log_target,
+            ) as i32))),
+            PROFILE_MARK_INDEX => {
+                let id: u32 = args.nth(0);
+
+                self.stats.lock().unwrap().mark(id);
+                Ok(None)
+            }
+            PERMISSION_DENIED_INDEX => Ok(Some(RuntimeValue::I32(PERMISSION_DENIED))),
+            _ => Err(wasmi::Trap::new(TrapKind::UnexpectedSignature)),
         }
     }
 }
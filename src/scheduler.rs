@@ -0,0 +1,325 @@
+use std::time::Duration;
+
+use wasmi::{
+    Externals, FuncInstance, FuncRef, RuntimeValue, StackRecycler, TableRef, Trap, ValueType,
+};
+
+use crate::channel::ChannelError;
+use crate::metering::Fuel;
+use crate::preemption::Preemption;
+
+/// The longest the runtime gives up the core in one go, so that the watchdog can be fed and
+/// the expired timers dispatched in between.
+pub(crate) const MAX_SLEEP: Duration = Duration::from_secs(1);
+
+/// The shortest period of a periodic timer, `esp_timer` rejects shorter ones.
+pub(crate) const MIN_PERIOD: Duration = Duration::from_micros(50);
+
+/// The code of `ESP_ERR_INVALID_ARG`, returned for a periodic timer that is too short.
+const ESP_ERR_INVALID_ARG: i32 = 0x102;
+
+/// The type for the handles that are given out for a software timer.
+pub(crate) type TimerHandle = u32;
+
+/// How a module waits: its delays, timers and the waits for channels. They are split up and
+/// dispatched here, against the clock and the timers of an implementor, which are those of
+/// the device in the runtime and virtual ones in the simulator.
+pub(crate) trait Scheduler: Externals + Sized {
+    /// The duration of a FreeRTOS tick. Shorter delays are busy waited.
+    const TICK_PERIOD: Duration;
+
+    /// Returns the time passed since an arbitrary, fixed point.
+    fn now(&self) -> Duration;
+
+    /// Waits without giving up the core.
+    fn busy_wait(&mut self, duration: Duration);
+
+    /// Gives up the core for `duration`, or until a timer expires.
+    fn sleep(&mut self, duration: Duration);
+
+    /// Called after the module blocked on a channel for `duration`. The time passed by
+    /// itself, unless it is virtual.
+    fn blocked(&mut self, _duration: Duration) {}
+
+    /// The preemption of the module, if it gives up the core regularly.
+    fn preemption(&mut self) -> Option<&mut Preemption>;
+
+    /// The fuel of the module, if it is metered.
+    fn fuel(&mut self) -> Option<&mut Fuel>;
+
+    /// Returns true if the module has an active timer.
+    fn has_timers(&self) -> bool;
+
+    /// Creates and starts a timer, returns its handle or the error code for the module.
+    fn create_timer(
+        &mut self,
+        period: Duration,
+        periodic: bool,
+        func_index: u32,
+    ) -> Result<TimerHandle, i32>;
+
+    /// Takes the next expired timer and returns its handle and the index of the guest
+    /// function to call. A one-shot timer is freed once it is taken.
+    fn next_expired(&mut self) -> Option<(TimerHandle, u32)>;
+
+    /// The function table of the module, which holds the timer callbacks.
+    fn table(&self) -> Option<&TableRef>;
+
+    /// The stack timer callbacks run on, taken while a callback runs.
+    fn callback_stack(&mut self) -> &mut Option<StackRecycler>;
+
+    /// Calls a timer callback of the module, the function at `func_index` of its table.
+    fn invoke_callback(
+        &mut self,
+        _func_index: u32,
+        callback: &FuncRef,
+        args: &[RuntimeValue],
+        stack: &mut StackRecycler,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        FuncInstance::invoke_with_stack(callback, args, self, stack)
+    }
+
+    /// Tells the preemption and the fuel that the module gave up the core.
+    fn on_sleep(&mut self) {
+        if let Some(preemption) = self.preemption() {
+            preemption.on_sleep();
+        }
+        if let Some(fuel) = self.fuel() {
+            fuel.on_sleep();
+        }
+    }
+
+    /// Delays the module. Whole ticks are slept, so that other tasks can run in the
+    /// meantime, in slices of at most [`MAX_SLEEP`], and only the rest is busy waited. The
+    /// timers that expire while the module sleeps are dispatched when they expire.
+    fn delay(&mut self, duration: Duration) -> Result<(), Trap> {
+        let tick_us = Self::TICK_PERIOD.as_micros();
+        let max_ticks = (MAX_SLEEP.as_micros() / tick_us).max(1);
+        let deadline = self.now() + duration;
+        loop {
+            let remaining = deadline.saturating_sub(self.now());
+            // too short to give up the core, busy wait for the rest
+            if remaining < Self::TICK_PERIOD {
+                self.busy_wait(remaining);
+                if let Some(preemption) = self.preemption() {
+                    preemption.on_busy_wait(remaining);
+                }
+                return Ok(());
+            }
+
+            let ticks = (remaining.as_micros() / tick_us).min(max_ticks);
+            self.sleep(Self::TICK_PERIOD * ticks as u32);
+            self.on_sleep();
+            self.dispatch_timers()?;
+        }
+    }
+
+    /// Starts a timer that calls the guest function at `func_index` of the function table
+    /// after `period_us` microseconds, periodically for any `periodic` but zero. The
+    /// callback has to take the handle of the timer.
+    fn timer_start(
+        &mut self,
+        period_us: u64,
+        periodic: i32,
+        func_index: u32,
+    ) -> Result<TimerHandle, i32> {
+        let callback = match self.table().map(|table| table.get(func_index)) {
+            Some(Ok(Some(func))) => func,
+            _ => return Err(-1),
+        };
+        if callback.signature().params() != [ValueType::I32] {
+            return Err(-1);
+        }
+        let period = Duration::from_micros(period_us);
+        let periodic = periodic != 0;
+        if periodic && period < MIN_PERIOD {
+            return Err(ESP_ERR_INVALID_ARG);
+        }
+
+        self.create_timer(period, periodic, func_index)
+    }
+
+    /// Waits up to `timeout_us` microseconds for a timer to expire and dispatches all
+    /// expired timers to the guest. Without timers, there is nothing to wait for.
+    fn timer_poll(&mut self, timeout_us: u64) -> Result<(), Trap> {
+        if timeout_us > 0 && self.has_timers() {
+            self.sleep(Duration::from_micros(timeout_us));
+            self.on_sleep();
+        }
+        self.dispatch_timers()
+    }
+
+    /// Calls the guest callback of every expired timer. Does nothing if called from within
+    /// a callback, the remaining timers are dispatched once it returned.
+    fn dispatch_timers(&mut self) -> Result<(), Trap> {
+        let mut stack = match self.callback_stack().take() {
+            Some(stack) => stack,
+            None => return Ok(()),
+        };
+
+        let mut result = Ok(());
+        while let Some((handle, func_index)) = self.next_expired() {
+            let callback = match self.table().map(|table| table.get(func_index)) {
+                Some(Ok(Some(func))) => func,
+                _ => continue,
+            };
+
+            let args = [RuntimeValue::I32(handle as i32)];
+            if let Err(trap) = self.invoke_callback(func_index, &callback, &args, &mut stack) {
+                result = Err(trap);
+                break;
+            }
+        }
+
+        *self.callback_stack() = Some(stack);
+        result
+    }
+
+    /// Calls `op` with a timeout until it doesn't time out anymore, or until `timeout_us`
+    /// microseconds passed, `u64::MAX` waits forever. The wait is split up into slices of
+    /// at most [`MAX_SLEEP`], expired timers are dispatched in between.
+    fn wait_for_channel<T>(
+        &mut self,
+        timeout_us: u64,
+        mut op: impl FnMut(Duration) -> Result<T, ChannelError>,
+    ) -> Result<Result<T, ChannelError>, Trap> {
+        let deadline = match timeout_us {
+            u64::MAX => None,
+            timeout_us => Some(self.now() + Duration::from_micros(timeout_us)),
+        };
+
+        loop {
+            let remaining = deadline.map_or(Duration::MAX, |deadline| {
+                deadline.saturating_sub(self.now())
+            });
+            let slice = remaining.min(MAX_SLEEP);
+            let res = op(slice);
+            // a module that doesn't wait doesn't give up the core
+            if !matches!(res, Err(ChannelError::Timeout)) || slice.is_zero() {
+                return Ok(res);
+            }
+
+            self.blocked(slice);
+            self.on_sleep();
+            if slice == remaining {
+                return Ok(res);
+            }
+            self.dispatch_timers()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmi::RuntimeArgs;
+
+    /// Waits on a virtual clock and remembers how it waited, without timers.
+    #[derive(Default)]
+    struct Waits {
+        now: Duration,
+        sleeps: Vec<Duration>,
+        busy: Duration,
+        callback_stack: Option<StackRecycler>,
+    }
+
+    impl Externals for Waits {
+        fn invoke_index(
+            &mut self,
+            _index: usize,
+            _args: RuntimeArgs,
+        ) -> Result<Option<RuntimeValue>, Trap> {
+            unreachable!("the waits have no host functions")
+        }
+    }
+
+    impl Scheduler for Waits {
+        const TICK_PERIOD: Duration = Duration::from_millis(10);
+
+        fn now(&self) -> Duration {
+            self.now
+        }
+
+        fn busy_wait(&mut self, duration: Duration) {
+            self.now += duration;
+            self.busy += duration;
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.now += duration;
+            self.sleeps.push(duration);
+        }
+
+        fn blocked(&mut self, duration: Duration) {
+            self.sleep(duration);
+        }
+
+        fn preemption(&mut self) -> Option<&mut Preemption> {
+            None
+        }
+
+        fn fuel(&mut self) -> Option<&mut Fuel> {
+            None
+        }
+
+        fn has_timers(&self) -> bool {
+            false
+        }
+
+        fn create_timer(&mut self, _: Duration, _: bool, _: u32) -> Result<TimerHandle, i32> {
+            Err(-1)
+        }
+
+        fn next_expired(&mut self) -> Option<(TimerHandle, u32)> {
+            None
+        }
+
+        fn table(&self) -> Option<&TableRef> {
+            None
+        }
+
+        fn callback_stack(&mut self) -> &mut Option<StackRecycler> {
+            &mut self.callback_stack
+        }
+    }
+
+    #[test]
+    fn delays_sleep_whole_ticks_in_slices_and_busy_wait_the_rest() {
+        let mut waits = Waits::default();
+        waits.delay(Duration::from_micros(2_509_999)).unwrap();
+
+        let sleeps = [1000, 1000, 500].map(Duration::from_millis);
+        assert_eq!(waits.sleeps, sleeps);
+        assert_eq!(waits.busy, Duration::from_micros(9_999));
+        assert_eq!(waits.now, Duration::from_micros(2_509_999));
+    }
+
+    #[test]
+    fn channels_are_waited_for_in_slices() {
+        let mut waits = Waits::default();
+        let mut slices = Vec::new();
+        let res = waits.wait_for_channel(2_500_000, |slice| {
+            slices.push(slice);
+            Err::<(), _>(ChannelError::Timeout)
+        });
+
+        assert!(matches!(res, Ok(Err(ChannelError::Timeout))));
+        let expected = [1000, 1000, 500].map(Duration::from_millis);
+        assert_eq!(slices, expected);
+        assert_eq!(waits.sleeps, expected);
+    }
+
+    #[test]
+    fn a_channel_that_is_not_waited_for_is_tried_once() {
+        let mut waits = Waits::default();
+        let mut tries = 0;
+        let res = waits.wait_for_channel(0, |_| {
+            tries += 1;
+            Err::<(), _>(ChannelError::Timeout)
+        });
+
+        assert!(matches!(res, Ok(Err(ChannelError::Timeout))));
+        assert_eq!(tries, 1);
+        assert!(waits.sleeps.is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+/// The id of custom sections in the binary format.
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// Reads an unsigned LEB128 encoded `u32` and advances `bytes` past it.
+pub(crate) fn read_u32(bytes: &mut &[u8]) -> Option<u32> {
+    let mut value = 0_u32;
+    for shift in (0..35).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Splits `len` bytes off the front of `bytes`.
+pub(crate) fn split<'a>(bytes: &mut &'a [u8], len: u32) -> Option<&'a [u8]> {
+    if bytes.len() < len as usize {
+        return None;
+    }
+    let (head, rest) = bytes.split_at(len as usize);
+    *bytes = rest;
+
+    Some(head)
+}
+
+/// A section of a Wasm module, as found in the binary.
+pub(crate) struct RawSection<'a> {
+    pub(crate) id: u8,
+    /// The name of a custom section, empty for all other sections.
+    pub(crate) name: &'a str,
+    /// The payload, without the name of a custom section.
+    pub(crate) payload: &'a [u8],
+    /// The offset of the section in the module, including its header.
+    pub(crate) offset: usize,
+}
+
+/// Walks the sections of a Wasm module without parsing them. Cheaper than deserializing
+/// the whole module, if only the custom sections are of interest. Returns `None` if the
+/// module is malformed.
+pub(crate) fn sections(wasm: &[u8]) -> Option<Vec<RawSection<'_>>> {
+    // the magic number and version
+    let mut bytes = wasm.get(8..)?;
+    let mut sections = Vec::new();
+
+    while let Some((&id, rest)) = bytes.split_first() {
+        let offset = wasm.len() - bytes.len();
+        bytes = rest;
+        let len = read_u32(&mut bytes)?;
+        let mut payload = split(&mut bytes, len)?;
+
+        let mut name = "";
+        if id == CUSTOM_SECTION_ID {
+            let name_len = read_u32(&mut payload)?;
+            name = core::str::from_utf8(split(&mut payload, name_len)?).ok()?;
+        }
+
+        sections.push(RawSection {
+            id,
+            name,
+            payload,
+            offset,
+        });
+    }
+
+    Some(sections)
+}
+
+/// Returns true if the section is a custom section with the given name.
+pub(crate) fn is_custom(section: &RawSection<'_>, name: &str) -> bool {
+    section.id == CUSTOM_SECTION_ID && section.name == name
+}
+
+/// Returns the payload of the first custom section with the given name.
+pub(crate) fn custom_section<'a>(wasm: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    sections(wasm)?
+        .into_iter()
+        .find(|section| is_custom(section, name))
+        .map(|section| section.payload)
+}
@@ -0,0 +1,156 @@
+use ed25519_compact::{PublicKey, Signature};
+use log::warn;
+
+use crate::sections;
+
+/// The name of the custom section that holds the signature of a module. It has to be the
+/// last section and signs all bytes of the module that come before it.
+pub(crate) const SIGNATURE_SECTION: &str = "signature";
+
+/// What happens to modules without a signature. Modules with an invalid signature
+/// are always rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SignaturePolicy {
+    /// Unsigned modules are not loaded.
+    Reject,
+    /// Unsigned modules are loaded with a warning.
+    Warn,
+}
+
+/// Splits a module into the part that is covered by the signature and the signature
+/// itself. Returns `None` for the signature if the module is not signed.
+pub(crate) fn split_signature(wasm: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+    let sections = sections::sections(wasm).ok_or("the module is malformed")?;
+    // a signature anywhere else wouldn't cover the sections that follow it
+    if sections
+        .iter()
+        .rev()
+        .skip(1)
+        .any(|section| sections::is_custom(section, SIGNATURE_SECTION))
+    {
+        return Err("the signature is not the last section of the module".into());
+    }
+
+    match sections.last() {
+        Some(section) if sections::is_custom(section, SIGNATURE_SECTION) => {
+            Ok((&wasm[..section.offset], Some(section.payload)))
+        }
+        _ => Ok((wasm, None)),
+    }
+}
+
+/// Verifies the signature of a module against a raw Ed25519 public key, on the device the
+/// one compiled into the firmware. Has to be called before the module is parsed.
+pub(crate) fn verify(
+    name: &str,
+    wasm: &[u8],
+    policy: SignaturePolicy,
+    public_key: &[u8; PublicKey::BYTES],
+) -> Result<(), String> {
+    let (signed, signature) = match split_signature(wasm)? {
+        (signed, Some(signature)) => (signed, signature),
+        (_, None) if policy == SignaturePolicy::Warn => {
+            warn!("Module {} is not signed", name);
+            return Ok(());
+        }
+        (_, None) => return Err("the module is not signed".into()),
+    };
+
+    let public_key = PublicKey::new(*public_key);
+    let signature = Signature::from_slice(signature).map_err(|_| "the signature is malformed")?;
+    public_key
+        .verify(signed, &signature)
+        .map_err(|_| String::from("the signature of the module is invalid"))
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_compact::{KeyPair, Seed};
+
+    use super::*;
+
+    /// An empty module.
+    const MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn key_pair(seed: u8) -> KeyPair {
+        KeyPair::from_seed(Seed::new([seed; Seed::BYTES]))
+    }
+
+    /// Appends a custom section named `signature` with the payload.
+    fn append_signature(wasm: &mut Vec<u8>, payload: &[u8]) {
+        wasm.push(0);
+        wasm.push((1 + SIGNATURE_SECTION.len() + payload.len()) as u8);
+        wasm.push(SIGNATURE_SECTION.len() as u8);
+        wasm.extend_from_slice(SIGNATURE_SECTION.as_bytes());
+        wasm.extend_from_slice(payload);
+    }
+
+    fn signed(key_pair: &KeyPair) -> Vec<u8> {
+        let mut wasm = MODULE.to_vec();
+        append_signature(&mut wasm, key_pair.sk.sign(MODULE, None).as_ref());
+        wasm
+    }
+
+    #[test]
+    fn a_signed_module_is_verified() {
+        let key_pair = key_pair(1);
+        let wasm = signed(&key_pair);
+
+        assert_eq!(split_signature(&wasm).unwrap().0, MODULE);
+        assert_eq!(
+            verify("test", &wasm, SignaturePolicy::Reject, &key_pair.pk),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn a_signature_of_another_key_or_module_is_invalid() {
+        let wasm = signed(&key_pair(1));
+        let invalid = Err(String::from("the signature of the module is invalid"));
+        assert_eq!(
+            verify("test", &wasm, SignaturePolicy::Warn, &key_pair(2).pk),
+            invalid
+        );
+
+        // the version of the module is changed after it was signed
+        let mut tampered = wasm.clone();
+        tampered[4] = 2;
+        assert_eq!(
+            verify("test", &tampered, SignaturePolicy::Warn, &key_pair(1).pk),
+            invalid
+        );
+
+        let mut malformed = MODULE.to_vec();
+        append_signature(&mut malformed, b"short");
+        assert_eq!(
+            verify("test", &malformed, SignaturePolicy::Warn, &key_pair(1).pk),
+            Err(String::from("the signature is malformed"))
+        );
+    }
+
+    #[test]
+    fn unsigned_modules_depend_on_the_policy() {
+        let public_key = key_pair(1).pk;
+        assert_eq!(split_signature(MODULE), Ok((MODULE, None)));
+        assert_eq!(
+            verify("test", MODULE, SignaturePolicy::Reject, &public_key),
+            Err(String::from("the module is not signed"))
+        );
+        assert_eq!(
+            verify("test", MODULE, SignaturePolicy::Warn, &public_key),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn the_signature_has_to_be_the_last_section() {
+        let mut wasm = signed(&key_pair(1));
+        // an empty custom section after the signature
+        wasm.extend_from_slice(&[0, 2, 1, b'x']);
+        assert_eq!(
+            split_signature(&wasm).unwrap_err(),
+            "the signature is not the last section of the module"
+        );
+        assert!(split_signature(&wasm[..6]).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use wasmi::{StackRecycler, Trap, TrapKind, DEFAULT_CALL_STACK_LIMIT, DEFAULT_VALUE_STACK_LIMIT};
+
+use crate::manifest::Manifest;
+
+/// The limits of the stacks of the interpreter that runs a module. A module that exceeds
+/// either of them traps with a stack overflow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct StackLimits {
+    /// The size of the value stack in bytes, which holds the arguments, locals and operands
+    /// of all running functions. It is allocated in full once the module is started.
+    pub(crate) value_stack: usize,
+    /// The most functions that may run nested, each of them takes a frame of the call stack.
+    pub(crate) call_depth: usize,
+}
+
+impl StackLimits {
+    /// Lowers the limits to those in the `stack` line of the manifest, a module can't raise
+    /// them beyond the limits of the firmware.
+    pub(crate) fn lowered_by(self, manifest: &Manifest) -> Self {
+        match manifest.stack_limits() {
+            Some((value_stack, call_depth)) => Self {
+                value_stack: self.value_stack.min(value_stack as usize),
+                call_depth: self.call_depth.min(call_depth as usize),
+            },
+            None => self,
+        }
+    }
+
+    /// Creates the stacks of the interpreter. They are reused by every invocation. The
+    /// interpreter gets one more frame than the call depth, the shadow stack of a module
+    /// whose calls are tracked enforces it instead.
+    pub(crate) fn recycler(&self) -> StackRecycler {
+        StackRecycler::with_limits(self.value_stack, self.call_depth.saturating_add(1))
+    }
+
+    /// Describes which of the limits a module exceeded that overflowed its stack. The
+    /// interpreter traps the same way for both, only the shadow stack knows whether it was
+    /// the call depth. Without tracked calls, it is unknown which one it was.
+    pub(crate) fn exceeded(&self, shadow: &ShadowStack) -> String {
+        match (shadow.call_depth, shadow.call_depth_exceeded) {
+            (None, _) => format!(
+                "the value stack exceeded {} bytes or the call stack {} frames",
+                self.value_stack, self.call_depth
+            ),
+            (Some(call_depth), true) => format!("the call stack exceeded {} frames", call_depth),
+            (Some(_), false) => format!(
+                "the value stack exceeded {} bytes at a depth of {} frames",
+                self.value_stack,
+                shadow.frames.len()
+            ),
+        }
+    }
+}
+
+impl Default for StackLimits {
+    /// The limits of wasmi, which are far beyond the memory of the device.
+    fn default() -> Self {
+        Self {
+            value_stack: DEFAULT_VALUE_STACK_LIMIT,
+            call_depth: DEFAULT_CALL_STACK_LIMIT,
+        }
+    }
+}
+
+/// The functions a module is in, the innermost last, as the host sees them through the calls
+/// the instrumentation injects. It enforces the call depth of the module in place of the
+/// interpreter, so that it knows which limit a module that overflowed its stack exceeded.
+/// Timer callbacks run on stacks of their own, but their frames count towards the call
+/// depth as well.
+#[derive(Default)]
+pub(crate) struct ShadowStack {
+    frames: Vec<u32>,
+    // the call depth that is enforced, none if the calls of the module are not tracked
+    call_depth: Option<usize>,
+    // set once a function was entered beyond the call depth
+    call_depth_exceeded: bool,
+}
+
+impl ShadowStack {
+    /// Creates the shadow stack of a module, which enforces the call depth of `limits` if
+    /// the calls of the module are tracked.
+    pub(crate) fn new(limits: &StackLimits, tracked: bool) -> Self {
+        Self {
+            call_depth: tracked.then_some(limits.call_depth),
+            ..Default::default()
+        }
+    }
+
+    /// Enters the function with the given index. Traps with a stack overflow, like the
+    /// interpreter would, if the function exceeds the call depth.
+    pub(crate) fn enter(&mut self, func_index: u32) -> Result<(), Trap> {
+        // like in the interpreter, which can't call from its last frame: a function in it
+        // trapped once it called the host to enter
+        if matches!(self.call_depth, Some(call_depth) if self.frames.len() + 1 >= call_depth) {
+            self.call_depth_exceeded = true;
+            return Err(TrapKind::StackOverflow.into());
+        }
+        self.frames.push(func_index);
+        Ok(())
+    }
+
+    /// Leaves the innermost function.
+    pub(crate) fn leave(&mut self) {
+        self.frames.pop();
+    }
+
+    /// Returns the indices of the functions the module is in, the innermost last. After a
+    /// trap, these are the functions that were running when the module trapped.
+    pub(crate) fn frames(&self) -> &[u32] {
+        &self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: StackLimits = StackLimits {
+        value_stack: 1024,
+        call_depth: 3,
+    };
+
+    #[test]
+    fn the_shadow_stack_enforces_the_call_depth() {
+        let mut shadow = ShadowStack::new(&LIMITS, true);
+        shadow.enter(7).unwrap();
+        shadow.enter(8).unwrap();
+        assert_eq!(
+            LIMITS.exceeded(&shadow),
+            "the value stack exceeded 1024 bytes at a depth of 2 frames"
+        );
+
+        assert!(matches!(
+            shadow.enter(9).unwrap_err().kind(),
+            TrapKind::StackOverflow
+        ));
+        assert_eq!(shadow.frames(), [7, 8]);
+        assert_eq!(LIMITS.exceeded(&shadow), "the call stack exceeded 3 frames");
+    }
+
+    #[test]
+    fn without_tracked_calls_the_limit_is_unknown() {
+        let shadow = ShadowStack::new(&LIMITS, false);
+        assert_eq!(
+            LIMITS.exceeded(&shadow),
+            "the value stack exceeded 1024 bytes or the call stack 3 frames"
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use core::fmt;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::imports;
+
+/// The most spans a module can mark with different ids, so that a module that marks
+/// new ids all the time can't exhaust the heap. Marks of further ids are only counted.
+pub(crate) const MAX_SPANS: usize = 64;
+
+/// How often something happened and how long it took.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Counter {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Counter {
+    fn add(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"count\":{},\"total_us\":{},\"max_us\":{}}}",
+            self.count,
+            self.total.as_micros(),
+            self.max.as_micros()
+        )
+    }
+}
+
+impl fmt::Display for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "count {}, total {:?}, max {:?}",
+            self.count, self.total, self.max
+        )
+    }
+}
+
+/// A call into the host that is being measured.
+pub(crate) struct HostCall {
+    started: Instant,
+    callbacks: Duration,
+}
+
+/// An invocation of the module that is being measured.
+pub(crate) struct Invocation {
+    started: Instant,
+    host: Duration,
+}
+
+/// Where the time of a module goes: the calls into each host function, the time spent in
+/// the interpreter per invocation and the spans the module marked with `profile_mark`.
+/// Also how large its memory got, which grows with its heap, and how deep its calls nested.
+#[derive(Default)]
+pub(crate) struct Stats {
+    host: BTreeMap<usize, Counter>,
+    // the time of all host calls, without the callbacks into the module they dispatched
+    host_total: Duration,
+    // the time spent in callbacks into the module, which is not part of the host calls
+    callbacks: Duration,
+    interpreter: Counter,
+    spans: BTreeMap<u32, Counter>,
+    open_spans: HashMap<u32, Instant>,
+    // the marks of spans beyond MAX_SPANS
+    dropped_marks: u64,
+    // the largest size of the memory in bytes
+    heap_watermark: usize,
+    failed_grows: u64,
+    // the most functions that ran nested
+    call_depth: usize,
+}
+
+impl Stats {
+    pub(crate) fn begin_host_call(&self) -> HostCall {
+        HostCall {
+            started: Instant::now(),
+            callbacks: self.callbacks,
+        }
+    }
+
+    /// Counts a call into the host function with the given index. The callbacks into the
+    /// module that ran during the call are not counted as time of the host.
+    pub(crate) fn end_host_call(&mut self, index: usize, call: HostCall) {
+        let duration = call
+            .started
+            .elapsed()
+            .saturating_sub(self.callbacks - call.callbacks);
+        self.host.entry(index).or_default().add(duration);
+        self.host_total += duration;
+    }
+
+    /// Records a callback into the module that ran for `duration`.
+    pub(crate) fn add_callback(&mut self, duration: Duration) {
+        self.callbacks += duration;
+    }
+
+    pub(crate) fn begin_invocation(&self) -> Invocation {
+        Invocation {
+            started: Instant::now(),
+            host: self.host_total,
+        }
+    }
+
+    /// Counts the time of the invocation that was not spent in the host as time of the
+    /// interpreter.
+    pub(crate) fn end_invocation(&mut self, invocation: Invocation) {
+        let host = self.host_total - invocation.host;
+        self.interpreter
+            .add(invocation.started.elapsed().saturating_sub(host));
+    }
+
+    /// Starts the span with the given id, or ends it if it was started before. A span
+    /// with a new id isn't started once [`MAX_SPANS`] ids are known or open, its marks
+    /// are counted as dropped.
+    pub(crate) fn mark(&mut self, id: u32) {
+        if let Some(started) = self.open_spans.remove(&id) {
+            self.spans.entry(id).or_default().add(started.elapsed());
+            return;
+        }
+
+        let known = self.spans.len()
+            + self
+                .open_spans
+                .keys()
+                .filter(|id| !self.spans.contains_key(id))
+                .count();
+        if !self.spans.contains_key(&id) && known >= MAX_SPANS {
+            self.dropped_marks += 1;
+            return;
+        }
+        self.open_spans.insert(id, Instant::now());
+    }
+
+    /// Records the size of the memory in bytes, after it was created or grown.
+    pub(crate) fn memory_size(&mut self, bytes: usize) {
+        self.heap_watermark = self.heap_watermark.max(bytes);
+    }
+
+    /// Counts a `memory.grow` that failed.
+    pub(crate) fn failed_grow(&mut self) {
+        self.failed_grows += 1;
+    }
+
+    /// Records the number of functions that are running nested, after one was entered.
+    pub(crate) fn call_depth(&mut self, depth: usize) {
+        self.call_depth = self.call_depth.max(depth);
+    }
+
+    /// Returns the stats as a JSON object, with all durations in microseconds.
+    #[allow(dead_code)] // used by tools/simulator
+    pub(crate) fn to_json(&self) -> String {
+        let host: Vec<String> = self
+            .host
+            .iter()
+            .map(|(&index, counter)| {
+                format!("\"{}\":{}", imports::name_of(index), counter.to_json())
+            })
+            .collect();
+        let spans: Vec<String> = self
+            .spans
+            .iter()
+            .map(|(id, counter)| format!("\"{}\":{}", id, counter.to_json()))
+            .collect();
+
+        let memory = format!(
+            "{{\"heap_watermark\":{},\"failed_grows\":{}}}",
+            self.heap_watermark, self.failed_grows
+        );
+        let stack = format!("{{\"call_depth\":{}}}", self.call_depth);
+
+        format!(
+            "{{\"interpreter\":{},\"host\":{{{}}},\"spans\":{{{}}},\"dropped_marks\":{},\"memory\":{},\"stack\":{}}}",
+            self.interpreter.to_json(),
+            host.join(","),
+            spans.join(","),
+            self.dropped_marks,
+            memory,
+            stack
+        )
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interpreter: {}", self.interpreter)?;
+        write!(
+            f,
+            "\n  memory: heap watermark {} bytes, {} failed grows",
+            self.heap_watermark, self.failed_grows
+        )?;
+        write!(
+            f,
+            "\n  stack: call depth watermark {} frames",
+            self.call_depth
+        )?;
+        for (&index, counter) in &self.host {
+            write!(f, "\n  {}: {}", imports::name_of(index), counter)?;
+        }
+        for (id, counter) in &self.spans {
+            write!(f, "\n  span {}: {}", id, counter)?;
+        }
+        if self.dropped_marks > 0 {
+            write!(
+                f,
+                "\n  {} marks of spans beyond the first {} were dropped",
+                self.dropped_marks, MAX_SPANS
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_beyond_the_limit_are_dropped() {
+        let mut stats = Stats::default();
+        let ids = MAX_SPANS as u32;
+        // spans that are never ended
+        for id in 0..ids + 10 {
+            stats.mark(id);
+        }
+        assert_eq!(stats.open_spans.len(), MAX_SPANS);
+        assert_eq!(stats.dropped_marks, 10);
+
+        // the known spans still end and start again
+        for id in 0..ids {
+            stats.mark(id);
+            stats.mark(id);
+        }
+        assert_eq!(stats.spans.len(), MAX_SPANS);
+        assert_eq!(stats.open_spans.len(), MAX_SPANS);
+        stats.mark(ids);
+        assert_eq!(stats.dropped_marks, 11);
+        assert!(stats.to_json().contains("\"dropped_marks\":11"));
+    }
+}
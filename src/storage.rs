@@ -0,0 +1,165 @@
+use core::ffi::c_void;
+use std::ffi::{CStr, CString};
+
+use esp_idf_sys::{
+    esp, nvs_close, nvs_commit, nvs_entry_find, nvs_entry_info, nvs_entry_info_t, nvs_entry_next,
+    nvs_erase_key, nvs_flash_erase, nvs_flash_init, nvs_get_blob, nvs_handle_t, nvs_open,
+    nvs_open_mode_t_NVS_READWRITE, nvs_release_iterator, nvs_set_blob, nvs_type_t_NVS_TYPE_BLOB,
+    EspError, ESP_ERR_NVS_INVALID_NAME, ESP_ERR_NVS_NEW_VERSION_FOUND, ESP_ERR_NVS_NOT_FOUND,
+    ESP_ERR_NVS_NO_FREE_PAGES,
+};
+
+/// The NVS partition used for guest storage.
+const PARTITION: &[u8] = b"nvs\0";
+
+/// Initializes the default NVS partition. Must be called once before any
+/// [`Storage`] is opened. An outdated or full partition gets erased.
+pub(crate) fn init() -> Result<(), EspError> {
+    let res = unsafe { nvs_flash_init() };
+    if res == ESP_ERR_NVS_NO_FREE_PAGES as i32 || res == ESP_ERR_NVS_NEW_VERSION_FOUND as i32 {
+        esp!(unsafe { nvs_flash_erase() })?;
+        return esp!(unsafe { nvs_flash_init() });
+    }
+
+    esp!(res)
+}
+
+/// Derives the NVS namespace of a module from its name. Namespaces are limited to
+/// 15 characters, so the name is hashed (32 bit FNV-1a) instead of being used directly.
+pub(crate) fn namespace_of(module_name: &str) -> CString {
+    let hash = module_name.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+
+    CString::new(format!("wasm{:08x}", hash)).unwrap()
+}
+
+/// Converts a key into a C string, NVS keys can't contain a NUL byte.
+fn to_key(key: &str) -> Result<CString, EspError> {
+    CString::new(key).map_err(|_| EspError::from(ESP_ERR_NVS_INVALID_NAME as i32).unwrap())
+}
+
+/// Persistent key-value storage of a single module, backed by an NVS namespace
+/// that is derived from the module's name. Values are stored as blobs.
+pub(crate) struct Storage {
+    namespace: CString,
+    handle: nvs_handle_t,
+}
+
+impl Storage {
+    /// Opens the storage of the module with the given name.
+    pub(crate) fn open(module_name: &str) -> Result<Self, EspError> {
+        Self::open_namespace(namespace_of(module_name))
+    }
+
+    /// Opens a namespace of the firmware itself. The namespaces of modules all start
+    /// with `wasm`, so other names can't clash with them.
+    pub(crate) fn open_system(namespace: &str) -> Result<Self, EspError> {
+        Self::open_namespace(to_key(namespace)?)
+    }
+
+    fn open_namespace(namespace: CString) -> Result<Self, EspError> {
+        let mut handle: nvs_handle_t = 0;
+        esp!(unsafe {
+            nvs_open(
+                namespace.as_ptr(),
+                nvs_open_mode_t_NVS_READWRITE,
+                &mut handle as *mut _,
+            )
+        })?;
+
+        Ok(Self { namespace, handle })
+    }
+
+    /// Reads the value stored under `key`, returns `None` if there is none.
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Vec<u8>>, EspError> {
+        let key = to_key(key)?;
+
+        // query the length first
+        let mut len = 0;
+        let res = unsafe {
+            nvs_get_blob(
+                self.handle,
+                key.as_ptr(),
+                core::ptr::null_mut(),
+                &mut len as *mut _,
+            )
+        };
+        if res == ESP_ERR_NVS_NOT_FOUND as i32 {
+            return Ok(None);
+        }
+        esp!(res)?;
+
+        let mut value = vec![0_u8; len as usize];
+        esp!(unsafe {
+            nvs_get_blob(
+                self.handle,
+                key.as_ptr(),
+                value.as_mut_ptr() as *mut c_void,
+                &mut len as *mut _,
+            )
+        })?;
+
+        Ok(Some(value))
+    }
+
+    /// Stores `value` under `key` and commits it to flash.
+    pub(crate) fn set(&mut self, key: &str, value: &[u8]) -> Result<(), EspError> {
+        let key = to_key(key)?;
+        esp!(unsafe {
+            nvs_set_blob(
+                self.handle,
+                key.as_ptr(),
+                value.as_ptr() as *const c_void,
+                value.len() as _,
+            )
+        })?;
+
+        esp!(unsafe { nvs_commit(self.handle) })
+    }
+
+    /// Removes `key`. Returns false if there was no such key.
+    pub(crate) fn delete(&mut self, key: &str) -> Result<bool, EspError> {
+        let key = to_key(key)?;
+        let res = unsafe { nvs_erase_key(self.handle, key.as_ptr()) };
+        if res == ESP_ERR_NVS_NOT_FOUND as i32 {
+            return Ok(false);
+        }
+        esp!(res)?;
+
+        esp!(unsafe { nvs_commit(self.handle) })?;
+        Ok(true)
+    }
+
+    /// Returns all keys of this module.
+    pub(crate) fn keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+        let mut iter = unsafe {
+            nvs_entry_find(
+                PARTITION.as_ptr() as *const _,
+                self.namespace.as_ptr(),
+                nvs_type_t_NVS_TYPE_BLOB,
+            )
+        };
+
+        while !iter.is_null() {
+            let mut info: nvs_entry_info_t = unsafe { core::mem::zeroed() };
+            unsafe { nvs_entry_info(iter, &mut info as *mut _) };
+
+            let key = unsafe { CStr::from_ptr(info.key.as_ptr()) };
+            keys.push(key.to_string_lossy().into_owned());
+
+            // `nvs_entry_next` releases the iterator once the end is reached
+            iter = unsafe { nvs_entry_next(iter) };
+        }
+        unsafe { nvs_release_iterator(iter) };
+
+        keys
+    }
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        unsafe { nvs_close(self.handle) };
+    }
+}
@@ -0,0 +1,530 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{error, info};
+use wasmi::{ImportsBuilder, ModuleInstance, ModuleRef, RuntimeValue, StackRecycler, TrapKind};
+
+use crate::bytes::MODULE_SIGNING_KEY;
+use crate::console::ConsoleTransport;
+use crate::debugger::Debugger;
+use crate::dump::{self, Symbols};
+use crate::entry::{self, Call, Entry, Failure, Invoker};
+use crate::image;
+use crate::imports::{InstrumentationResolver, UartModuleImportResolver};
+use crate::logging;
+use crate::manifest::Manifest;
+use crate::memory::{self, DeclaredMemory, HostMemory, MemoryKind};
+use crate::metering::{self, FuelConfig, Instrumentation, INSTRUMENTATION};
+use crate::preemption::{PreemptionConfig, YieldHook};
+use crate::profiler::{self, Profile, ProfileConfig};
+use crate::restart::{CrashCounter, RestartPolicy};
+use crate::runtime::Runtime;
+use crate::signature::{self, SignaturePolicy};
+use crate::stack::{ShadowStack, StackLimits};
+use crate::stats::Stats;
+use crate::storage::Storage;
+use crate::trace::ConsoleSink;
+use crate::trap::TrapReport;
+
+/// The size of the FreeRTOS task that runs a module. The stacks of the interpreter
+/// are allocated on the heap, this only needs to fit the host side.
+const TASK_STACK_SIZE: usize = 16 * 1024;
+
+/// Describes a module that is run by the [`Supervisor`].
+pub(crate) struct ModuleSpec {
+    /// The name of the module, also used for its task and storage.
+    pub(crate) name: &'static str,
+    /// The image of the module, either a plain or a compressed Wasm module.
+    pub(crate) image: &'static [u8],
+    /// The manifest of the module, if it is not embedded into the module itself.
+    pub(crate) manifest: Option<&'static str>,
+    /// The exports the host calls to run the module, e.g. [`entry::START`].
+    pub(crate) entry: Entry,
+    /// The most bytes of memory the module may have, lowered by the `memory` line of its
+    /// manifest. A module that needs more from the start is rejected, `memory.grow` fails
+    /// beyond it.
+    pub(crate) max_memory: u32,
+    /// The limits of the value and call stack of the module, lowered by the `stack` line of
+    /// its manifest. The value stack is allocated in full on the heap when the module starts.
+    pub(crate) stack: StackLimits,
+    pub(crate) fuel: FuelConfig,
+    pub(crate) preemption: PreemptionConfig,
+    /// Samples the call stack of the module, to find the functions it spends its time in.
+    pub(crate) profile: Option<ProfileConfig>,
+    /// What happens once the module failed.
+    pub(crate) restart: RestartPolicy,
+    /// Attaches the debugger to the module, over the console. The module halts on the first
+    /// function it enters, until a client continues it.
+    pub(crate) debug: bool,
+    /// Logs the globals, data segments and top of the stack of the module once it trapped.
+    pub(crate) dump_on_trap: bool,
+    /// Prints a trace of all calls into the host to the console, to replay them with
+    /// `tools/simulator`.
+    pub(crate) trace: bool,
+}
+
+/// The status of a module run by the [`Supervisor`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ModuleStatus {
+    /// The module is being loaded and instantiated.
+    Starting,
+    /// The entry point of the module is running.
+    Running,
+    /// The entry point of the module returned without an error code.
+    Finished,
+    /// The module failed and is restarted after a delay.
+    Restarting { restarts: u32, reason: String },
+    /// The module couldn't be started, trapped or returned an error code.
+    Failed(String),
+}
+
+impl ModuleStatus {
+    /// Returns true if the module doesn't run anymore.
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self, ModuleStatus::Finished | ModuleStatus::Failed(_))
+    }
+}
+
+/// A module running in its own task.
+struct ModuleTask {
+    name: &'static str,
+    status: Arc<Mutex<ModuleStatus>>,
+    stats: Arc<Mutex<Stats>>,
+    profile: Arc<Mutex<Profile>>,
+    _handle: JoinHandle<()>,
+}
+
+/// The module that takes over once another module crashed too often in a row.
+struct SafeMode {
+    spec: &'static ModuleSpec,
+    /// The number of crashes in a row after which a module is replaced.
+    crashes: u32,
+    /// Set once the safe mode module was started, it is only started once.
+    started: AtomicBool,
+}
+
+/// The state shared between the supervisor and the tasks of the modules.
+#[derive(Clone)]
+struct Shared {
+    tasks: Arc<Mutex<Vec<ModuleTask>>>,
+    signatures: SignaturePolicy,
+    crashes: Arc<Mutex<CrashCounter>>,
+    safe_mode: Option<Arc<SafeMode>>,
+}
+
+/// Runs each module in its own FreeRTOS task, with its own runtime. Shared peripherals are
+/// arbitrated between the runtimes, a pin or UART that is used by one module can't be
+/// initialized by another. Failed modules are restarted according to their
+/// [`RestartPolicy`], with a fresh runtime that releases everything the module held.
+pub(crate) struct Supervisor {
+    shared: Shared,
+}
+
+impl Supervisor {
+    /// Creates a supervisor that verifies the signature of every module with the given policy.
+    /// NVS has to be initialized before, it holds the crash counters of the modules.
+    pub(crate) fn new(signatures: SignaturePolicy) -> Self {
+        Self {
+            shared: Shared {
+                tasks: Default::default(),
+                signatures,
+                crashes: Arc::new(Mutex::new(CrashCounter::open())),
+                safe_mode: None,
+            },
+        }
+    }
+
+    /// Replaces a module that crashed `crashes` times in a row with the safe mode module,
+    /// also across reboots. The safe mode module is started at most once.
+    pub(crate) fn with_safe_mode(mut self, spec: &'static ModuleSpec, crashes: u32) -> Self {
+        self.shared.crashes.lock().unwrap().persist_up_to(crashes);
+        self.shared.safe_mode = Some(Arc::new(SafeMode {
+            spec,
+            crashes,
+            started: AtomicBool::new(false),
+        }));
+        self
+    }
+
+    /// Starts a new task that runs the module from its entry point.
+    pub(crate) fn spawn(&mut self, spec: &'static ModuleSpec) -> std::io::Result<()> {
+        spawn_task(spec, self.shared.clone())
+    }
+
+    /// Returns the name and status of every module.
+    pub(crate) fn status(&self) -> Vec<(&'static str, ModuleStatus)> {
+        self.shared
+            .tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|task| (task.name, task.status.lock().unwrap().clone()))
+            .collect()
+    }
+
+    /// Resets the crash counters of the modules that run stably, needs to be called regularly.
+    pub(crate) fn reset_stable_crashes(&self) {
+        self.shared.crashes.lock().unwrap().reset_stable();
+    }
+
+    /// Logs the status of every module.
+    pub(crate) fn log_status(&self) {
+        for (name, status) in self.status() {
+            info!("Module {}: {:?}", name, status);
+        }
+    }
+
+    /// Logs where the time of every module went, since it was first started.
+    pub(crate) fn dump_stats(&self) {
+        for task in self.shared.tasks.lock().unwrap().iter() {
+            info!(
+                "Stats of module {}: {}",
+                task.name,
+                task.stats.lock().unwrap()
+            );
+        }
+    }
+
+    /// Prints the sampled call stacks of every profiled module to the console, since it was
+    /// first started.
+    pub(crate) fn dump_profiles(&self) {
+        for task in self.shared.tasks.lock().unwrap().iter() {
+            let profile = task.profile.lock().unwrap();
+            if !profile.is_empty() {
+                profiler::print(task.name, &profile);
+            }
+        }
+    }
+
+    /// Returns true if none of the modules runs anymore.
+    pub(crate) fn all_done(&self) -> bool {
+        self.status().iter().all(|(_, status)| status.is_done())
+    }
+}
+
+/// Starts a new task that runs the module and restarts it if necessary.
+fn spawn_task(spec: &'static ModuleSpec, shared: Shared) -> std::io::Result<()> {
+    let status = Arc::new(Mutex::new(ModuleStatus::Starting));
+    let task_status = status.clone();
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let task_stats = stats.clone();
+    let profile = Arc::new(Mutex::new(Profile::default()));
+    let task_profile = profile.clone();
+    let tasks = shared.tasks.clone();
+
+    let handle = thread::Builder::new()
+        .name(spec.name.into())
+        .stack_size(TASK_STACK_SIZE)
+        .spawn(move || supervise(spec, &shared, &task_status, &task_stats, &task_profile))?;
+
+    tasks.lock().unwrap().push(ModuleTask {
+        name: spec.name,
+        status,
+        stats,
+        profile,
+        _handle: handle,
+    });
+    Ok(())
+}
+
+/// Runs the module until it finished, restarting it according to its policy. Hands over
+/// to the safe mode module once the module crashed too often in a row.
+fn supervise(
+    spec: &'static ModuleSpec,
+    shared: &Shared,
+    status: &Mutex<ModuleStatus>,
+    stats: &Arc<Mutex<Stats>>,
+    profile: &Arc<Mutex<Profile>>,
+) {
+    let mut restarts = 0;
+    loop {
+        let crashes = shared.crashes.lock().unwrap().get(spec.name, spec.image);
+        if let Some(safe_mode) = shared
+            .safe_mode
+            .as_ref()
+            .filter(|safe_mode| crashes >= safe_mode.crashes)
+        {
+            error!(
+                "Module {} crashed {} times in a row, switching to safe mode",
+                spec.name, crashes
+            );
+            enter_safe_mode(safe_mode, shared);
+            *status.lock().unwrap() = ModuleStatus::Failed(format!(
+                "replaced by the safe mode after {} crashes",
+                crashes
+            ));
+            return;
+        }
+
+        shared
+            .crashes
+            .lock()
+            .unwrap()
+            .started(spec.name, spec.image);
+        let result = run_module(spec, shared.signatures, status, stats, profile);
+        shared
+            .crashes
+            .lock()
+            .unwrap()
+            .stopped(spec.name, result.is_err());
+
+        let reason = match result {
+            Ok(()) => {
+                *status.lock().unwrap() = ModuleStatus::Finished;
+                return;
+            }
+            Err(reason) => reason,
+        };
+        error!("Module {} failed: {}", spec.name, reason);
+
+        match spec.restart.delay(restarts) {
+            Some(delay) => {
+                info!("Restarting module {} in {:?}", spec.name, delay);
+                restarts += 1;
+                *status.lock().unwrap() = ModuleStatus::Restarting { restarts, reason };
+                thread::sleep(delay);
+            }
+            None => {
+                *status.lock().unwrap() = ModuleStatus::Failed(reason);
+                return;
+            }
+        }
+    }
+}
+
+/// Starts the safe mode module, unless it was started already. It runs without a
+/// safe mode itself, so that a crashing safe mode module can't replace itself.
+fn enter_safe_mode(safe_mode: &SafeMode, shared: &Shared) {
+    if safe_mode.started.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let shared = Shared {
+        safe_mode: None,
+        ..shared.clone()
+    };
+    if let Err(err) = spawn_task(safe_mode.spec, shared) {
+        error!("Could not start the safe mode module: {}", err);
+    }
+}
+
+/// Loads, instantiates and runs a module. Errors are returned instead of panicking, a
+/// panic would abort the whole device and with it all other modules.
+fn run_module(
+    spec: &ModuleSpec,
+    signatures: SignaturePolicy,
+    status: &Mutex<ModuleStatus>,
+    stats: &Arc<Mutex<Stats>>,
+    profile: &Arc<Mutex<Profile>>,
+) -> Result<(), String> {
+    let wasm = image::decompress(spec.image)?;
+    // nothing of the module is looked at before it is verified, not even its manifest
+    signature::verify(spec.name, &wasm, signatures, MODULE_SIGNING_KEY)?;
+
+    let manifest = match spec.manifest {
+        Some(text) => Manifest::parse(text)?,
+        None => Manifest::from_wasm(&wasm)?.unwrap_or_else(|| {
+            info!(
+                "Module {} has no manifest, it is granted nothing",
+                spec.name
+            );
+            Manifest::default()
+        }),
+    };
+
+    // fuel is always charged, the calls only for the lowered stack limits, to tell which of them
+    // the module exceeded, the profiler and the debugger
+    let stack = spec.stack.lowered_by(&manifest);
+    let instrumentation = Instrumentation {
+        fuel: true,
+        calls: spec.profile.is_some() || spec.debug || stack != StackLimits::default(),
+    };
+    let (module, names) =
+        metering::load_instrumented(&wasm, instrumentation).map_err(|err| err.to_string())?;
+    info!("Module {} loaded successfully!", spec.name);
+
+    // the maximum the module declares for its memory is not trusted, only the limit of the host
+    let declared = DeclaredMemory::of(&wasm)?;
+    let memory_limit = memory::limit(Some(spec.max_memory), &manifest);
+    declared.check(memory_limit)?;
+    if declared.exceeds(memory_limit) {
+        info!(
+            "Module {} declares a maximum of {:?} pages of memory, it is limited to {} pages",
+            spec.name,
+            declared.maximum.map(|pages| pages.0),
+            memory_limit.0
+        );
+    }
+
+    // instantiate a module and pass it the import resolver
+    let host_memory = HostMemory::new(memory_limit);
+    let resolver = UartModuleImportResolver::new(&manifest, &host_memory);
+    let instance = ModuleInstance::new(
+        &module,
+        &ImportsBuilder::new()
+            .with_resolver("env", &resolver)
+            .with_resolver(INSTRUMENTATION, &InstrumentationResolver),
+    )
+    .map_err(|err| err.to_string())?
+    .assert_no_start();
+
+    // fetch the memory of the module (needed for the write and read buffer)
+    let memory = memory::find(&instance, &declared, &host_memory);
+    if let MemoryKind::None = declared.kind {
+        info!(
+            "Module {} has no memory, host functions can't access it",
+            spec.name
+        );
+    }
+
+    // the function table is optional and only needed for callbacks into the module
+    let table = instance
+        .export_by_name("__indirect_function_table")
+        .and_then(|export| export.as_table().cloned());
+
+    // the yield hook subscribes the task to the watchdog, so it has to be created in this task
+    let mut runtime = Runtime::new(memory.as_ref(), table)
+        .with_name(spec.name)
+        .with_fuel(spec.fuel)
+        .with_memory_limit(memory_limit)
+        .with_stats(stats.clone())
+        .with_call_stack(ShadowStack::new(&stack, instrumentation.calls))
+        .with_preemption(spec.preemption, Box::new(FreeRtosYield::new()));
+    if manifest.allows_storage() {
+        match Storage::open(spec.name) {
+            Ok(storage) => runtime = runtime.with_storage(storage),
+            Err(err) => info!(
+                "Could not open the storage of module {}: {}",
+                spec.name, err
+            ),
+        }
+    }
+    if let Some(config) = spec.profile {
+        profile.lock().unwrap().set_names(names.clone());
+        runtime = runtime.with_profiler(config, profile.clone());
+    }
+    let symbols = Symbols::from_wasm(&wasm);
+    if spec.debug {
+        match ConsoleTransport::open() {
+            Ok(transport) => {
+                let debugger = Debugger::new(
+                    Box::new(transport),
+                    instance.clone(),
+                    memory.clone(),
+                    names.clone(),
+                    symbols.clone(),
+                )
+                .with_logs(logging::buffered);
+                runtime = runtime.with_debugger(debugger);
+            }
+            Err(err) => info!(
+                "Could not attach the debugger to module {}: {}",
+                spec.name, err
+            ),
+        }
+    }
+    if spec.trace {
+        runtime = runtime.with_trace(Box::new(ConsoleSink::new(spec.name)));
+    }
+    let mut runtime = runtime.with_manifest(manifest);
+    let mut stack_rec = stack.recycler();
+    spec.entry.check(&instance)?;
+
+    info!("Calling {} of module {}!", spec.entry, spec.name);
+    *status.lock().unwrap() = ModuleStatus::Running;
+    let mut invoker = DeviceInvoker {
+        instance: &instance,
+        runtime: &mut runtime,
+        stack: &mut stack_rec,
+        stats,
+    };
+    match entry::run(&spec.entry, &mut invoker) {
+        Ok(result) => {
+            if let Some(value) = result {
+                info!("Module {} returned {}", spec.name, entry::describe(&value));
+            }
+            Ok(())
+        }
+        Err(Failure::Error(wasmi::Error::Trap(trap))) => {
+            let mut report = TrapReport::new(&trap, runtime.call_stack().frames(), &names);
+            if let TrapKind::StackOverflow = trap.kind() {
+                report = report.with_cause(&stack.exceeded(runtime.call_stack()));
+            }
+            runtime.record_trap(&trap);
+            runtime.debug_trap(&report.summary());
+            error!("Module {} trapped: {}", spec.name, report);
+            if spec.dump_on_trap {
+                info!(
+                    "Memory of module {}: {}",
+                    spec.name,
+                    dump::report(&instance, memory.as_ref(), &symbols)
+                );
+            }
+            Err(report.summary())
+        }
+        Err(failure) => Err(failure.to_string()),
+    }
+}
+
+/// Yields to FreeRTOS and feeds the task watchdog. Subscribes the current task to the
+/// task watchdog while it exists, so it has to be created and dropped in the task that
+/// runs the module.
+struct FreeRtosYield;
+
+impl FreeRtosYield {
+    fn new() -> Self {
+        unsafe { esp_idf_sys::esp_task_wdt_add(core::ptr::null_mut()) };
+        Self
+    }
+}
+
+impl YieldHook for FreeRtosYield {
+    fn yield_now(&mut self) {
+        unsafe {
+            esp_idf_sys::esp_task_wdt_reset();
+            // a delay of one tick also lets lower priority tasks, like the idle task, run
+            esp_idf_sys::vTaskDelay(1);
+        }
+    }
+
+    fn slept(&mut self) {
+        unsafe { esp_idf_sys::esp_task_wdt_reset() };
+    }
+}
+
+impl Drop for FreeRtosYield {
+    fn drop(&mut self) {
+        unsafe { esp_idf_sys::esp_task_wdt_delete(core::ptr::null_mut()) };
+    }
+}
+
+/// Calls the exports of a module on the device, each with a full tank of fuel.
+struct DeviceInvoker<'r, 'a> {
+    instance: &'r ModuleRef,
+    runtime: &'r mut Runtime<'a>,
+    stack: &'r mut StackRecycler,
+    stats: &'r Mutex<Stats>,
+}
+
+impl Invoker for DeviceInvoker<'_, '_> {
+    fn invoke(&mut self, call: &Call) -> Result<Option<RuntimeValue>, wasmi::Error> {
+        self.runtime.refuel();
+        let invocation = self.stats.lock().unwrap().begin_invocation();
+        let result = self.instance.invoke_export_with_stack(
+            &call.export,
+            &call.args,
+            self.runtime,
+            self.stack,
+        );
+        self.stats.lock().unwrap().end_invocation(invocation);
+        result
+    }
+
+    fn pause(&mut self, interval: Duration) -> bool {
+        self.runtime.record_pause();
+        thread::sleep(interval);
+        true
+    }
+}
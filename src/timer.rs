@@ -0,0 +1,183 @@
+use core::ffi::c_void;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use esp_idf_sys::{
+    esp, esp_timer_create, esp_timer_create_args_t, esp_timer_delete,
+    esp_timer_dispatch_t_ESP_TIMER_TASK, esp_timer_handle_t, esp_timer_start_once,
+    esp_timer_start_periodic, esp_timer_stop, EspError,
+};
+
+use crate::scheduler::TimerHandle;
+
+/// The maximum number of timers a single runtime can hold at once.
+pub(crate) const MAX_TIMERS: u32 = 8;
+
+/// Tells the timers apart that had the same handle, handles are reused once a timer is freed.
+type TimerId = u64;
+
+/// Queue of timers that fired but have not been dispatched to the guest yet,
+/// identified by their handle and id. Filled from the `esp_timer` task, drained
+/// by the runtime.
+#[derive(Default)]
+struct EventQueue {
+    fired: Mutex<VecDeque<(TimerHandle, TimerId)>>,
+    signal: Condvar,
+}
+
+impl EventQueue {
+    fn push(&self, event: (TimerHandle, TimerId)) {
+        self.fired.lock().unwrap().push_back(event);
+        self.signal.notify_one();
+    }
+
+    fn pop(&self) -> Option<(TimerHandle, TimerId)> {
+        self.fired.lock().unwrap().pop_front()
+    }
+
+    /// Blocks until an event is queued or the timeout passed.
+    fn wait(&self, timeout: Duration) {
+        let fired = self.fired.lock().unwrap();
+        if fired.is_empty() {
+            let _ = self.signal.wait_timeout(fired, timeout).unwrap();
+        }
+    }
+}
+
+/// Data handed to the `esp_timer` callback. Lives in a `Box` that is owned by
+/// the corresponding [`Timer`] and outlives the underlying `esp_timer`.
+struct TimerContext {
+    handle: TimerHandle,
+    id: TimerId,
+    events: Arc<EventQueue>,
+}
+
+/// Called by the `esp_timer` task whenever a timer expires.
+unsafe extern "C" fn on_timer_expired(arg: *mut c_void) {
+    let context = &*(arg as *const TimerContext);
+    context.events.push((context.handle, context.id));
+}
+
+/// A single `esp_timer` together with the guest function it calls back.
+struct Timer {
+    raw: esp_timer_handle_t,
+    id: TimerId,
+    func_index: u32,
+    periodic: bool,
+    _context: Box<TimerContext>,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        // stopping fails if the timer is not running, which is fine here
+        unsafe {
+            esp_timer_stop(self.raw);
+            esp_timer_delete(self.raw);
+        }
+    }
+}
+
+/// The software timers of one runtime. Timers are backed by `esp_timer` and
+/// report expirations as events, that the runtime dispatches to the guest.
+#[derive(Default)]
+pub(crate) struct Timers {
+    timers: HashMap<TimerHandle, Timer>,
+    events: Arc<EventQueue>,
+    // the id of the next timer, never reused
+    next_id: TimerId,
+}
+
+impl Timers {
+    /// Creates and starts a timer that expires after `period_us` microseconds,
+    /// either once or periodically. Returns the handle of the new timer.
+    pub(crate) fn start(
+        &mut self,
+        period_us: u64,
+        periodic: bool,
+        func_index: u32,
+    ) -> Result<TimerHandle, EspError> {
+        let handle = (0..MAX_TIMERS)
+            .find(|handle| !self.timers.contains_key(handle))
+            .ok_or_else(|| EspError::from(esp_idf_sys::ESP_ERR_NO_MEM as i32).unwrap())?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let context = Box::new(TimerContext {
+            handle,
+            id,
+            events: self.events.clone(),
+        });
+
+        let mut raw: esp_timer_handle_t = core::ptr::null_mut();
+        esp!(unsafe {
+            esp_timer_create(
+                &esp_timer_create_args_t {
+                    callback: Some(on_timer_expired),
+                    arg: &*context as *const TimerContext as *mut c_void,
+                    dispatch_method: esp_timer_dispatch_t_ESP_TIMER_TASK,
+                    name: b"wasm_timer\0".as_ptr() as *const _,
+                    skip_unhandled_events: true,
+                },
+                &mut raw as *mut _,
+            )
+        })?;
+
+        // from here on the timer gets cleaned up on drop
+        let timer = Timer {
+            raw,
+            id,
+            func_index,
+            periodic,
+            _context: context,
+        };
+
+        esp!(unsafe {
+            if periodic {
+                esp_timer_start_periodic(timer.raw, period_us)
+            } else {
+                esp_timer_start_once(timer.raw, period_us)
+            }
+        })?;
+
+        self.timers.insert(handle, timer);
+
+        Ok(handle)
+    }
+
+    /// Stops and frees a timer. Returns false if the handle is unknown.
+    pub(crate) fn cancel(&mut self, handle: TimerHandle) -> bool {
+        self.timers.remove(&handle).is_some()
+    }
+
+    /// Returns true if no timer is active.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Takes the next expired timer from the queue and returns its handle and the
+    /// index of the guest function to call. A one-shot timer is freed once it is
+    /// taken, its handle can be given out again.
+    pub(crate) fn next_expired(&mut self) -> Option<(TimerHandle, u32)> {
+        while let Some((handle, id)) = self.events.pop() {
+            // the timer might have been cancelled after it fired, and its handle
+            // given to a new timer since
+            let timer = match self.timers.get(&handle) {
+                Some(timer) if timer.id == id => timer,
+                _ => continue,
+            };
+            let func_index = timer.func_index;
+            if !timer.periodic {
+                self.timers.remove(&handle);
+            }
+            return Some((handle, func_index));
+        }
+
+        None
+    }
+
+    /// Blocks until a timer expires or the timeout passed.
+    pub(crate) fn wait(&self, timeout: Duration) {
+        self.events.wait(timeout);
+    }
+}
@@ -0,0 +1,585 @@
+use core::cell::RefCell;
+use std::io::{self, Write};
+
+use log::info;
+use wasmi::nan_preserving_float::{F32, F64};
+use wasmi::{LittleEndianConvert, MemoryRef, RuntimeValue};
+
+use crate::sections;
+
+/// The first bytes of a trace, followed by the version of the format.
+const MAGIC: &[u8; 4] = b"WTRC";
+const VERSION: u8 = 2;
+
+/// The number of trace bytes printed per line by the [`ConsoleSink`].
+const BYTES_PER_LINE: usize = 48;
+
+/// Something that happened at the interface between the host and a module. A call into
+/// the host is recorded as [`Event::Call`], followed by the memory the host read and
+/// wrote, the callbacks into the module and finally its result. A `memory.grow` is recorded
+/// as [`Event::Grow`], since its result depends on the limits of the device.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Event {
+    /// The module called the host function with the given index.
+    Call { index: u32, args: Vec<RuntimeValue> },
+    /// The host read the memory of the module.
+    Read { offset: u32, bytes: Vec<u8> },
+    /// The host wrote the memory of the module.
+    Write { offset: u32, bytes: Vec<u8> },
+    /// The host called the function of the module at the index of its function table.
+    Callback {
+        func_index: u32,
+        args: Vec<RuntimeValue>,
+    },
+    /// The host function returned.
+    Return(Option<RuntimeValue>),
+    /// The host function trapped, or the module trapped. The trap of the module is recorded
+    /// last, after the trap of a host function that caused it.
+    Trap(String),
+    /// The module grew its memory by `pages`, `memory.grow` returned `result`: the previous
+    /// size in pages or -1.
+    Grow { pages: u32, result: i32 },
+    /// The host paused between two calls of `run` of a lifecycle, whose calls are not
+    /// recorded otherwise.
+    Pause,
+}
+
+impl Event {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Event::Call { index, args } => {
+                out.push(1);
+                write_u32(out, *index);
+                write_values(out, args);
+            }
+            Event::Read { offset, bytes } => {
+                out.push(2);
+                write_u32(out, *offset);
+                write_bytes(out, bytes);
+            }
+            Event::Write { offset, bytes } => {
+                out.push(3);
+                write_u32(out, *offset);
+                write_bytes(out, bytes);
+            }
+            Event::Callback { func_index, args } => {
+                out.push(4);
+                write_u32(out, *func_index);
+                write_values(out, args);
+            }
+            Event::Return(value) => {
+                out.push(5);
+                write_values(out, value.as_slice());
+            }
+            Event::Trap(message) => {
+                out.push(6);
+                write_bytes(out, message.as_bytes());
+            }
+            Event::Grow { pages, result } => {
+                out.push(7);
+                write_u32(out, *pages);
+                write_values(out, &[RuntimeValue::I32(*result)]);
+            }
+            Event::Pause => out.push(8),
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let (&tag, rest) = input.split_first()?;
+        *input = rest;
+
+        let event = match tag {
+            1 => Event::Call {
+                index: sections::read_u32(input)?,
+                args: read_values(input)?,
+            },
+            2 => Event::Read {
+                offset: sections::read_u32(input)?,
+                bytes: read_bytes(input)?.to_vec(),
+            },
+            3 => Event::Write {
+                offset: sections::read_u32(input)?,
+                bytes: read_bytes(input)?.to_vec(),
+            },
+            4 => Event::Callback {
+                func_index: sections::read_u32(input)?,
+                args: read_values(input)?,
+            },
+            5 => Event::Return(read_values(input)?.pop()),
+            6 => Event::Trap(String::from_utf8_lossy(read_bytes(input)?).into_owned()),
+            7 => Event::Grow {
+                pages: sections::read_u32(input)?,
+                result: match read_values(input)?[..] {
+                    [RuntimeValue::I32(result)] => result,
+                    _ => return None,
+                },
+            },
+            8 => Event::Pause,
+            _ => return None,
+        };
+
+        Some(event)
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    write_u64(out, value as u64);
+}
+
+/// Writes an unsigned LEB128 number, like the numbers in a Wasm module.
+fn write_u64(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_u64(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+fn read_bytes<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = sections::read_u32(input)?;
+    sections::split(input, len)
+}
+
+/// Writes values with their type, integers are zigzag encoded so that small negative
+/// numbers stay small.
+fn write_values(out: &mut Vec<u8>, values: &[RuntimeValue]) {
+    write_u32(out, values.len() as u32);
+    for value in values {
+        match *value {
+            RuntimeValue::I32(value) => {
+                out.push(0);
+                write_u64(out, ((value << 1) ^ (value >> 31)) as u32 as u64);
+            }
+            RuntimeValue::I64(value) => {
+                out.push(1);
+                write_u64(out, ((value << 1) ^ (value >> 63)) as u64);
+            }
+            RuntimeValue::F32(value) => {
+                out.push(2);
+                write_u64(out, value.to_bits() as u64);
+            }
+            RuntimeValue::F64(value) => {
+                out.push(3);
+                write_u64(out, value.to_bits());
+            }
+        }
+    }
+}
+
+fn read_values(input: &mut &[u8]) -> Option<Vec<RuntimeValue>> {
+    let count = sections::read_u32(input)?;
+    let mut values = Vec::new();
+    for _ in 0..count {
+        let (&tag, rest) = input.split_first()?;
+        *input = rest;
+        let bits = read_u64(input)?;
+        let value = match tag {
+            0 => RuntimeValue::I32(((bits as u32 >> 1) as i32) ^ -((bits & 1) as i32)),
+            1 => RuntimeValue::I64(((bits >> 1) as i64) ^ -((bits & 1) as i64)),
+            2 => RuntimeValue::F32(F32::from_bits(bits as u32)),
+            3 => RuntimeValue::F64(F64::from_bits(bits)),
+            _ => return None,
+        };
+        values.push(value);
+    }
+
+    Some(values)
+}
+
+/// Parses a complete trace into its events.
+#[allow(dead_code)] // used by tools/simulator
+pub(crate) fn parse(trace: &[u8]) -> Result<Vec<Event>, String> {
+    let mut input = match trace.strip_prefix(MAGIC.as_slice()) {
+        Some([VERSION, rest @ ..]) => rest,
+        Some(_) => return Err("the trace has an unsupported version".into()),
+        None => return Err("this is not a trace".into()),
+    };
+
+    let mut events = Vec::new();
+    while !input.is_empty() {
+        let event = Event::decode(&mut input)
+            .ok_or_else(|| format!("the trace is corrupt after {} events", events.len()))?;
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+/// Writes the events of a module into a sink. Recording stops at the first error of the sink.
+pub(crate) struct Recorder {
+    sink: Option<Box<dyn Write>>,
+    buffer: Vec<u8>,
+}
+
+impl Recorder {
+    /// Starts a trace in the sink.
+    pub(crate) fn new(sink: Box<dyn Write>) -> Self {
+        let mut recorder = Self {
+            sink: Some(sink),
+            buffer: Vec::new(),
+        };
+        recorder.buffer.extend_from_slice(MAGIC);
+        recorder.buffer.push(VERSION);
+        recorder.write();
+
+        recorder
+    }
+
+    fn record(&mut self, event: &Event) {
+        event.encode(&mut self.buffer);
+        self.write();
+    }
+
+    fn write(&mut self) {
+        if let Some(sink) = self.sink.as_mut() {
+            if let Err(err) = sink.write_all(&self.buffer) {
+                info!("Stopped recording the trace: {}", err);
+                self.sink = None;
+            }
+        }
+        self.buffer.clear();
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Some(sink) = self.sink.as_mut() {
+            let _ = sink.flush();
+        }
+    }
+}
+
+/// The memory of a module, which records every access of the host while a trace
+/// is recorded. It has the same methods as [`MemoryRef`] that the runtime uses. Every
+/// access fails if the module has no memory the host can access.
+pub(crate) struct TracedMemory<'a> {
+    memory: Option<&'a MemoryRef>,
+    recorder: RefCell<Option<Recorder>>,
+}
+
+impl<'a> TracedMemory<'a> {
+    pub(crate) fn new(memory: Option<&'a MemoryRef>) -> Self {
+        Self {
+            memory,
+            recorder: RefCell::new(None),
+        }
+    }
+
+    /// Records all events from now on.
+    pub(crate) fn start_recording(&mut self, recorder: Recorder) {
+        *self.recorder.get_mut() = Some(recorder);
+    }
+
+    /// Records the event, `event` is only called while a trace is recorded.
+    pub(crate) fn record(&self, event: impl FnOnce() -> Event) {
+        if let Some(recorder) = self.recorder.borrow_mut().as_mut() {
+            recorder.record(&event());
+        }
+    }
+
+    /// Returns the memory itself, e.g. to grow it. Accesses through it are not recorded.
+    pub(crate) fn untraced(&self) -> Option<&'a MemoryRef> {
+        self.memory
+    }
+
+    fn memory(&self) -> Result<&'a MemoryRef, wasmi::Error> {
+        self.memory
+            .ok_or_else(|| wasmi::Error::Memory("the module has no memory".into()))
+    }
+
+    pub(crate) fn get(&self, offset: u32, size: usize) -> Result<Vec<u8>, wasmi::Error> {
+        let bytes = self.memory()?.get(offset, size)?;
+        self.record(|| Event::Read {
+            offset,
+            bytes: bytes.clone(),
+        });
+
+        Ok(bytes)
+    }
+
+    pub(crate) fn set(&self, offset: u32, bytes: &[u8]) -> Result<(), wasmi::Error> {
+        self.memory()?.set(offset, bytes)?;
+        self.record(|| Event::Write {
+            offset,
+            bytes: bytes.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    pub(crate) fn get_value<T: LittleEndianConvert>(&self, offset: u32) -> Result<T, wasmi::Error> {
+        let memory = self.memory()?;
+        let value = memory.get_value(offset)?;
+        self.record(|| Event::Read {
+            offset,
+            bytes: memory
+                .get(offset, core::mem::size_of::<T>())
+                .unwrap_or_default(),
+        });
+
+        Ok(value)
+    }
+
+    pub(crate) fn set_value<T: LittleEndianConvert>(
+        &self,
+        offset: u32,
+        value: T,
+    ) -> Result<(), wasmi::Error> {
+        let memory = self.memory()?;
+        memory.set_value(offset, value)?;
+        self.record(|| Event::Write {
+            offset,
+            bytes: memory
+                .get(offset, core::mem::size_of::<T>())
+                .unwrap_or_default(),
+        });
+
+        Ok(())
+    }
+
+    /// Lets `fill` write `len` bytes at `offset` in place. Returns false if they are out of bounds.
+    pub(crate) fn fill(&self, offset: u32, len: u32, fill: impl FnOnce(&mut [u8])) -> bool {
+        let memory = match self.memory {
+            Some(memory) => memory,
+            None => return false,
+        };
+        let start = offset as usize;
+        let filled = memory.with_direct_access_mut(|memory| {
+            match memory.get_mut(start..start.saturating_add(len as usize)) {
+                Some(buf) => {
+                    fill(buf);
+                    true
+                }
+                None => false,
+            }
+        });
+
+        if filled {
+            self.record(|| Event::Write {
+                offset,
+                bytes: memory.get(offset, len as usize).unwrap_or_default(),
+            });
+        }
+        filled
+    }
+}
+
+/// Prints a trace to the console as hex, each line prefixed with `trace <module>`. The
+/// trace can be restored from a log of the console with [`from_console`]. A new trace,
+/// e.g. of a restarted module, starts with the line `trace <module> start`.
+pub(crate) struct ConsoleSink {
+    module: &'static str,
+    pending: Vec<u8>,
+}
+
+impl ConsoleSink {
+    pub(crate) fn new(module: &'static str) -> Self {
+        println!("trace {} start", module);
+        Self {
+            module,
+            pending: Vec::new(),
+        }
+    }
+
+    fn print_line(&mut self, len: usize) {
+        let hex: String = self
+            .pending
+            .drain(..len)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        println!("trace {} {}", self.module, hex);
+    }
+}
+
+impl Write for ConsoleSink {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(bytes);
+        while self.pending.len() >= BYTES_PER_LINE {
+            self.print_line(BYTES_PER_LINE);
+        }
+
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            self.print_line(self.pending.len());
+        }
+
+        Ok(())
+    }
+}
+
+/// Restores the last trace of a module from a log of the console. Lines of other modules
+/// and of the logger are skipped.
+#[allow(dead_code)] // used by tools/simulator
+pub(crate) fn from_console(log: &str, module: &str) -> Result<Vec<u8>, String> {
+    let prefix = format!("trace {} ", module);
+    let mut trace = Vec::new();
+    for line in log.lines() {
+        let hex = match line.find(&prefix) {
+            Some(start) => line[start + prefix.len()..].trim(),
+            None => continue,
+        };
+        if hex == "start" {
+            trace.clear();
+            continue;
+        }
+        for i in (0..hex.len()).step_by(2) {
+            let byte = hex
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("invalid trace line: {}", line))?;
+            trace.push(byte);
+        }
+    }
+
+    if trace.is_empty() {
+        return Err(format!("the log contains no trace of module {}", module));
+    }
+    Ok(trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(events: &[Event]) -> Vec<u8> {
+        let mut trace = MAGIC.to_vec();
+        trace.push(VERSION);
+        for event in events {
+            event.encode(&mut trace);
+        }
+        trace
+    }
+
+    #[test]
+    fn every_event_round_trips() {
+        let events = vec![
+            Event::Call {
+                index: 3,
+                args: vec![
+                    RuntimeValue::I32(i32::MIN),
+                    RuntimeValue::I32(-1),
+                    RuntimeValue::I64(i64::MAX),
+                    RuntimeValue::I64(-300),
+                    RuntimeValue::F32((-0.5_f32).into()),
+                    RuntimeValue::F64(f64::NEG_INFINITY.into()),
+                ],
+            },
+            Event::Read {
+                offset: u32::MAX,
+                bytes: b"hello".to_vec(),
+            },
+            Event::Write {
+                offset: 0,
+                bytes: Vec::new(),
+            },
+            Event::Callback {
+                func_index: 200,
+                args: vec![RuntimeValue::I32(7)],
+            },
+            Event::Return(Some(RuntimeValue::I64(i64::MIN))),
+            Event::Return(None),
+            Event::Trap("unreachable ✗".into()),
+            Event::Grow {
+                pages: 2,
+                result: 17,
+            },
+            Event::Grow {
+                pages: 65536,
+                result: -1,
+            },
+            Event::Pause,
+        ];
+
+        assert_eq!(parse(&encode(&events)).unwrap(), events);
+    }
+
+    #[test]
+    fn nans_keep_their_bits() {
+        // NaN isn't equal to itself, so the bits are compared
+        let f32_nan = F32::from_bits(0xffc0_0001);
+        let f64_nan = F64::from_bits(0x7ff8_0000_0000_0123);
+        let events = vec![
+            Event::Call {
+                index: 1,
+                args: vec![RuntimeValue::F32(f32_nan)],
+            },
+            Event::Return(Some(RuntimeValue::F64(f64_nan))),
+        ];
+
+        let parsed = parse(&encode(&events)).unwrap();
+        match &parsed[..] {
+            [Event::Call { args, .. }, Event::Return(Some(RuntimeValue::F64(f64)))] => {
+                assert!(
+                    matches!(args[..], [RuntimeValue::F32(f32)] if f32.to_bits() == f32_nan.to_bits())
+                );
+                assert_eq!(f64.to_bits(), f64_nan.to_bits());
+            }
+            events => panic!("unexpected events {:?}", events),
+        }
+    }
+
+    #[test]
+    fn broken_traces_are_rejected() {
+        assert_eq!(parse(b"WASM\x01").unwrap_err(), "this is not a trace");
+        assert_eq!(
+            parse(b"WTRC\x01").unwrap_err(),
+            "the trace has an unsupported version"
+        );
+
+        let trace = encode(&[Event::Return(None), Event::Trap("oops".into())]);
+        assert_eq!(
+            parse(&trace[..trace.len() - 1]).unwrap_err(),
+            "the trace is corrupt after 1 events"
+        );
+        let mut unknown = encode(&[]);
+        unknown.push(42);
+        assert!(parse(&unknown).is_err());
+    }
+
+    #[test]
+    fn the_last_trace_is_restored_from_the_console() {
+        let log = "\
+trace main start
+trace main 5754524302
+[    1.000000] INFO  guest::main: restarting
+trace other start
+trace other 0500
+trace main start
+trace main 5754
+trace main 52430205
+trace main 00
+";
+        assert_eq!(
+            parse(&from_console(log, "main").unwrap()).unwrap(),
+            vec![Event::Return(None)]
+        );
+        assert!(from_console(log, "missing").is_err());
+        assert!(from_console("trace main 5x", "main").is_err());
+    }
+}
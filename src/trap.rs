@@ -0,0 +1,167 @@
+use core::fmt;
+use std::collections::HashMap;
+
+use wasmi::{HostError, Trap, TrapKind};
+
+use crate::sections;
+
+/// The id of the subsection of the name section that holds the function names.
+const FUNCTION_NAMES: u8 = 1;
+
+/// The number of innermost frames of a backtrace that are printed, a module that recursed
+/// until its stack overflowed has thousands.
+const BACKTRACE_FRAMES: usize = 32;
+
+/// The names of the functions of a module, from its name section.
+#[derive(Clone, Default)]
+pub(crate) struct FunctionNames {
+    names: HashMap<u32, String>,
+}
+
+impl FunctionNames {
+    /// Reads the function names from the name section of a module. The section is read
+    /// directly, parity-wasm rejects the whole section if it contains subsections it
+    /// doesn't know, like the label or data segment names emitted by recent compilers.
+    pub(crate) fn from_wasm(wasm: &[u8]) -> Self {
+        Self {
+            names: name_map(wasm, FUNCTION_NAMES),
+        }
+    }
+
+    pub(crate) fn get(&self, index: u32) -> Option<&str> {
+        self.names.get(&index).map(String::as_str)
+    }
+
+    /// Returns the index of the function with the given name.
+    pub(crate) fn find(&self, name: &str) -> Option<u32> {
+        self.names
+            .iter()
+            .find(|(_, function)| *function == name)
+            .map(|(&index, _)| index)
+    }
+}
+
+/// Reads a subsection of the name section that maps indices to names, e.g. the function
+/// names. Returns no names if the module has no such subsection or it is malformed.
+pub(crate) fn name_map(wasm: &[u8], subsection_id: u8) -> HashMap<u32, String> {
+    sections::custom_section(wasm, "name")
+        .and_then(|section| parse_name_map(section, subsection_id))
+        .unwrap_or_default()
+}
+
+fn parse_name_map(mut section: &[u8], subsection_id: u8) -> Option<HashMap<u32, String>> {
+    while let Some((&id, rest)) = section.split_first() {
+        section = rest;
+        let len = sections::read_u32(&mut section)?;
+        let mut subsection = sections::split(&mut section, len)?;
+        if id != subsection_id {
+            continue;
+        }
+
+        let mut names = HashMap::new();
+        for _ in 0..sections::read_u32(&mut subsection)? {
+            let index = sections::read_u32(&mut subsection)?;
+            let len = sections::read_u32(&mut subsection)?;
+            let name = sections::split(&mut subsection, len)?;
+            names.insert(index, String::from_utf8_lossy(name).into_owned());
+        }
+        return Some(names);
+    }
+
+    None
+}
+
+/// The trap that is raised once a module reported a panic.
+#[derive(Debug)]
+pub(crate) struct GuestPanic {
+    pub(crate) message: String,
+    pub(crate) file: String,
+    pub(crate) line: u32,
+}
+
+impl fmt::Display for GuestPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the module panicked at {}:{}: {}",
+            self.file, self.line, self.message
+        )
+    }
+}
+
+impl HostError for GuestPanic {}
+
+/// Describes why a module trapped.
+pub(crate) fn describe(trap: &Trap) -> String {
+    match trap.kind() {
+        TrapKind::Host(err) => err.to_string(),
+        kind => format!("{:?}", kind),
+    }
+}
+
+/// Describes why and where a module trapped.
+pub(crate) struct TrapReport {
+    kind: String,
+    /// The functions that were running, innermost first, with their index and name.
+    backtrace: Vec<(u32, Option<String>)>,
+}
+
+impl TrapReport {
+    /// Creates a report from a trap and the call stack of the module at the time
+    /// of the trap, with the outermost function first.
+    pub(crate) fn new(trap: &Trap, call_stack: &[u32], names: &FunctionNames) -> Self {
+        let kind = describe(trap);
+        let backtrace = call_stack
+            .iter()
+            .rev()
+            .map(|&index| (index, names.get(index).map(String::from)))
+            .collect();
+
+        Self { kind, backtrace }
+    }
+
+    /// Adds why the module trapped beyond the kind of the trap, e.g. which limit of the
+    /// stack it exceeded.
+    pub(crate) fn with_cause(mut self, cause: &str) -> Self {
+        self.kind = format!("{} ({})", self.kind, cause);
+        self
+    }
+
+    /// Returns a single line summary of the trap.
+    pub(crate) fn summary(&self) -> String {
+        match self.backtrace.first() {
+            Some(frame) => format!("{} in {}", self.kind, Function(frame)),
+            None => self.kind.clone(),
+        }
+    }
+}
+
+/// Formats a frame of the backtrace.
+struct Function<'a>(&'a (u32, Option<String>));
+
+impl fmt::Display for Function<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            (index, Some(name)) => write!(f, "function {} ({})", index, name),
+            (index, None) => write!(f, "function {}", index),
+        }
+    }
+}
+
+impl fmt::Display for TrapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary())?;
+        for (depth, frame) in self.backtrace.iter().enumerate().take(BACKTRACE_FRAMES) {
+            write!(f, "\n  #{} {}", depth, Function(frame))?;
+        }
+        if self.backtrace.len() > BACKTRACE_FRAMES {
+            write!(
+                f,
+                "\n  ... {} more frames",
+                self.backtrace.len() - BACKTRACE_FRAMES
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs;
+use std::process::exit;
+
+// the image format of the runtime, so that both agree on it
+#[path = "../../../src/image.rs"]
+mod image;
+
+const USAGE: &str = "usage:
+    module-image compress <module> <image>     compresses a module into an image
+    module-image decompress <image> <module>   restores the module of an image";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args[..] {
+        ["compress", module, image] => compress(module, image),
+        ["decompress", image, module] => decompress(image, module),
+        _ => Err(USAGE.into()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("could not read {}: {}", path, err))
+}
+
+fn write(path: &str, bytes: &[u8]) -> Result<(), String> {
+    fs::write(path, bytes).map_err(|err| format!("could not write {}: {}", path, err))
+}
+
+fn compress(module: &str, image: &str) -> Result<(), String> {
+    let wasm = read(module)?;
+    if !wasm.starts_with(b"\0asm") {
+        return Err(format!("{} is not a Wasm module", module));
+    }
+
+    let compressed = image::compress(&wasm);
+    // only write images the runtime can load
+    if *image::decompress(&compressed)? != *wasm {
+        return Err(format!("the image of {} doesn't match the module", module));
+    }
+    write(image, &compressed)?;
+
+    println!(
+        "compressed {} ({} bytes) into {} ({} bytes)",
+        module,
+        wasm.len(),
+        image,
+        compressed.len()
+    );
+    Ok(())
+}
+
+fn decompress(image: &str, module: &str) -> Result<(), String> {
+    let wasm = image::decompress(&read(image)?)?.into_owned();
+    write(module, &wasm)?;
+
+    println!("decompressed {} into {}", image, module);
+    Ok(())
+}
@@ -0,0 +1,11 @@
+;; Busy waits for 9999 us, just short of a tick, 100 times, and then sleeps for 1.5 s.
+(module
+  (import "env" "delay_us" (func $delay_us (param i32)))
+  (import "env" "delay_ms" (func $delay_ms (param i32)))
+  (func (export "start")
+    (local $i i32)
+    (loop $busy
+      (call $delay_us (i32.const 9999))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br_if $busy (i32.lt_u (local.get $i) (i32.const 100))))
+    (call $delay_ms (i32.const 1500))))
@@ -0,0 +1,55 @@
+;; Sends and receives over a channel, it traps unless the channel answers like on the device. Waits for 1 ms for
+;; a message that never comes. Run it with the manifest `channel sim-loop`.
+(module
+  (import "env" "chan_open" (func $chan_open (param i32 i32 i32) (result i32)))
+  (import "env" "chan_send" (func $chan_send (param i32 i32 i32 i64) (result i32)))
+  (import "env" "chan_recv" (func $chan_recv (param i32 i32 i32 i64 i32) (result i32)))
+  (memory 1)
+  ;; the handle is written to 64 and the length of a message to 68, messages are received to 128
+  (data (i32.const 0) "sim-loop")
+  (data (i32.const 16) "secret")
+  (data (i32.const 32) "ping")
+  (func $expect (param $res i32) (param $expected i32)
+    (if (i32.ne (local.get $res) (local.get $expected))
+      (then unreachable)))
+  (func (export "start")
+    (local $chan i32)
+    (local $i i32)
+    ;; PERMISSION_DENIED
+    (call $expect (call $chan_open (i32.const 16) (i32.const 6) (i32.const 64)) (i32.const -2))
+    (call $expect (call $chan_open (i32.const 0) (i32.const 8) (i32.const 64)) (i32.const 0))
+    (local.set $chan (i32.load (i32.const 64)))
+
+    ;; ESP_ERR_TIMEOUT after 1 ms
+    (call $expect (call $chan_recv (local.get $chan) (i32.const 128) (i32.const 16) (i64.const 1000) (i32.const 68))
+      (i32.const 0x107))
+
+    ;; ESP_ERR_INVALID_SIZE for a buffer that is too small, the message stays in the channel
+    (call $expect (call $chan_send (local.get $chan) (i32.const 32) (i32.const 4) (i64.const 0)) (i32.const 0))
+    (call $expect (call $chan_recv (local.get $chan) (i32.const 128) (i32.const 2) (i64.const 0) (i32.const 68))
+      (i32.const 0x104))
+    (call $expect (i32.load (i32.const 68)) (i32.const 4))
+    (call $expect (call $chan_recv (local.get $chan) (i32.const 128) (i32.const 16) (i64.const 0) (i32.const 68))
+      (i32.const 0))
+    (call $expect (i32.load (i32.const 68)) (i32.const 4))
+    (call $expect (i32.load (i32.const 128)) (i32.load (i32.const 32)))
+
+    ;; ESP_ERR_INVALID_SIZE for a message longer than 256 bytes
+    (call $expect (call $chan_send (local.get $chan) (i32.const 1024) (i32.const 300) (i64.const 0))
+      (i32.const 0x104))
+
+    ;; ESP_ERR_TIMEOUT once the channel holds 8 messages
+    (loop $fill
+      (call $expect (call $chan_send (local.get $chan) (i32.const 32) (i32.const 4) (i64.const 0)) (i32.const 0))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br_if $fill (i32.lt_u (local.get $i) (i32.const 8))))
+    (call $expect (call $chan_send (local.get $chan) (i32.const 32) (i32.const 4) (i64.const 0)) (i32.const 0x107))
+    (loop $drain
+      (call $expect (call $chan_recv (local.get $chan) (i32.const 128) (i32.const 16) (i64.const 0) (i32.const 68))
+        (i32.const 0))
+      (local.set $i (i32.sub (local.get $i) (i32.const 1)))
+      (br_if $drain (local.get $i)))
+
+    ;; a handle that was never given out
+    (call $expect (call $chan_recv (i32.const 7) (i32.const 128) (i32.const 16) (i64.const 0) (i32.const 68))
+      (i32.const -1))))
@@ -0,0 +1,16 @@
+;; Sleeps for 1.5 s and 250 us and checks that as much time passed meanwhile, it traps
+;; otherwise. Run it with `simulator run clock.wasm`.
+(module
+  (import "env" "time_now_us" (func $time_now_us (result i64)))
+  (import "env" "uptime_ms" (func $uptime_ms (result i64)))
+  (import "env" "delay_ms" (func $delay_ms (param i32)))
+  (import "env" "delay_us" (func $delay_us (param i32)))
+  (func (export "start")
+    (local $started i64)
+    (local.set $started (call $time_now_us))
+    (call $delay_ms (i32.const 1500))
+    (call $delay_us (i32.const 250))
+    (if (i64.ne (i64.sub (call $time_now_us) (local.get $started)) (i64.const 1500250))
+      (then unreachable))
+    (if (i64.ne (call $uptime_ms) (i64.const 1500))
+      (then unreachable))))
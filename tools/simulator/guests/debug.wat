@@ -0,0 +1,10 @@
+;; Writes "wasm" to its memory and reads the clock from a nested function. Debugged with a breakpoint on `read`, its
+;; call stack and memory can be read once it halts there.
+(module
+  (import "env" "time_now_us" (func $time_now_us (result i64)))
+  (memory (export "memory") 1)
+  (func $read
+    (drop (call $time_now_us)))
+  (func $start (export "start")
+    (i32.store (i32.const 16) (i32.const 0x6d736177))
+    (call $read)))
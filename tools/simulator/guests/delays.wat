@@ -0,0 +1,30 @@
+;; Delays for shorter and longer than a tick of 10 ms and checks after each delay that exactly as much time
+;; passed, it traps otherwise. Of the 2.545 s, the 1 us, the 9999 us and the 5 ms that are left of the
+;; 25 ms are busy waited.
+(module
+  (import "env" "time_now_us" (func $time_now_us (result i64)))
+  (import "env" "delay_ms" (func $delay_ms (param i32)))
+  (import "env" "delay_us" (func $delay_us (param i32)))
+  (global $last (mut i64) (i64.const 0))
+  ;; traps unless `us` microseconds passed since the last check
+  (func $passed (param $us i64)
+    (local $now i64)
+    (local.set $now (call $time_now_us))
+    (if (i64.ne (i64.sub (local.get $now) (global.get $last)) (local.get $us))
+      (then unreachable))
+    (global.set $last (local.get $now)))
+  (func (export "start")
+    (call $delay_us (i32.const 0))
+    (call $passed (i64.const 0))
+    (call $delay_ms (i32.const 0))
+    (call $passed (i64.const 0))
+    (call $delay_us (i32.const 1))
+    (call $passed (i64.const 1))
+    (call $delay_us (i32.const 9999))
+    (call $passed (i64.const 9999))
+    (call $delay_us (i32.const 10000))
+    (call $passed (i64.const 10000))
+    (call $delay_ms (i32.const 25))
+    (call $passed (i64.const 25000))
+    (call $delay_ms (i32.const 2500))
+    (call $passed (i64.const 2500000))))
@@ -0,0 +1,10 @@
+;; Recurses 50 functions deep with 32 locals each, it needs a large value stack for a
+;; shallow call stack. Build it with `wat2wasm frames.wat`.
+(module
+  (func $down (param $n i32)
+    (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+    (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+    (if (local.get $n)
+      (then (call $down (i32.sub (local.get $n) (i32.const 1))))))
+  (func (export "start")
+    (call $down (i32.const 50))))
@@ -0,0 +1,29 @@
+;; Initializes, drives and reads pins of the board, it traps unless the board answers like the device.
+;; Pin 6 has to be claimed by someone else and pin 8 has to be high. Run it with the manifest `gpio 6 7 8 12`.
+(module
+  (import "env" "gpio_init" (func $gpio_init (param i32 i32 i32) (result i32)))
+  (import "env" "gpio_deinit" (func $gpio_deinit (param i32 i32) (result i32)))
+  (import "env" "gpio_read" (func $gpio_read (param i32 i32 i32) (result i32)))
+  (import "env" "gpio_write" (func $gpio_write (param i32 i32 i32) (result i32)))
+  (memory 1)
+  (func $expect (param $res i32) (param $expected i32)
+    (if (i32.ne (local.get $res) (local.get $expected))
+      (then unreachable)))
+  (func (export "start")
+    ;; claimed by someone else, not granted and not a GPIO
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 6) (i32.const 0)) (i32.const -1))
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 9) (i32.const 0)) (i32.const -2))
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 12) (i32.const 0)) (i32.const -1))
+
+    ;; pin 7 is driven high and stays high after it is freed
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 7) (i32.const 0)) (i32.const 0))
+    (call $expect (call $gpio_write (i32.const 0) (i32.const 7) (i32.const 1)) (i32.const 0))
+    (call $expect (call $gpio_read (i32.const 0) (i32.const 7) (i32.const 0)) (i32.const -1))
+    (call $expect (call $gpio_deinit (i32.const 0) (i32.const 7)) (i32.const 0))
+    (call $expect (call $gpio_write (i32.const 0) (i32.const 7) (i32.const 0)) (i32.const -1))
+
+    ;; pin 8 is read
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 8) (i32.const 1)) (i32.const 0))
+    (call $expect (call $gpio_read (i32.const 0) (i32.const 8) (i32.const 0)) (i32.const 0))
+    (call $expect (i32.load8_u (i32.const 0)) (i32.const 1))
+    (call $expect (call $gpio_write (i32.const 0) (i32.const 8) (i32.const 0)) (i32.const -1))))
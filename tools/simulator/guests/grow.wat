@@ -0,0 +1,11 @@
+;; Grows its memory twice. Replayed against a trace in which the second growth failed on the
+;; device, the simulator has to fail it as well, even though there is memory left here.
+(module
+  (memory 1)
+  (func (export "start")
+    (if (i32.ne (memory.grow (i32.const 1)) (i32.const 1))
+      (then unreachable))
+    (if (i32.ne (memory.grow (i32.const 100)) (i32.const -1))
+      (then unreachable))
+    (if (i32.ne (memory.size) (i32.const 2))
+      (then unreachable))))
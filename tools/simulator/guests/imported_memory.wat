@@ -0,0 +1,16 @@
+;; Imports its memory from the host without a maximum. The host writes random bytes into it and lets it grow to the
+;; limit of the manifest, but not beyond. Run it with the manifest `memory 131072`, which allows two pages.
+(module
+  (import "env" "memory" (memory 1))
+  (import "env" "random_fill" (func $random_fill (param i32 i32) (result i32)))
+  (func (export "start")
+    (if (call $random_fill (i32.const 0) (i32.const 8))
+      (then unreachable))
+    (if (i64.eqz (i64.load (i32.const 0)))
+      (then unreachable))
+    (if (i32.ne (memory.grow (i32.const 1)) (i32.const 1))
+      (then unreachable))
+    (if (i32.ne (memory.grow (i32.const 1)) (i32.const -1))
+      (then unreachable))
+    (if (i32.ne (memory.size) (i32.const 2))
+      (then unreachable))))
@@ -0,0 +1,5 @@
+;; Imports `__consume_fuel` to refill its fuel, which only the instrumentation may call. The module is rejected.
+(module
+  (import "env" "__consume_fuel" (func $consume_fuel (param i32)))
+  (func (export "start")
+    (call $consume_fuel (i32.const -1))))
@@ -0,0 +1,23 @@
+;; Counts the calls of `loop` and fails with the error code 7 on the third, so that its
+;; `deinit` is called. Build it with `wat2wasm lifecycle.wat` and run it with
+;; `--entry "lifecycle setup loop deinit"`. `deinit` traps unless it follows the error code.
+;; `add` and `scale` return plain values, e.g. for `--entry "add i32:2 i32:3"`.
+(module
+  (global $count (mut i32) (i32.const -1))
+  (func (export "setup") (result i32)
+    (global.set $count (i32.const 0))
+    (i32.const 0))
+  (func (export "loop") (result i32)
+    (global.set $count (i32.add (global.get $count) (i32.const 1)))
+    (if (result i32) (i32.eq (global.get $count) (i32.const 3))
+      (then (i32.const 7))
+      (else (i32.const 0))))
+  (func (export "deinit") (result i32)
+    (if (i32.ne (global.get $count) (i32.const 3))
+      (then unreachable))
+    (global.set $count (i32.const -2))
+    (i32.const 0))
+  (func (export "add") (param i32 i32) (result i32)
+    (i32.add (local.get 0) (local.get 1)))
+  (func (export "scale") (param $value i32) (param $factor f32) (result f32)
+    (f32.mul (f32.convert_i32_s (local.get $value)) (local.get $factor))))
@@ -0,0 +1,13 @@
+;; Has no memory at all. Every host function that takes a pointer returns an error code instead of reading or
+;; writing memory. Run it with the manifest granting `storage` and `channel sim-none`.
+(module
+  (import "env" "random_fill" (func $random_fill (param i32 i32) (result i32)))
+  (import "env" "kv_get" (func $kv_get (param i32 i32 i32 i32 i32) (result i32)))
+  (import "env" "chan_open" (func $chan_open (param i32 i32 i32) (result i32)))
+  (func $expect (param $res i32) (param $expected i32)
+    (if (i32.ne (local.get $res) (local.get $expected))
+      (then unreachable)))
+  (func (export "start")
+    (call $expect (call $random_fill (i32.const 0) (i32.const 8)) (i32.const 1))
+    (call $expect (call $kv_get (i32.const 0) (i32.const 4) (i32.const 0) (i32.const 0) (i32.const 0)) (i32.const -1))
+    (call $expect (call $chan_open (i32.const 0) (i32.const 8) (i32.const 0)) (i32.const -1))))
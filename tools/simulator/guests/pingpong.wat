@@ -0,0 +1,40 @@
+;; `ping` sends the numbers 0 to 99 over the channel `sim-ping` and expects each of them back over `sim-pong`,
+;; incremented by `pong`. Both wait for each other forever. Run them side by side with the manifest
+;; `channel sim-ping sim-pong`.
+(module
+  (import "env" "chan_open" (func $chan_open (param i32 i32 i32) (result i32)))
+  (import "env" "chan_send" (func $chan_send (param i32 i32 i32 i64) (result i32)))
+  (import "env" "chan_recv" (func $chan_recv (param i32 i32 i32 i64 i32) (result i32)))
+  (memory 1)
+  ;; the handles are written to 64 and 68, the length of a message to 72 and the message itself to 128
+  (data (i32.const 0) "sim-ping")
+  (data (i32.const 16) "sim-pong")
+  (func $open
+    (if (call $chan_open (i32.const 0) (i32.const 8) (i32.const 64)) (then unreachable))
+    (if (call $chan_open (i32.const 16) (i32.const 8) (i32.const 68)) (then unreachable)))
+  (func $send (param $handle i32)
+    (if (call $chan_send (i32.load (local.get $handle)) (i32.const 128) (i32.const 4) (i64.const -1))
+      (then unreachable)))
+  (func $recv (param $handle i32)
+    (if (call $chan_recv (i32.load (local.get $handle)) (i32.const 128) (i32.const 4) (i64.const -1) (i32.const 72))
+      (then unreachable)))
+  (func (export "ping")
+    (local $i i32)
+    (call $open)
+    (loop $rounds
+      (i32.store (i32.const 128) (local.get $i))
+      (call $send (i32.const 64))
+      (call $recv (i32.const 68))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (if (i32.ne (i32.load (i32.const 128)) (local.get $i))
+        (then unreachable))
+      (br_if $rounds (i32.lt_u (local.get $i) (i32.const 100)))))
+  (func (export "pong")
+    (local $i i32)
+    (call $open)
+    (loop $rounds
+      (call $recv (i32.const 64))
+      (i32.store (i32.const 128) (i32.add (i32.load (i32.const 128)) (i32.const 1)))
+      (call $send (i32.const 68))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br_if $rounds (i32.lt_u (local.get $i) (i32.const 100))))))
@@ -0,0 +1,12 @@
+;; Reads the clock three times from a function whose name contains a `;`, twice through a function without a name.
+;; Profiled on every call into the host, its collapsed stacks end in the host function.
+(module
+  (import "env" "time_now_us" (func $time_now_us (result i64)))
+  (func $read (@name "read;clock")
+    (drop (call $time_now_us)))
+  (func
+    (call $read)
+    (call $read))
+  (func $start (export "start")
+    (call 2)
+    (call $read)))
@@ -0,0 +1,10 @@
+;; `sample` returns 8 random bytes. It traps unless the RNG refuses to fill a buffer outside of the memory.
+(module
+  (import "env" "random_fill" (func $random_fill (param i32 i32) (result i32)))
+  (memory 1)
+  (func (export "sample") (result i64)
+    (if (i32.ne (call $random_fill (i32.const 65532) (i32.const 8)) (i32.const 1))
+      (then unreachable))
+    (if (call $random_fill (i32.const 0) (i32.const 8))
+      (then unreachable))
+    (i64.load (i32.const 0))))
@@ -0,0 +1,8 @@
+;; Recurses 1000 functions deep with small frames, it needs a deep call stack. Build it with
+;; `wat2wasm recursion.wat`.
+(module
+  (func $down (param $n i32)
+    (if (local.get $n)
+      (then (call $down (i32.sub (local.get $n) (i32.const 1))))))
+  (func (export "start")
+    (call $down (i32.const 1000))))
@@ -0,0 +1,9 @@
+;; Exports its memory as `heap` instead of `memory`, the host writes random bytes into it all the same.
+(module
+  (import "env" "random_fill" (func $random_fill (param i32 i32) (result i32)))
+  (memory (export "heap") 1)
+  (func (export "start")
+    (if (call $random_fill (i32.const 0) (i32.const 8))
+      (then unreachable))
+    (if (i64.eqz (i64.load (i32.const 0)))
+      (then unreachable))))
@@ -0,0 +1,18 @@
+;; `start` spins forever without sleeping, `sleepy` spends a little fuel at a time 20 times and sleeps for a
+;; tick in between.
+(module
+  (import "env" "delay_ms" (func $delay_ms (param i32)))
+  (func (export "start")
+    (loop $spin
+      (br $spin)))
+  (func (export "sleepy")
+    (local $round i32)
+    (local $i i32)
+    (loop $rounds
+      (local.set $i (i32.const 0))
+      (loop $burn
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br_if $burn (i32.lt_u (local.get $i) (i32.const 50))))
+      (call $delay_ms (i32.const 10))
+      (local.set $round (i32.add (local.get $round) (i32.const 1)))
+      (br_if $rounds (i32.lt_u (local.get $round) (i32.const 20))))))
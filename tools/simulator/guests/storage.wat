@@ -0,0 +1,49 @@
+;; Counts its runs in the key `runs` of its storage and traps unless the count is the expected one, so that
+;; runs against the same storage count up. Checks the errors of NVS along the way. `denied` traps unless the
+;; storage is denied to the module.
+(module
+  (import "env" "kv_get" (func $kv_get (param i32 i32 i32 i32 i32) (result i32)))
+  (import "env" "kv_set" (func $kv_set (param i32 i32 i32 i32) (result i32)))
+  (import "env" "kv_delete" (func $kv_delete (param i32 i32) (result i32)))
+  (import "env" "kv_list" (func $kv_list (param i32 i32 i32) (result i32)))
+  (memory 1)
+  ;; the count is read to 64 and its length to 80, the keys are listed to 96
+  (data (i32.const 0) "runs")
+  (data (i32.const 16) "a-key-that-is-too-long")
+  (func (export "start") (param $expected i32)
+    (local $res i32)
+    (local.set $res (call $kv_get (i32.const 0) (i32.const 4) (i32.const 64) (i32.const 4) (i32.const 80)))
+    ;; ESP_ERR_NVS_NOT_FOUND on the first run
+    (if (i32.eq (local.get $res) (i32.const 0x1102))
+      (then (i32.store (i32.const 64) (i32.const 0)))
+      (else
+        (if (local.get $res) (then unreachable))
+        (if (i32.ne (i32.load (i32.const 80)) (i32.const 4)) (then unreachable))))
+    (i32.store (i32.const 64) (i32.add (i32.load (i32.const 64)) (i32.const 1)))
+    (if (i32.ne (i32.load (i32.const 64)) (local.get $expected))
+      (then unreachable))
+    (if (call $kv_set (i32.const 0) (i32.const 4) (i32.const 64) (i32.const 4))
+      (then unreachable))
+
+    ;; ESP_ERR_NVS_INVALID_LENGTH, the length is written anyway
+    (if (i32.ne (call $kv_get (i32.const 0) (i32.const 4) (i32.const 64) (i32.const 2) (i32.const 80))
+                (i32.const 0x110c))
+      (then unreachable))
+    (if (i32.ne (i32.load (i32.const 80)) (i32.const 4)) (then unreachable))
+    ;; ESP_ERR_NVS_KEY_TOO_LONG
+    (if (i32.ne (call $kv_set (i32.const 16) (i32.const 22) (i32.const 64) (i32.const 4)) (i32.const 0x1109))
+      (then unreachable))
+    ;; ESP_ERR_NVS_NOT_FOUND
+    (if (i32.ne (call $kv_delete (i32.const 16) (i32.const 5)) (i32.const 0x1102))
+      (then unreachable))
+
+    ;; the only key is `runs`, terminated by a NUL byte
+    (if (call $kv_list (i32.const 96) (i32.const 32) (i32.const 80))
+      (then unreachable))
+    (if (i32.ne (i32.load (i32.const 80)) (i32.const 5)) (then unreachable))
+    (if (i32.ne (i32.load (i32.const 96)) (i32.load (i32.const 0))) (then unreachable))
+    (if (i32.load8_u (i32.const 100)) (then unreachable)))
+  (func (export "denied")
+    ;; PERMISSION_DENIED
+    (if (i32.ne (call $kv_set (i32.const 0) (i32.const 4) (i32.const 64) (i32.const 4)) (i32.const -2))
+      (then unreachable))))
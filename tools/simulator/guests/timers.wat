@@ -0,0 +1,57 @@
+;; Starts a periodic timer of 100 ms and a one-shot timer of 250 ms, sleeps for a second and checks that the
+;; timers called back at the right virtual time, it traps otherwise. Any `periodic` but zero starts a periodic
+;; timer, which can't be shorter than 50 us. Then starts more one-shot timers one after the other than a module
+;; can hold at once, which only works if expired one-shot timers are freed.
+(module
+  (import "env" "timer_start" (func $timer_start (param i64 i32 i32 i32) (result i32)))
+  (import "env" "timer_cancel" (func $timer_cancel (param i32) (result i32)))
+  (import "env" "time_now_us" (func $time_now_us (result i64)))
+  (import "env" "delay_ms" (func $delay_ms (param i32)))
+  (memory 1)
+  (table (export "__indirect_function_table") 2 funcref)
+  (elem (i32.const 0) $on_tick $on_once)
+  (global $ticks (mut i32) (i32.const 0))
+  (global $last_tick (mut i64) (i64.const 0))
+  (global $once (mut i32) (i32.const 0))
+  (global $once_at (mut i64) (i64.const 0))
+  (func $on_tick (param $handle i32)
+    (global.set $ticks (i32.add (global.get $ticks) (i32.const 1)))
+    (global.set $last_tick (call $time_now_us)))
+  (func $on_once (param $handle i32)
+    (global.set $once (i32.add (global.get $once) (i32.const 1)))
+    (global.set $once_at (call $time_now_us)))
+  (func (export "start")
+    (local $i i32)
+    ;; the handle of the periodic timer is at 0, the one of the one-shot timers at 4
+    (if (i32.ne (call $timer_start (i64.const 49) (i32.const 1) (i32.const 0) (i32.const 0)) (i32.const 0x102))
+      (then unreachable))
+    (if (call $timer_start (i64.const 100000) (i32.const 2) (i32.const 0) (i32.const 0))
+      (then unreachable))
+    (if (call $timer_start (i64.const 250000) (i32.const 0) (i32.const 1) (i32.const 4))
+      (then unreachable))
+    (call $delay_ms (i32.const 1000))
+    (if (i32.ne (global.get $ticks) (i32.const 10))
+      (then unreachable))
+    (if (i64.ne (global.get $last_tick) (i64.const 1000000))
+      (then unreachable))
+    (if (i32.ne (global.get $once) (i32.const 1))
+      (then unreachable))
+    (if (i64.ne (global.get $once_at) (i64.const 250000))
+      (then unreachable))
+
+    (loop $again
+      (if (call $timer_start (i64.const 1000) (i32.const 0) (i32.const 1) (i32.const 4))
+        (then unreachable))
+      (call $delay_ms (i32.const 10))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br_if $again (i32.lt_u (local.get $i) (i32.const 20))))
+    (if (i32.ne (global.get $once) (i32.const 21))
+      (then unreachable))
+
+    ;; the expired one-shot timer is gone, the periodic one can be cancelled once
+    (if (i32.ne (call $timer_cancel (i32.load (i32.const 4))) (i32.const -1))
+      (then unreachable))
+    (if (call $timer_cancel (i32.load (i32.const 0)))
+      (then unreachable))
+    (if (i32.ne (call $timer_cancel (i32.load (i32.const 0))) (i32.const -1))
+      (then unreachable))))
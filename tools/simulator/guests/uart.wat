@@ -0,0 +1,58 @@
+;; `hold` opens the UART over pins 2 and 3 and initializes pin 4 as GPIO, then tries to free the UART pins
+;; with `gpio_deinit`. `take` waits until it did and tries to claim all three pins. Both trap unless the pins
+;; stay with `hold`. Run them side by side with a manifest that grants `uart`, `gpio 2 3 4` and
+;; `channel sim-uart-held sim-uart-done`.
+(module
+  (import "env" "uart_init" (func $uart_init (param i32 i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+  (import "env" "gpio_init" (func $gpio_init (param i32 i32 i32) (result i32)))
+  (import "env" "gpio_deinit" (func $gpio_deinit (param i32 i32) (result i32)))
+  (import "env" "chan_open" (func $chan_open (param i32 i32 i32) (result i32)))
+  (import "env" "chan_send" (func $chan_send (param i32 i32 i32 i64) (result i32)))
+  (import "env" "chan_recv" (func $chan_recv (param i32 i32 i32 i64 i32) (result i32)))
+  (memory 1)
+  ;; the handles of the channels are written to 64 and 68, the length of a message to 72, the handle of the
+  ;; UART to 76 and the empty messages are read from 128
+  (data (i32.const 0) "sim-uart-held")
+  (data (i32.const 16) "sim-uart-done")
+  (func $expect (param $res i32) (param $expected i32)
+    (if (i32.ne (local.get $res) (local.get $expected))
+      (then unreachable)))
+  (func $open
+    (call $expect (call $chan_open (i32.const 0) (i32.const 13) (i32.const 64)) (i32.const 0))
+    (call $expect (call $chan_open (i32.const 16) (i32.const 13) (i32.const 68)) (i32.const 0)))
+  (func $send (param $handle i32)
+    (call $expect
+      (call $chan_send (i32.load (local.get $handle)) (i32.const 128) (i32.const 0) (i64.const -1))
+      (i32.const 0)))
+  (func $recv (param $handle i32)
+    (call $expect
+      (call $chan_recv (i32.load (local.get $handle)) (i32.const 128) (i32.const 0) (i64.const -1) (i32.const 72))
+      (i32.const 0)))
+  (func $uart (result i32)
+    (call $uart_init (i32.const 76) (i32.const 0) (i32.const 2) (i32.const 0) (i32.const 3)
+      (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)))
+  (func (export "hold")
+    (call $open)
+    ;; a pin used as GPIO can't be handed to the UART
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 2) (i32.const 0)) (i32.const 0))
+    (call $expect (call $uart) (i32.const -1))
+    (call $expect (call $gpio_deinit (i32.const 0) (i32.const 2)) (i32.const 0))
+    (call $expect (call $uart) (i32.const 0))
+    (call $expect (i32.load8_u (i32.const 76)) (i32.const 1))
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 4) (i32.const 0)) (i32.const 0))
+
+    ;; the pins of the UART are neither initialized nor freed as GPIO, pin 4 isn't freed on another port
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 2) (i32.const 0)) (i32.const -1))
+    (call $expect (call $gpio_deinit (i32.const 0) (i32.const 2)) (i32.const -1))
+    (call $expect (call $gpio_deinit (i32.const 0) (i32.const 3)) (i32.const -1))
+    (call $expect (call $gpio_deinit (i32.const 1) (i32.const 4)) (i32.const -1))
+    (call $send (i32.const 64))
+    (call $recv (i32.const 68)))
+  (func (export "take")
+    (call $open)
+    (call $recv (i32.const 64))
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 2) (i32.const 0)) (i32.const -1))
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 3) (i32.const 1)) (i32.const -1))
+    (call $expect (call $gpio_init (i32.const 0) (i32.const 4) (i32.const 0)) (i32.const -1))
+    (call $expect (call $uart) (i32.const -1))
+    (call $send (i32.const 68))))
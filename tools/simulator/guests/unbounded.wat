@@ -0,0 +1,7 @@
+;; Recurses until its stack overflows, whatever its limits. Build it with
+;; `wat2wasm unbounded.wat`.
+(module
+  (func $down
+    (call $down))
+  (func (export "start")
+    (call $down)))
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// The pins of the ESP32-C3 that modules can use as GPIO, like on the device.
+const GPIO_PINS: [u32; 15] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 18, 19, 20, 21];
+
+/// Returns true if modules can use the pin as GPIO.
+pub(crate) fn is_gpio(pin: u32) -> bool {
+    GPIO_PINS.contains(&pin)
+}
+
+/// The board simulated modules run on. Modules that run side by side share it like they
+/// share the device: what one of them writes to a pin, the others read from it. Which
+/// module may use a pin is arbitrated by the claims of the runtime.
+#[derive(Default)]
+pub(crate) struct Board {
+    // the pins that were never driven are low
+    levels: Mutex<HashMap<u32, bool>>,
+}
+
+impl Board {
+    /// Returns true if the pin is high.
+    pub(crate) fn level(&self, pin: u32) -> bool {
+        self.levels
+            .lock()
+            .unwrap()
+            .get(&pin)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Drives the pin high or low.
+    pub(crate) fn set_level(&self, pin: u32, high: bool) {
+        self.levels.lock().unwrap().insert(pin, high);
+    }
+}
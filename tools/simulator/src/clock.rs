@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+/// The virtual time of a simulated module since it was started. It only passes while the
+/// module sleeps or busy waits, so that a run takes the same virtual time on every machine.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Clock {
+    now: Duration,
+    busy: Duration,
+}
+
+impl Clock {
+    /// Returns the virtual time passed since the module was started.
+    pub(crate) fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Returns the virtual time the module busy waited.
+    pub(crate) fn busy(&self) -> Duration {
+        self.busy
+    }
+
+    /// Lets `duration` pass while the module sleeps.
+    pub(crate) fn sleep(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+
+    /// Lets `duration` pass while the module busy waits.
+    pub(crate) fn busy_wait(&mut self, duration: Duration) {
+        self.now += duration;
+        self.busy += duration;
+    }
+}
@@ -0,0 +1,10 @@
+//! The error codes of ESP-IDF that the runtime returns to modules, the simulated board
+//! returns the same.
+
+pub(crate) const ESP_ERR_NO_MEM: i32 = 0x101;
+pub(crate) const ESP_ERR_INVALID_SIZE: i32 = 0x104;
+pub(crate) const ESP_ERR_TIMEOUT: i32 = 0x107;
+pub(crate) const ESP_ERR_NVS_NOT_FOUND: i32 = 0x1102;
+pub(crate) const ESP_ERR_NVS_INVALID_NAME: i32 = 0x1106;
+pub(crate) const ESP_ERR_NVS_KEY_TOO_LONG: i32 = 0x1109;
+pub(crate) const ESP_ERR_NVS_INVALID_LENGTH: i32 = 0x110c;
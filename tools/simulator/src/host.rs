@@ -0,0 +1,787 @@
+use core::fmt;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use wasmi::memory_units::{Bytes, Pages};
+use wasmi::{
+    Externals, HostError, MemoryRef, ModuleRef, RuntimeArgs, RuntimeValue, StackRecycler, TableRef,
+    Trap, TrapKind,
+};
+
+use crate::board::{self, Board};
+use crate::channel::{ChannelError, ChannelHandle, Channels, MAX_MESSAGE_LEN};
+use crate::clock::Clock;
+use crate::dump;
+use crate::entry::{self, Call, Entry, Failure, Invoker};
+use crate::esp::{
+    ESP_ERR_INVALID_SIZE, ESP_ERR_NO_MEM, ESP_ERR_NVS_INVALID_LENGTH, ESP_ERR_NVS_NOT_FOUND,
+    ESP_ERR_TIMEOUT,
+};
+use crate::imports::{
+    self, CHAN_OPEN_INDEX, CHAN_RECV_INDEX, CHAN_SEND_INDEX, CONSUME_FUEL_INDEX, DELAY_MS_INDEX,
+    DELAY_US_INDEX, ENTER_FUNCTION_INDEX, GPIO_DEINIT_INDEX, GPIO_INIT_INDEX, GPIO_READ_INDEX,
+    GPIO_WRITE_INDEX, KV_DELETE_INDEX, KV_GET_INDEX, KV_LIST_INDEX, KV_SET_INDEX,
+    LEAVE_FUNCTION_INDEX, LOG_INDEX, LOG_MAX_LEVEL_INDEX, MEMORY_GROW_INDEX,
+    PERMISSION_DENIED_INDEX, PRINT_INDEX, PROFILE_MARK_INDEX, RANDOM_FILL_INDEX,
+    REPORT_PANIC_INDEX, TIMER_CANCEL_INDEX, TIMER_POLL_INDEX, TIMER_START_INDEX, TIME_NOW_US_INDEX,
+    UART_INIT_INDEX, UART_READ_INDEX, UART_WRITE_INDEX, UPTIME_MS_INDEX,
+};
+use crate::instance;
+use crate::manifest::{Manifest, PERMISSION_DENIED};
+use crate::memory;
+use crate::metering::{Fuel, FuelConfig, Instrumentation};
+use crate::peripherals::Claims;
+use crate::preemption::Preemption;
+use crate::rng::Rng;
+use crate::scheduler::{Scheduler, TimerHandle};
+use crate::stack::{ShadowStack, StackLimits};
+use crate::stats::Stats;
+use crate::storage::Storage;
+use crate::timer::Timers;
+use crate::trap::GuestPanic;
+
+/// The code the runtime returns for a pin it has no driver for.
+const NO_PIN_DRIVER: i32 = 2;
+
+/// Limits of the stacks used when calling back into the guest, like on the device.
+const CALLBACK_STACK_LIMIT: usize = 8 * 1024;
+
+/// A host function that modules can import, but the simulator doesn't implement.
+#[derive(Debug)]
+struct NotSimulated(&'static str);
+
+impl fmt::Display for NotSimulated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not simulated", self.0)
+    }
+}
+
+impl HostError for NotSimulated {}
+
+/// Answers the calls of a module into the host like the runtime on the device, against a
+/// simulated board with virtual time.
+struct Host {
+    manifest: Manifest,
+    memory: Option<MemoryRef>,
+    memory_limit: Pages,
+    table: Option<TableRef>,
+    call_stack: ShadowStack,
+    clock: Clock,
+    fuel: Option<Fuel>,
+    preemption: Option<Preemption>,
+    timers: Timers,
+    storage: Storage,
+    rng: Rng,
+    board: Arc<Board>,
+    claims: Claims,
+    // the pins the module initialized, true for an input
+    gpio: HashMap<u32, bool>,
+    // the handle of the UART connection, once the module opened it
+    uart: Option<u8>,
+    channels: Channels,
+    // taken while a callback runs, so that callbacks are never nested
+    callback_stack: Option<StackRecycler>,
+    stats: Stats,
+    echo: bool,
+}
+
+impl Host {
+    /// Reads a string from the memory of the module, like the runtime does for messages.
+    fn read_lossy(&self, (offset, len): (u32, u32)) -> String {
+        self.memory
+            .as_ref()
+            .and_then(|memory| memory.get(offset, len as usize).ok())
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Reads a string from the memory of the module, `None` if it is out of bounds or not
+    /// UTF-8.
+    fn read_str(&self, (offset, len): (u32, u32)) -> Option<String> {
+        let bytes = self.memory.as_ref()?.get(offset, len as usize).ok()?;
+        String::from_utf8(bytes).ok()
+    }
+
+    /// Writes `bytes` to a buffer of the module with capacity `cap` and the number of bytes
+    /// to `len_ptr`, like the runtime does.
+    fn write_buffer(&self, bytes: &[u8], buf: u32, cap: u32, len_ptr: u32) -> i32 {
+        let memory = match self.memory.as_ref() {
+            Some(memory) => memory,
+            None => return 1,
+        };
+        if memory.set_value(len_ptr, bytes.len() as u32).is_err() {
+            return 1;
+        }
+        if bytes.len() > cap as usize {
+            return ESP_ERR_NVS_INVALID_LENGTH;
+        }
+
+        memory.set(buf, bytes).map_or(1, |_| 0)
+    }
+
+    /// Opens the UART over the pins, once the controller and the pins are claimed like on
+    /// the device. Nothing is connected to it: what the module writes is dropped and there
+    /// is nothing to read.
+    fn uart_init(&mut self, tx: u32, rx: u32, handle: u32) -> i32 {
+        if !self.manifest.allows_uart()
+            || ![tx, rx].iter().all(|&pin| self.manifest.allows_pin(pin))
+        {
+            return PERMISSION_DENIED;
+        }
+        // one connection per module, like on the device
+        if self.uart.is_some() {
+            return -1;
+        }
+        // the controller and the pins might be in use by another module, or as GPIO by this one
+        if !self.claims.claim_uart(&[tx, rx]) {
+            return -1;
+        }
+        if !board::is_gpio(tx) || !board::is_gpio(rx) {
+            self.claims.release_uart();
+            return NO_PIN_DRIVER;
+        }
+
+        let res = match self
+            .memory
+            .as_ref()
+            .map(|memory| memory.set_value(handle, 1u8))
+        {
+            Some(Ok(())) => 0,
+            _ => 1,
+        };
+        self.uart = Some(1);
+        res
+    }
+
+    /// Initializes a pin of the board as input or output, once it is claimed.
+    fn gpio_init(&mut self, port: u32, pin: u32, is_input: bool) -> i32 {
+        if port != 0 {
+            return -1;
+        }
+        if !self.manifest.allows_pin(pin) {
+            return PERMISSION_DENIED;
+        }
+        // the pin might be in use by another module, or by the UART of this one
+        if !board::is_gpio(pin) || !self.claims.claim_gpio(pin) {
+            return -1;
+        }
+
+        self.gpio.insert(pin, is_input);
+        0
+    }
+
+    /// Frees a pin the module initialized, so that other modules can claim it. The pins of
+    /// the UART stay claimed.
+    fn gpio_deinit(&mut self, port: u32, pin: u32) -> i32 {
+        if !self.manifest.allows_pin(pin) {
+            return PERMISSION_DENIED;
+        }
+        if port != 0 || self.gpio.remove(&pin).is_none() {
+            return -1;
+        }
+
+        self.claims.release_gpio(pin);
+        0
+    }
+
+    /// Reads the level of an input pin to the memory of the module.
+    fn gpio_read(&mut self, port: u32, pin: u32, offset: u32) -> i32 {
+        if port != 0 || self.gpio.get(&pin) != Some(&true) {
+            return -1;
+        }
+
+        let level = self.board.level(pin) as u8;
+        match self
+            .memory
+            .as_ref()
+            .map(|memory| memory.set_value(offset, level))
+        {
+            Some(Ok(())) => 0,
+            _ => 1,
+        }
+    }
+
+    /// Drives an output pin.
+    fn gpio_write(&mut self, port: u32, pin: u32, value: u32) -> i32 {
+        if port != 0 || self.gpio.get(&pin) != Some(&false) {
+            return -1;
+        }
+
+        self.board.set_level(pin, value != 0);
+        0
+    }
+
+    /// Reads the value stored under a key into a buffer of the module.
+    fn kv_get(&mut self, key: (u32, u32), buf: u32, cap: u32, len_ptr: u32) -> i32 {
+        let key = match self.read_str(key) {
+            Some(key) => key,
+            None => return -1,
+        };
+
+        match self.storage.get(&key) {
+            Ok(Some(value)) => self.write_buffer(&value, buf, cap, len_ptr),
+            Ok(None) => ESP_ERR_NVS_NOT_FOUND,
+            Err(code) => code,
+        }
+    }
+
+    /// Stores a value under a key, overwriting any previous value.
+    fn kv_set(&mut self, key: (u32, u32), (offset, len): (u32, u32)) -> i32 {
+        let key = match self.read_str(key) {
+            Some(key) => key,
+            None => return -1,
+        };
+        let value = match self
+            .memory
+            .as_ref()
+            .and_then(|memory| memory.get(offset, len as usize).ok())
+        {
+            Some(value) => value,
+            None => return 1,
+        };
+
+        match self.storage.set(&key, &value) {
+            Ok(()) => 0,
+            Err(code) => code,
+        }
+    }
+
+    /// Removes a key together with its value.
+    fn kv_delete(&mut self, key: (u32, u32)) -> i32 {
+        let key = match self.read_str(key) {
+            Some(key) => key,
+            None => return -1,
+        };
+
+        match self.storage.delete(&key) {
+            Ok(true) => 0,
+            Ok(false) => ESP_ERR_NVS_NOT_FOUND,
+            Err(code) => code,
+        }
+    }
+
+    /// Writes all keys into a buffer of the module, each key terminated by a NUL byte.
+    fn kv_list(&mut self, buf: u32, cap: u32, len_ptr: u32) -> i32 {
+        let mut bytes = Vec::new();
+        for key in self.storage.keys() {
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.push(0);
+        }
+
+        self.write_buffer(&bytes, buf, cap, len_ptr)
+    }
+
+    /// Fills a buffer of the module with random bytes from the seeded RNG.
+    fn random_fill(&mut self, offset: u32, len: u32) -> i32 {
+        let memory = match self.memory.as_ref() {
+            Some(memory) => memory,
+            None => return 1,
+        };
+        // checks the bounds before the RNG advances
+        let mut buf = match memory.get(offset, len as usize) {
+            Ok(buf) => buf,
+            Err(_) => return 1,
+        };
+        self.rng.fill(&mut buf);
+
+        memory.set(offset, &buf).map_or(1, |_| 0)
+    }
+
+    /// Opens the channel with the given name and writes its handle to the memory of the
+    /// module. Channels are shared by all modules of the simulator, like on the device.
+    fn chan_open(&mut self, name: (u32, u32), handle: u32) -> i32 {
+        let name = match self.read_str(name) {
+            Some(name) => name,
+            None => return -1,
+        };
+        if !self.manifest.allows_channel(&name) {
+            return PERMISSION_DENIED;
+        }
+
+        match self.channels.open(&name) {
+            Some(channel) => self.set_value(handle, channel),
+            None => ESP_ERR_NO_MEM,
+        }
+    }
+
+    /// Sends a message over a channel, waits up to `timeout_us` microseconds if the channel
+    /// is full.
+    fn chan_send(
+        &mut self,
+        handle: ChannelHandle,
+        (offset, len): (u32, u32),
+        timeout_us: u64,
+    ) -> Result<i32, Trap> {
+        let channel = match self.channels.get(handle) {
+            Some(channel) => channel,
+            None => return Ok(-1),
+        };
+        if len as usize > MAX_MESSAGE_LEN {
+            return Ok(ESP_ERR_INVALID_SIZE);
+        }
+        let message = match self
+            .memory
+            .as_ref()
+            .and_then(|memory| memory.get(offset, len as usize).ok())
+        {
+            Some(message) => message,
+            None => return Ok(1),
+        };
+
+        let res = self.wait_for_channel(timeout_us, |timeout| channel.send(&message, timeout))?;
+        Ok(match res {
+            Ok(()) => 0,
+            Err(err) => channel_error_code(err),
+        })
+    }
+
+    /// Receives a message from a channel into a buffer of the module, waits up to
+    /// `timeout_us` microseconds if the channel is empty. A message that doesn't fit stays
+    /// in the channel, only its length is written.
+    fn chan_recv(
+        &mut self,
+        handle: ChannelHandle,
+        buf: u32,
+        cap: u32,
+        timeout_us: u64,
+        len_ptr: u32,
+    ) -> Result<i32, Trap> {
+        let channel = match self.channels.get(handle) {
+            Some(channel) => channel,
+            None => return Ok(-1),
+        };
+
+        let res =
+            self.wait_for_channel(timeout_us, |timeout| channel.recv(cap as usize, timeout))?;
+        Ok(match res {
+            Ok(message) => self.write_buffer(&message, buf, cap, len_ptr),
+            Err(ChannelError::TooLarge(len)) => match self.set_value(len_ptr, len as u32) {
+                0 => ESP_ERR_INVALID_SIZE,
+                err => err,
+            },
+            Err(err) => channel_error_code(err),
+        })
+    }
+
+    /// Writes a handle or a length to the memory of the module, returns the error code for
+    /// the module.
+    fn set_value(&self, offset: u32, value: u32) -> i32 {
+        match self
+            .memory
+            .as_ref()
+            .map(|memory| memory.set_value(offset, value))
+        {
+            Some(Ok(())) => 0,
+            _ => 1,
+        }
+    }
+
+    fn call_host(&mut self, index: usize, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
+        // every call into the host is a chance to deliver expired timers, like on the device
+        self.dispatch_timers()?;
+
+        match index {
+            UART_INIT_INDEX => {
+                let res = self.uart_init(args.nth(2), args.nth(4), args.nth(0));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            UART_WRITE_INDEX => {
+                let handle: u8 = args.nth(0);
+                let res = if self.uart == Some(handle) { 0 } else { 1 };
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            // there is never a byte to read, which fails like on the device
+            UART_READ_INDEX => Ok(Some(RuntimeValue::I32(1))),
+            PRINT_INDEX => {
+                let offset: u32 = args.nth(0);
+                let len: u32 = args.nth(1);
+                let bytes = self
+                    .memory
+                    .as_ref()
+                    .and_then(|memory| memory.get(offset, len as usize).ok())
+                    .ok_or_else(|| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+                if self.echo {
+                    println!("{}", String::from_utf8_lossy(&bytes));
+                }
+                Ok(None)
+            }
+            LOG_INDEX => {
+                let level: u32 = args.nth(0);
+                let target = self.read_lossy((args.nth(1), args.nth(2)));
+                let message = self.read_lossy((args.nth(3), args.nth(4)));
+                if self.echo {
+                    eprintln!("{} guest::{}: {}", level, target, message);
+                }
+                Ok(None)
+            }
+            // every record of the module is logged
+            LOG_MAX_LEVEL_INDEX => Ok(Some(RuntimeValue::I32(log::LevelFilter::Trace as i32))),
+            TIME_NOW_US_INDEX => Ok(Some(RuntimeValue::I64(self.clock.now().as_micros() as i64))),
+            UPTIME_MS_INDEX => Ok(Some(RuntimeValue::I64(self.clock.now().as_millis() as i64))),
+            DELAY_MS_INDEX => {
+                let ms: u32 = args.nth(0);
+                self.delay(Duration::from_millis(ms as u64))?;
+                Ok(None)
+            }
+            DELAY_US_INDEX => {
+                let us: u32 = args.nth(0);
+                self.delay(Duration::from_micros(us as u64))?;
+                Ok(None)
+            }
+            TIMER_START_INDEX => {
+                let period_us: u64 = args.nth(0);
+                let periodic: i32 = args.nth(1);
+                let func_index: u32 = args.nth(2);
+                let handle: u32 = args.nth(3);
+                let res = match self.timer_start(period_us, periodic, func_index) {
+                    Ok(timer) => self.set_value(handle, timer),
+                    Err(code) => code,
+                };
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            TIMER_CANCEL_INDEX => {
+                let handle: u32 = args.nth(0);
+                let res = if self.timers.cancel(handle) { 0 } else { -1 };
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            TIMER_POLL_INDEX => {
+                let timeout_us: u64 = args.nth(0);
+                self.timer_poll(timeout_us)?;
+                Ok(Some(RuntimeValue::I32(0)))
+            }
+            GPIO_INIT_INDEX => {
+                let input: i32 = args.nth(2);
+                let res = self.gpio_init(args.nth(0), args.nth(1), input == 1);
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            GPIO_DEINIT_INDEX => {
+                let res = self.gpio_deinit(args.nth(0), args.nth(1));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            GPIO_READ_INDEX => {
+                let res = self.gpio_read(args.nth(0), args.nth(1), args.nth(2));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            GPIO_WRITE_INDEX => {
+                let res = self.gpio_write(args.nth(0), args.nth(1), args.nth(2));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            CHAN_OPEN_INDEX => {
+                let res = self.chan_open((args.nth(0), args.nth(1)), args.nth(2));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            CHAN_SEND_INDEX => {
+                let res = self.chan_send(args.nth(0), (args.nth(1), args.nth(2)), args.nth(3))?;
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            CHAN_RECV_INDEX => {
+                let handle: u32 = args.nth(0);
+                let timeout_us: u64 = args.nth(3);
+                let res =
+                    self.chan_recv(handle, args.nth(1), args.nth(2), timeout_us, args.nth(4))?;
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            KV_GET_INDEX => {
+                let key: (u32, u32) = (args.nth(0), args.nth(1));
+                let res = self.kv_get(key, args.nth(2), args.nth(3), args.nth(4));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            KV_SET_INDEX => {
+                let res = self.kv_set((args.nth(0), args.nth(1)), (args.nth(2), args.nth(3)));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            KV_DELETE_INDEX => {
+                let res = self.kv_delete((args.nth(0), args.nth(1)));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            KV_LIST_INDEX => {
+                let res = self.kv_list(args.nth(0), args.nth(1), args.nth(2));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            RANDOM_FILL_INDEX => {
+                let res = self.random_fill(args.nth(0), args.nth(1));
+                Ok(Some(RuntimeValue::I32(res)))
+            }
+            PROFILE_MARK_INDEX => {
+                let id: u32 = args.nth(0);
+                self.stats.mark(id);
+                Ok(None)
+            }
+            REPORT_PANIC_INDEX => Err(Trap::new(TrapKind::Host(Box::new(GuestPanic {
+                message: self.read_lossy((args.nth(0), args.nth(1))),
+                file: self.read_lossy((args.nth(2), args.nth(3))),
+                line: args.nth(4),
+            })))),
+            PERMISSION_DENIED_INDEX => Ok(Some(RuntimeValue::I32(PERMISSION_DENIED))),
+            _ => Err(Trap::new(TrapKind::Host(Box::new(NotSimulated(
+                imports::name_of(index),
+            ))))),
+        }
+    }
+}
+
+impl Externals for Host {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        match index {
+            CONSUME_FUEL_INDEX => {
+                let amount: u32 = args.nth(0);
+                if let Some(preemption) = self.preemption.as_mut() {
+                    preemption.on_instructions(amount as u64);
+                }
+                if let Some(fuel) = self.fuel.as_mut() {
+                    fuel.consume(amount as u64)
+                        .map_err(|err| Trap::new(TrapKind::Host(Box::new(err))))?;
+                }
+                return Ok(None);
+            }
+            ENTER_FUNCTION_INDEX => {
+                self.call_stack.enter(args.nth(0))?;
+                self.stats.call_depth(self.call_stack.frames().len());
+                return Ok(None);
+            }
+            LEAVE_FUNCTION_INDEX => {
+                self.call_stack.leave();
+                return Ok(None);
+            }
+            MEMORY_GROW_INDEX => {
+                let pages: u32 = args.nth(0);
+                let previous = self.memory.as_ref().and_then(|memory| {
+                    let previous = memory::grow(memory, pages, self.memory_limit).ok()?;
+                    self.stats.memory_size(Bytes::from(memory.current_size()).0);
+                    Some(previous.0 as i32)
+                });
+                if previous.is_none() {
+                    self.stats.failed_grow();
+                }
+                return Ok(Some(RuntimeValue::I32(previous.unwrap_or(-1))));
+            }
+            _ => (),
+        }
+
+        if let Some(preemption) = self.preemption.as_mut() {
+            preemption.on_host_call();
+        }
+        let call = self.stats.begin_host_call();
+        let result = self.call_host(index, args);
+        self.stats.end_host_call(index, call);
+        result
+    }
+}
+
+/// Waits in virtual time: sleeping lets the clock pass until the next timer is due, and the
+/// time the module blocked on a channel in real time passes as well.
+impl Scheduler for Host {
+    /// The tick of the default tick rate of 100 Hz.
+    const TICK_PERIOD: Duration = Duration::from_millis(10);
+
+    fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    fn busy_wait(&mut self, duration: Duration) {
+        self.clock.busy_wait(duration);
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        let now = self.clock.now();
+        let mut wake = now + duration;
+        if let Some(due) = self.timers.next_due() {
+            wake = wake.min(due.max(now));
+        }
+        self.clock.sleep(wake - now);
+    }
+
+    fn blocked(&mut self, duration: Duration) {
+        self.clock.sleep(duration);
+    }
+
+    fn preemption(&mut self) -> Option<&mut Preemption> {
+        self.preemption.as_mut()
+    }
+
+    fn fuel(&mut self) -> Option<&mut Fuel> {
+        self.fuel.as_mut()
+    }
+
+    fn has_timers(&self) -> bool {
+        !self.timers.is_empty()
+    }
+
+    fn create_timer(
+        &mut self,
+        period: Duration,
+        periodic: bool,
+        func_index: u32,
+    ) -> Result<TimerHandle, i32> {
+        self.timers
+            .start(self.clock.now(), period, periodic, func_index)
+            .ok_or(ESP_ERR_NO_MEM)
+    }
+
+    fn next_expired(&mut self) -> Option<(TimerHandle, u32)> {
+        self.timers.next_expired(self.clock.now())
+    }
+
+    fn table(&self) -> Option<&TableRef> {
+        self.table.as_ref()
+    }
+
+    fn callback_stack(&mut self) -> &mut Option<StackRecycler> {
+        &mut self.callback_stack
+    }
+}
+
+/// Converts a channel error into the code returned to the module.
+fn channel_error_code(err: ChannelError) -> i32 {
+    match err {
+        ChannelError::Timeout => ESP_ERR_TIMEOUT,
+        ChannelError::TooLarge(_) => ESP_ERR_INVALID_SIZE,
+    }
+}
+
+/// How a module is run on the simulated board.
+#[derive(Default)]
+pub(crate) struct Options {
+    /// The limits of the stacks of the module, those of wasmi by default. The `stack` line
+    /// of the manifest lowers them.
+    pub(crate) stack: StackLimits,
+    /// The exports that are called to run the module, `start` by default.
+    pub(crate) entry: Entry,
+    /// Limits the fuel of the module like on the device, unlimited by default. Refills
+    /// wait for real time, not for virtual time.
+    pub(crate) fuel: Option<FuelConfig>,
+    /// Gives up the core like on the device, through a hook of the caller.
+    pub(crate) preemption: Option<Preemption>,
+    /// Prints what the module prints and logs while it runs.
+    pub(crate) echo: bool,
+    /// The file the storage of the module is kept in, it only lasts for the run without.
+    pub(crate) storage: Option<PathBuf>,
+    /// The seed of the RNG, the same seed gets the same random bytes.
+    pub(crate) seed: u64,
+    /// The board the module runs on, shared with the modules that run side by side.
+    pub(crate) board: Arc<Board>,
+}
+
+/// How a run ended.
+pub(crate) struct Ran {
+    pub(crate) stats: Stats,
+    /// The result of the export, if the entry point is a single call.
+    pub(crate) returned: Option<RuntimeValue>,
+    /// Why the module stopped early: the error code an export returned, or the backtrace
+    /// and memory of the module if it trapped.
+    pub(crate) failure: Option<String>,
+    /// The virtual time that passed while the module ran.
+    pub(crate) elapsed: Duration,
+    /// The part of `elapsed` the module busy waited in delays shorter than a tick.
+    pub(crate) busy_waited: Duration,
+}
+
+/// Runs the module from its entry point on the simulated board.
+pub(crate) fn run(wasm: &[u8], manifest: &Manifest, options: Options) -> Result<Ran, String> {
+    // like on the device, the calls are only tracked for the lowered stack limits
+    let stack = options.stack.lowered_by(manifest);
+    let instrumentation = Instrumentation {
+        fuel: options.fuel.is_some() || options.preemption.is_some(),
+        calls: stack != StackLimits::default(),
+    };
+    let loaded = instance::load(wasm, manifest, instrumentation)?;
+    let storage = match &options.storage {
+        Some(path) => Storage::open(path)?,
+        None => Storage::default(),
+    };
+    let mut host = Host {
+        manifest: manifest.clone(),
+        memory: loaded.memory.clone(),
+        memory_limit: loaded.memory_limit,
+        table: loaded.table.clone(),
+        call_stack: ShadowStack::new(&stack, instrumentation.calls),
+        clock: Clock::default(),
+        fuel: options.fuel.map(Fuel::new),
+        preemption: options.preemption,
+        timers: Timers::default(),
+        storage,
+        rng: Rng::seeded(options.seed),
+        board: options.board,
+        claims: Claims::default(),
+        gpio: HashMap::new(),
+        uart: None,
+        channels: Channels::default(),
+        callback_stack: Some(StackRecycler::with_limits(
+            CALLBACK_STACK_LIMIT,
+            CALLBACK_STACK_LIMIT,
+        )),
+        stats: Stats::default(),
+        echo: options.echo,
+    };
+    if let Some(memory) = host.memory.as_ref() {
+        host.stats.memory_size(Bytes::from(memory.current_size()).0);
+    }
+
+    let mut stack_rec = stack.recycler();
+    options.entry.check(&loaded.instance)?;
+    let mut invoker = HostInvoker {
+        instance: &loaded.instance,
+        host: &mut host,
+        stack: &mut stack_rec,
+    };
+    let mut returned = None;
+    let failure = match entry::run(&options.entry, &mut invoker) {
+        Ok(result) => {
+            returned = result;
+            None
+        }
+        Err(Failure::Error(wasmi::Error::Trap(trap))) => {
+            let (report, _) = instance::report_trap(&trap, &host.call_stack, &loaded.names, &stack);
+            Some(format!(
+                "{}\n{}",
+                report,
+                dump::report(&loaded.instance, host.memory.as_ref(), &loaded.symbols)
+            ))
+        }
+        Err(Failure::Error(err)) => return Err(err.to_string()),
+        Err(failure) => Some(failure.to_string()),
+    };
+
+    Ok(Ran {
+        stats: host.stats,
+        returned,
+        failure,
+        elapsed: host.clock.now(),
+        busy_waited: host.clock.busy(),
+    })
+}
+
+/// Calls the exports of a module on the simulated board. The pauses of a lifecycle pass in
+/// virtual time.
+struct HostInvoker<'r> {
+    instance: &'r ModuleRef,
+    host: &'r mut Host,
+    stack: &'r mut StackRecycler,
+}
+
+impl Invoker for HostInvoker<'_> {
+    /// Each export is called with a full tank of fuel, like on the device.
+    fn invoke(&mut self, call: &Call) -> Result<Option<RuntimeValue>, wasmi::Error> {
+        if let Some(fuel) = self.host.fuel.as_mut() {
+            fuel.reset();
+        }
+        let invocation = self.host.stats.begin_invocation();
+        let result =
+            self.instance
+                .invoke_export_with_stack(&call.export, &call.args, self.host, self.stack);
+        self.host.stats.end_invocation(invocation);
+        result
+    }
+
+    /// Timers that expire during the pause are dispatched, a trap of their callback stops
+    /// the lifecycle without deinit.
+    fn pause(&mut self, interval: Duration) -> bool {
+        self.host.delay(interval).is_ok()
+    }
+}
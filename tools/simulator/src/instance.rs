@@ -0,0 +1,79 @@
+use wasmi::memory_units::Pages;
+use wasmi::{ImportsBuilder, MemoryRef, ModuleInstance, ModuleRef, TableRef, Trap, TrapKind};
+
+use crate::dump::Symbols;
+use crate::imports::{InstrumentationResolver, UartModuleImportResolver};
+use crate::manifest::Manifest;
+use crate::memory::{self, DeclaredMemory, HostMemory};
+use crate::metering::{self, Instrumentation, INSTRUMENTATION};
+use crate::stack::{ShadowStack, StackLimits};
+use crate::trap::{FunctionNames, TrapReport};
+
+/// A module that is instrumented and instantiated like on the device, with everything of
+/// it the host needs.
+pub(crate) struct Loaded {
+    pub(crate) instance: ModuleRef,
+    pub(crate) memory: Option<MemoryRef>,
+    /// The most pages the memory may grow to.
+    pub(crate) memory_limit: Pages,
+    /// The function table, needed to call back into the module.
+    pub(crate) table: Option<TableRef>,
+    pub(crate) names: FunctionNames,
+    pub(crate) symbols: Symbols,
+}
+
+/// Instruments and instantiates the module. The limit of the firmware is unknown here, only
+/// the manifest limits the memory.
+pub(crate) fn load(
+    wasm: &[u8],
+    manifest: &Manifest,
+    instrumentation: Instrumentation,
+) -> Result<Loaded, String> {
+    let (module, names) =
+        metering::load_instrumented(wasm, instrumentation).map_err(|err| err.to_string())?;
+    let declared = DeclaredMemory::of(wasm)?;
+    let memory_limit = memory::limit(None, manifest);
+    declared.check(memory_limit)?;
+    let host_memory = HostMemory::new(memory_limit);
+    let resolver = UartModuleImportResolver::new(manifest, &host_memory);
+    let instance = ModuleInstance::new(
+        &module,
+        &ImportsBuilder::new()
+            .with_resolver("env", &resolver)
+            .with_resolver(INSTRUMENTATION, &InstrumentationResolver),
+    )
+    .map_err(|err| err.to_string())?
+    .assert_no_start();
+
+    let memory = memory::find(&instance, &declared, &host_memory);
+    let table = instance
+        .export_by_name("__indirect_function_table")
+        .and_then(|export| export.as_table().cloned());
+
+    Ok(Loaded {
+        instance,
+        memory,
+        memory_limit,
+        table,
+        names,
+        symbols: Symbols::from_wasm(wasm),
+    })
+}
+
+/// Describes a trap of the module like the firmware does. Returns the report together with
+/// the limit of the stack the module exceeded, if it overflowed its stack.
+pub(crate) fn report_trap(
+    trap: &Trap,
+    call_stack: &ShadowStack,
+    names: &FunctionNames,
+    stack: &StackLimits,
+) -> (TrapReport, Option<String>) {
+    let report = TrapReport::new(trap, call_stack.frames(), names);
+    match trap.kind() {
+        TrapKind::StackOverflow => {
+            let cause = stack.exceeded(call_stack);
+            (report.with_cause(&cause), Some(cause))
+        }
+        _ => (report, None),
+    }
+}
@@ -0,0 +1,486 @@
+use std::fs;
+use std::net::TcpListener;
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+
+mod board;
+mod clock;
+mod esp;
+mod host;
+mod instance;
+mod replay;
+mod rng;
+mod storage;
+mod supervisor;
+mod tcp;
+#[cfg(test)]
+mod tests;
+mod timer;
+
+// the parts of the runtime that don't depend on the ESP, so that the simulator loads and
+// instruments modules exactly like the firmware
+#[allow(dead_code)]
+#[path = "../../../src/channel.rs"]
+mod channel;
+#[path = "../../../src/debugger.rs"]
+mod debugger;
+#[path = "../../../src/dump.rs"]
+mod dump;
+#[path = "../../../src/entry.rs"]
+mod entry;
+#[path = "../../../src/image.rs"]
+mod image;
+#[allow(dead_code)]
+#[path = "../../../src/imports.rs"]
+mod imports;
+#[allow(dead_code)]
+#[path = "../../../src/manifest.rs"]
+mod manifest;
+#[allow(dead_code)]
+#[path = "../../../src/memory.rs"]
+mod memory;
+#[allow(dead_code)]
+#[path = "../../../src/metering.rs"]
+mod metering;
+#[allow(dead_code)]
+#[path = "../../../src/peripherals.rs"]
+mod peripherals;
+#[allow(dead_code)]
+#[path = "../../../src/preemption.rs"]
+mod preemption;
+#[allow(dead_code)]
+#[path = "../../../src/profiler.rs"]
+mod profiler;
+#[path = "../../../src/scheduler.rs"]
+mod scheduler;
+#[allow(dead_code)]
+#[path = "../../../src/sections.rs"]
+mod sections;
+// modules are run unsigned here, the signatures are only tested
+#[cfg(test)]
+#[path = "../../../src/signature.rs"]
+mod signature;
+#[path = "../../../src/stack.rs"]
+mod stack;
+#[path = "../../../src/stats.rs"]
+mod stats;
+#[allow(dead_code)]
+#[path = "../../../src/trace.rs"]
+mod trace;
+#[allow(dead_code)]
+#[path = "../../../src/trap.rs"]
+mod trap;
+
+use entry::Entry;
+use manifest::Manifest;
+use profiler::{Profile, ProfileConfig};
+use replay::Replayed;
+use stack::StackLimits;
+
+const USAGE: &str = "usage:
+    simulator run <module> [manifest]                  runs the module on a simulated board, in virtual time
+        --storage <file>    keeps the storage of the module in the file, instead of for one run
+        --seed <number>     seeds the RNG, 0 by default
+    simulator supervise <module>...                    runs the modules side by side on one simulated board,
+        each on its own thread with the manifest embedded into it
+    simulator replay <module> <trace> [manifest]       replays a trace against the module
+    simulator stats <module> <trace> [manifest]        replays a trace and prints the stats as JSON
+    simulator profile <module> <trace> <instructions> <host calls> [manifest]
+        replays a trace and prints the call stacks of the module as collapsed stacks, sampled
+        after every <instructions> instructions and <host calls> calls into the host, 0 for never
+    simulator debug <module> <trace> <port> [manifest]
+        replays a trace with the debugger attached, waits for a client on the port of localhost
+    simulator stack <module> <trace> [manifest]
+        finds the smallest value stack and call depth the module replays the trace with
+    simulator extract <log> <name> <trace>             restores the trace of a module from a console log
+    simulator extract-profile <log> <name> <output>    restores the profile of a module from a console log
+
+The commands that run or replay a module call the `start` export of the module, unless an entry point is given with
+    --entry \"<export> [<type>:<value>...]\"      calls the export with the arguments, e.g. \"blink i32:8\"
+    --entry \"lifecycle <init> <run> <deinit>\"   calls init, run until it returns an error code
+        or the trace ends, and deinit, - for none";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let options = match Options::take(&mut args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+    let entry = options.entry.clone();
+
+    let result = match args[..] {
+        ["run", module] => run(module, None, &options),
+        ["run", module, manifest] => run(module, Some(manifest), &options),
+        ["supervise", ref modules @ ..] if !modules.is_empty() => supervise(modules, &options),
+        ["replay", module, trace] => replay(module, trace, None, &entry),
+        ["replay", module, trace, manifest] => replay(module, trace, Some(manifest), &entry),
+        ["stats", module, trace] => stats(module, trace, None, &entry),
+        ["stats", module, trace, manifest] => stats(module, trace, Some(manifest), &entry),
+        ["profile", module, trace, instructions, host_calls] => {
+            profile(module, trace, (instructions, host_calls), None, &entry)
+        }
+        ["profile", module, trace, instructions, host_calls, manifest] => profile(
+            module,
+            trace,
+            (instructions, host_calls),
+            Some(manifest),
+            &entry,
+        ),
+        ["debug", module, trace, port] => debug(module, trace, port, None, &entry),
+        ["debug", module, trace, port, manifest] => {
+            debug(module, trace, port, Some(manifest), &entry)
+        }
+        ["stack", module, trace] => stack(module, trace, None, &entry),
+        ["stack", module, trace, manifest] => stack(module, trace, Some(manifest), &entry),
+        ["extract", log, name, trace] => extract(log, name, trace),
+        ["extract-profile", log, name, output] => extract_profile(log, name, output),
+        _ => Err(USAGE.into()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
+
+/// The options of the commands, they may be given anywhere.
+struct Options<'a> {
+    entry: Entry,
+    storage: Option<&'a str>,
+    seed: u64,
+}
+
+impl<'a> Options<'a> {
+    /// Removes the options from the arguments.
+    fn take(args: &mut Vec<&'a str>) -> Result<Self, String> {
+        let entry = match take_option(args, "--entry")? {
+            Some(text) => Entry::parse(text)?,
+            None => Entry::default(),
+        };
+
+        let storage = take_option(args, "--storage")?;
+        let seed = match take_option(args, "--seed")? {
+            Some(seed) => seed
+                .parse()
+                .map_err(|_| format!("{} is not a valid seed", seed))?,
+            None => 0,
+        };
+
+        Ok(Self {
+            entry,
+            storage,
+            seed,
+        })
+    }
+}
+
+/// Removes an option and its value from the arguments and returns the value.
+fn take_option<'a>(args: &mut Vec<&'a str>, name: &str) -> Result<Option<&'a str>, String> {
+    match args.iter().position(|&arg| arg == name) {
+        Some(index) if index + 1 < args.len() => {
+            let value = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(value))
+        }
+        Some(_) => Err(USAGE.into()),
+        None => Ok(None),
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("could not read {}: {}", path, err))
+}
+
+fn write(path: &str, bytes: &[u8]) -> Result<(), String> {
+    fs::write(path, bytes).map_err(|err| format!("could not write {}: {}", path, err))
+}
+
+/// Reads a module or an image, with the manifest from `manifest` or embedded into the module.
+fn load(module: &str, manifest: Option<&str>) -> Result<(Vec<u8>, Manifest), String> {
+    let wasm = image::decompress(&read(module)?)?.into_owned();
+    let manifest = match manifest {
+        Some(path) => {
+            let text = String::from_utf8(read(path)?)
+                .map_err(|_| format!("{} is not valid UTF-8", path))?;
+            Manifest::parse(&text)?
+        }
+        None => Manifest::from_wasm(&wasm)?.unwrap_or_default(),
+    };
+
+    Ok((wasm, manifest))
+}
+
+/// Runs the module on the simulated board, which answers its calls into the host like the
+/// device. Time only passes while the module sleeps.
+fn run(module: &str, manifest: Option<&str>, options: &Options) -> Result<(), String> {
+    let (wasm, manifest) = load(module, manifest)?;
+
+    let run = host::Options {
+        entry: options.entry.clone(),
+        echo: true,
+        storage: options.storage.map(Into::into),
+        seed: options.seed,
+        ..Default::default()
+    };
+    let ran = host::run(&wasm, &manifest, run)?;
+    if let Some(value) = &ran.returned {
+        eprintln!("the module returned {}", entry::describe(value));
+    }
+    println!(
+        "the module ran for {:?} of virtual time, {:?} of them busy waiting",
+        ran.elapsed, ran.busy_waited
+    );
+    println!("{}", ran.stats);
+    match ran.failure {
+        Some(failure) => Err(format!("the module failed: {}", failure)),
+        None => Ok(()),
+    }
+}
+
+/// Runs the modules on their own threads against the same board, like the supervisor of the
+/// firmware, and prints how each of them ended.
+fn supervise(modules: &[&str], options: &Options) -> Result<(), String> {
+    let mut specs = Vec::new();
+    for &module in modules {
+        let (wasm, manifest) = load(module, None)?;
+        specs.push(supervisor::ModuleSpec {
+            name: module.into(),
+            wasm,
+            manifest,
+            entry: options.entry.clone(),
+            seed: options.seed,
+        });
+    }
+
+    let statuses = supervisor::supervise(specs, &Default::default());
+    let mut failed = 0;
+    for status in statuses {
+        match status.result.and_then(|ran| match ran.failure {
+            Some(failure) => Err(failure),
+            None => Ok(ran),
+        }) {
+            Ok(ran) => println!("{}: ran for {:?} of virtual time", status.name, ran.elapsed),
+            Err(err) => {
+                println!("{}: failed: {}", status.name, err);
+                failed += 1;
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(format!("{} of {} modules failed", failed, modules.len())),
+    }
+}
+
+fn replay(module: &str, trace: &str, manifest: Option<&str>, entry: &Entry) -> Result<(), String> {
+    let (wasm, manifest) = load(module, manifest)?;
+    let events = trace::parse(&read(trace)?)?;
+
+    let replayed = replay::replay(&wasm, &manifest, events, options(entry))?;
+    print_outcome(&replayed);
+    println!(
+        "replayed {} calls into the host, the module behaved as recorded",
+        replayed.calls
+    );
+    Ok(())
+}
+
+/// Replays the trace and prints where the time of the module went, in the same format for
+/// every run, so that the stats can be compared between versions of a module.
+fn stats(module: &str, trace: &str, manifest: Option<&str>, entry: &Entry) -> Result<(), String> {
+    let (wasm, manifest) = load(module, manifest)?;
+    let events = trace::parse(&read(trace)?)?;
+
+    let replayed = replay::replay(&wasm, &manifest, events, options(entry))?;
+    print_outcome(&replayed);
+    println!("{}", replayed.stats.to_json());
+    Ok(())
+}
+
+/// Replays the trace, sampling the call stack of the module after every `instructions`
+/// executed instructions and every `host_calls` calls into the host, like the firmware
+/// does. Prints the samples as collapsed stacks.
+fn profile(
+    module: &str,
+    trace: &str,
+    (instructions, host_calls): (&str, &str),
+    manifest: Option<&str>,
+    entry: &Entry,
+) -> Result<(), String> {
+    let config = ProfileConfig {
+        instructions: instructions
+            .parse()
+            .map_err(|_| format!("invalid number of instructions: {}", instructions))?,
+        host_calls: host_calls
+            .parse()
+            .map_err(|_| format!("invalid number of host calls: {}", host_calls))?,
+    };
+    let (wasm, manifest) = load(module, manifest)?;
+    let events = trace::parse(&read(trace)?)?;
+
+    let profile = Arc::new(Mutex::new(Profile::default()));
+    let options = replay::Options {
+        profile: Some((config, profile.clone())),
+        ..options(entry)
+    };
+    print_outcome(&replay::replay(&wasm, &manifest, events, options)?);
+    for line in profile.lock().unwrap().collapsed() {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Replays the trace with the debugger attached, so that the debug protocol can be tried
+/// without a device.
+fn debug(
+    module: &str,
+    trace: &str,
+    port: &str,
+    manifest: Option<&str>,
+    entry: &Entry,
+) -> Result<(), String> {
+    let port = port
+        .parse()
+        .map_err(|_| format!("invalid port: {}", port))?;
+    let (wasm, manifest) = load(module, manifest)?;
+    let events = trace::parse(&read(trace)?)?;
+
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|err| format!("could not listen on port {}: {}", port, err))?;
+    let transport = tcp::TcpTransport::accept(listener)
+        .map_err(|err| format!("could not accept a client: {}", err))?;
+    let options = replay::Options {
+        debug: Some(Box::new(transport)),
+        ..options(entry)
+    };
+    let replayed = replay::replay(&wasm, &manifest, events, options)?;
+    print_outcome(&replayed);
+    println!(
+        "replayed {} calls into the host, the module behaved as recorded",
+        replayed.calls
+    );
+    Ok(())
+}
+
+/// Finds the smallest stacks the module replays the trace with, by replaying it again and
+/// again: the call depth with the value stack of wasmi, and the value stack with the call
+/// depth of wasmi. Prints them as the `stack` line of a manifest.
+fn stack(module: &str, trace: &str, manifest: Option<&str>, entry: &Entry) -> Result<(), String> {
+    let (wasm, manifest) = load(module, manifest)?;
+    let events = trace::parse(&read(trace)?)?;
+
+    // whether the replay gets as far with the limits as with those of wasmi, a module that
+    // overflows its stack traps where it didn't on the device, which diverges
+    let fits = |stack: StackLimits| {
+        let options = replay::Options {
+            stack,
+            ..options(entry)
+        };
+        matches!(
+            replay::replay(&wasm, &manifest, events.clone(), options),
+            Ok(Replayed {
+                stack_overflow: None,
+                ..
+            })
+        )
+    };
+    let replayed = replay::replay(&wasm, &manifest, events.clone(), options(entry))?;
+    if let Some(cause) = replayed.stack_overflow {
+        return Err(format!(
+            "the module overflows its stack before any limit is lowered: {}",
+            cause
+        ));
+    }
+
+    let defaults = StackLimits::default();
+    let call_depth = smallest(defaults.call_depth, |call_depth| {
+        fits(StackLimits {
+            call_depth,
+            ..defaults
+        })
+    });
+    // the value stack is made of 8 byte values
+    let value_stack = 8 * smallest(defaults.value_stack / 8, |values| {
+        fits(StackLimits {
+            value_stack: 8 * values,
+            ..defaults
+        })
+    });
+
+    println!(
+        "the module needs {} bytes of value stack and a call depth of {}",
+        value_stack, call_depth
+    );
+    println!("stack {} {}", value_stack, call_depth);
+    Ok(())
+}
+
+/// Returns the smallest number up to `max` that `fits`, given that all larger ones fit too.
+fn smallest(max: usize, fits: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (1, max);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if fits(middle) {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    high
+}
+
+/// The options of a replay that runs the module from its entry point.
+fn options(entry: &Entry) -> replay::Options {
+    replay::Options {
+        entry: entry.clone(),
+        ..Default::default()
+    }
+}
+
+/// Prints what the module returned, or why it failed like recorded.
+fn print_outcome(replayed: &Replayed) {
+    if let Some(value) = &replayed.returned {
+        eprintln!("the module returned {}", entry::describe(value));
+    }
+    if let Some(failure) = &replayed.failure {
+        eprintln!("the module failed: {}", failure);
+    }
+}
+
+fn extract(log: &str, name: &str, output: &str) -> Result<(), String> {
+    let log = String::from_utf8_lossy(&read(log)?).into_owned();
+    let trace = trace::from_console(&log, name)?;
+    // only write traces that can be replayed
+    let events = trace::parse(&trace)?;
+    write(output, &trace)?;
+
+    println!(
+        "extracted {} events of module {} into {}",
+        events.len(),
+        name,
+        output
+    );
+    Ok(())
+}
+
+fn extract_profile(log: &str, name: &str, output: &str) -> Result<(), String> {
+    let log = String::from_utf8_lossy(&read(log)?).into_owned();
+    let profile = profiler::from_console(&log, name)?;
+    let mut collapsed = profile.join("\n");
+    collapsed.push('\n');
+    write(output, collapsed.as_bytes())?;
+
+    println!(
+        "extracted {} stacks of module {} into {}",
+        profile.len(),
+        name,
+        output
+    );
+    Ok(())
+}
@@ -0,0 +1,455 @@
+use core::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use wasmi::memory_units::{Bytes, Pages};
+use wasmi::{
+    Externals, FuncInstance, HostError, MemoryRef, ModuleRef, RuntimeArgs, RuntimeValue,
+    StackRecycler, TableRef, Trap, TrapKind,
+};
+
+use crate::debugger::{Debugger, Transport};
+use crate::dump;
+use crate::entry::{self, Call, Entry, Failure, Invoker};
+use crate::imports::{
+    self, CONSUME_FUEL_INDEX, ENTER_FUNCTION_INDEX, LEAVE_FUNCTION_INDEX, MEMORY_GROW_INDEX,
+    PROFILE_MARK_INDEX,
+};
+use crate::instance;
+use crate::manifest::Manifest;
+use crate::memory;
+use crate::metering::Instrumentation;
+use crate::profiler::{Profile, ProfileConfig, Sampler};
+use crate::stack::{ShadowStack, StackLimits};
+use crate::stats::Stats;
+use crate::trace::Event;
+use crate::trap;
+
+/// The module did something else than recorded in the trace.
+#[derive(Debug)]
+struct Divergence(String);
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl HostError for Divergence {}
+
+/// A trap of a host function that was recorded in the trace.
+#[derive(Debug)]
+struct RecordedTrap(String);
+
+impl fmt::Display for RecordedTrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl HostError for RecordedTrap {}
+
+/// Answers the calls of a module into the host with the recorded results, and checks that
+/// the module makes the same calls with the same arguments and data as recorded.
+struct Replay {
+    events: Vec<Event>,
+    next: usize,
+    calls: usize,
+    memory: Option<MemoryRef>,
+    memory_limit: Pages,
+    table: Option<TableRef>,
+    call_stack: ShadowStack,
+    stats: Stats,
+    sampler: Option<Sampler>,
+    debugger: Option<Debugger>,
+}
+
+impl Replay {
+    fn next_event(&mut self) -> Option<Event> {
+        let event = self.events.get(self.next).cloned();
+        self.next += 1;
+        event
+    }
+
+    /// Stops the replay at the current event.
+    fn diverge(&self, message: String) -> Trap {
+        let message = format!("the module diverged at event {}: {}", self.next, message);
+        Trap::new(TrapKind::Host(Box::new(Divergence(message))))
+    }
+
+    /// Grows the memory like it grew on the device, the limit of the device is not known
+    /// here. A `memory.grow` that failed there fails here as well.
+    fn grow(&mut self, pages: u32) -> Result<i32, Trap> {
+        let recorded = match self.next_event() {
+            Some(Event::Grow {
+                pages: recorded,
+                result,
+            }) if recorded == pages => result,
+            Some(event) => {
+                return Err(self.diverge(format!(
+                    "the module grew its memory by {} pages, but the trace continues with {:?}",
+                    pages, event
+                )))
+            }
+            None => {
+                return Err(self.diverge(format!(
+                    "the module grew its memory by {} pages after the end of the trace",
+                    pages
+                )))
+            }
+        };
+        if recorded < 0 {
+            self.stats.failed_grow();
+            return Ok(-1);
+        }
+
+        let grown = self.memory.as_ref().map(|memory| {
+            let previous = memory::grow(memory, pages, self.memory_limit)?;
+            self.stats.memory_size(Bytes::from(memory.current_size()).0);
+            Ok::<_, String>(previous.0 as i32)
+        });
+        match grown {
+            Some(Ok(previous)) if previous == recorded => Ok(previous),
+            Some(Ok(previous)) => Err(self.diverge(format!(
+                "the memory had {} pages before it grew, but {} on the device",
+                previous, recorded
+            ))),
+            Some(Err(err)) => Err(self.diverge(format!(
+                "the memory grew by {} pages on the device, but not here: {}",
+                pages, err
+            ))),
+            None => Err(self.diverge("the module grew a memory the host can't access".into())),
+        }
+    }
+
+    /// Replays what happened during the host call, until it returned.
+    fn finish_call(&mut self, index: usize) -> Result<Option<RuntimeValue>, Trap> {
+        let name = imports::name_of(index);
+        loop {
+            match self.next_event() {
+                Some(Event::Read { offset, bytes }) => {
+                    let actual = self
+                        .memory
+                        .as_ref()
+                        .and_then(|memory| memory.get(offset, bytes.len()).ok());
+                    if actual.as_ref() != Some(&bytes) {
+                        return Err(self.diverge(format!(
+                            "{} read {:?} at {}, but the module passed {:?}",
+                            name, bytes, offset, actual
+                        )));
+                    }
+                }
+                Some(Event::Write { offset, bytes }) => {
+                    let written = self
+                        .memory
+                        .as_ref()
+                        .map(|memory| memory.set(offset, &bytes));
+                    if !matches!(written, Some(Ok(()))) {
+                        return Err(self.diverge(format!(
+                            "{} wrote {} bytes at {}, out of the memory of the module",
+                            name,
+                            bytes.len(),
+                            offset
+                        )));
+                    }
+                }
+                Some(Event::Callback { func_index, args }) => {
+                    let callback = match self.table.as_ref().map(|table| table.get(func_index)) {
+                        Some(Ok(Some(func))) => func,
+                        _ => {
+                            return Err(self.diverge(format!(
+                                "{} called back function {} of the table, which doesn't exist",
+                                name, func_index
+                            )))
+                        }
+                    };
+                    let started = Instant::now();
+                    let result = FuncInstance::invoke(&callback, &args, self);
+                    self.stats.add_callback(started.elapsed());
+                    result?;
+                }
+                Some(Event::Return(value)) => return Ok(value),
+                Some(Event::Trap(message)) => {
+                    return Err(Trap::new(TrapKind::Host(Box::new(RecordedTrap(message)))))
+                }
+                Some(event) => {
+                    return Err(self.diverge(format!(
+                        "{} didn't return before {:?}, the trace is corrupt",
+                        name, event
+                    )))
+                }
+                None => return Err(self.diverge(format!("the trace ended during {}", name))),
+            }
+        }
+    }
+}
+
+impl Externals for Replay {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        // the calls added by the instrumentation are not recorded, and fuel is not limited
+        match index {
+            CONSUME_FUEL_INDEX => {
+                if let Some(sampler) = self.sampler.as_mut() {
+                    let count = args.nth::<u32>(0) as u64;
+                    sampler.on_instructions(count, self.call_stack.frames());
+                }
+                return Ok(None);
+            }
+            ENTER_FUNCTION_INDEX => {
+                let func_index: u32 = args.nth(0);
+                self.call_stack.enter(func_index)?;
+                self.stats.call_depth(self.call_stack.frames().len());
+                if let Some(debugger) = self.debugger.as_mut() {
+                    debugger.on_enter(func_index, self.call_stack.frames());
+                }
+                return Ok(None);
+            }
+            LEAVE_FUNCTION_INDEX => {
+                self.call_stack.leave();
+                return Ok(None);
+            }
+            MEMORY_GROW_INDEX => {
+                let result = self.grow(args.nth(0))?;
+                return Ok(Some(RuntimeValue::I32(result)));
+            }
+            _ => (),
+        }
+
+        let args = args.as_ref().to_vec();
+        match self.next_event() {
+            Some(Event::Call {
+                index: recorded,
+                args: recorded_args,
+            }) if recorded as usize == index && recorded_args == args => (),
+            Some(Event::Call {
+                index: recorded,
+                args: recorded_args,
+            }) => {
+                return Err(self.diverge(format!(
+                    "the module called {}{:?} instead of {}{:?}",
+                    imports::name_of(index),
+                    args,
+                    imports::name_of(recorded as usize),
+                    recorded_args
+                )))
+            }
+            Some(event) => {
+                return Err(self.diverge(format!(
+                    "the module called {}{:?}, but the trace continues with {:?}",
+                    imports::name_of(index),
+                    args,
+                    event
+                )))
+            }
+            None => {
+                return Err(self.diverge(format!(
+                    "the module called {}{:?} after the end of the trace",
+                    imports::name_of(index),
+                    args
+                )))
+            }
+        }
+
+        self.calls += 1;
+        if let Some(sampler) = self.sampler.as_mut() {
+            sampler.on_host_call(index, self.call_stack.frames());
+        }
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.on_host_call(index, &args, self.call_stack.frames());
+        }
+        // the spans are measured by the simulator, like on the device
+        if index == PROFILE_MARK_INDEX {
+            self.stats.mark(args[0].try_into().unwrap_or_default());
+        }
+        let call = self.stats.begin_host_call();
+        let result = self.finish_call(index);
+        self.stats.end_host_call(index, call);
+
+        result
+    }
+}
+
+/// What is done next to replaying a trace.
+#[derive(Default)]
+pub(crate) struct Options {
+    /// Samples the call stack of the module into the profile.
+    pub(crate) profile: Option<(ProfileConfig, Arc<Mutex<Profile>>)>,
+    /// Attaches the debugger to the module, with the client on the other end.
+    pub(crate) debug: Option<Box<dyn Transport>>,
+    /// The limits of the stacks of the module, those of wasmi by default. The `stack` line
+    /// of the manifest lowers them.
+    pub(crate) stack: StackLimits,
+    /// The exports that are called to run the module, `start` by default.
+    pub(crate) entry: Entry,
+}
+
+/// How a replay ended.
+pub(crate) struct Replayed {
+    /// The number of replayed calls into the host.
+    pub(crate) calls: usize,
+    pub(crate) stats: Stats,
+    /// The result of the export, if the entry point is a single call.
+    pub(crate) returned: Option<RuntimeValue>,
+    /// Why the module stopped early: the error code an export returned, or the backtrace
+    /// and memory of the module if it trapped like recorded.
+    pub(crate) failure: Option<String>,
+    /// The limit of the stack the module exceeded, if it trapped with a stack overflow.
+    pub(crate) stack_overflow: Option<String>,
+}
+
+/// Runs the module from its entry point against the recorded events. Returns how the
+/// replay ended, or where the module diverged from the trace.
+pub(crate) fn replay(
+    wasm: &[u8],
+    manifest: &Manifest,
+    events: Vec<Event>,
+    options: Options,
+) -> Result<Replayed, String> {
+    // like the memory, the stacks are only limited further by the manifest
+    let stack = options.stack.lowered_by(manifest);
+    // fuel is charged like on the device, so that the module uses its value stack the same way
+
+    let instrumentation = Instrumentation {
+        fuel: true,
+        calls: options.profile.is_some()
+            || options.debug.is_some()
+            || stack != StackLimits::default(),
+    };
+    let loaded = instance::load(wasm, manifest, instrumentation)?;
+    let names = loaded.names;
+    let sampler = options.profile.map(|(config, profile)| {
+        profile.lock().unwrap().set_names(names.clone());
+        Sampler::new(config, profile)
+    });
+    let debugger = options.debug.map(|transport| {
+        Debugger::new(
+            transport,
+            loaded.instance.clone(),
+            loaded.memory.clone(),
+            names.clone(),
+            loaded.symbols.clone(),
+        )
+    });
+    let instance = loaded.instance;
+    let symbols = loaded.symbols;
+
+    let mut replay = Replay {
+        events,
+        next: 0,
+        calls: 0,
+        memory: loaded.memory,
+        memory_limit: loaded.memory_limit,
+        table: loaded.table,
+        call_stack: ShadowStack::new(&stack, instrumentation.calls),
+        stats: Stats::default(),
+        sampler,
+        debugger,
+    };
+    if let Some(memory) = replay.memory.as_ref() {
+        let size = Bytes::from(memory.current_size()).0;
+        replay.stats.memory_size(size);
+    }
+    let mut stack_rec = stack.recycler();
+    options.entry.check(&instance)?;
+    let mut invoker = ReplayInvoker {
+        instance: &instance,
+        replay: &mut replay,
+        stack: &mut stack_rec,
+    };
+    let result = entry::run(&options.entry, &mut invoker);
+    let mut returned = None;
+    let mut stack_overflow = None;
+    let failure = match result {
+        Ok(result) => {
+            returned = result;
+            None
+        }
+        // a trap of the module is fine, as long as it was recorded like this
+        Err(Failure::Error(wasmi::Error::Trap(trap))) => {
+            let (report, cause) = instance::report_trap(&trap, &replay.call_stack, &names, &stack);
+            stack_overflow = cause;
+            if let Some(debugger) = replay.debugger.as_mut() {
+                debugger.on_trap(&report.summary(), replay.call_stack.frames());
+            }
+            if let TrapKind::Host(err) = trap.kind() {
+                if let Some(divergence) = err.downcast_ref::<Divergence>() {
+                    return Err(divergence.to_string());
+                }
+            }
+            // the trap of the module is recorded last
+            let recorded = replay.next_event();
+            if !matches!(&recorded, Some(Event::Trap(message)) if *message == trap::describe(&trap))
+            {
+                let trace = match recorded {
+                    Some(event) => format!("the trace continues with {:?}", event),
+                    None => String::from("the trace ends"),
+                };
+                return Err(format!(
+                    "the module diverged at event {}: it trapped, but {}\n{}",
+                    replay.next, trace, report
+                ));
+            }
+            Some(format!(
+                "{}\n{}",
+                report,
+                dump::report(&instance, replay.memory.as_ref(), &symbols)
+            ))
+        }
+        Err(Failure::Error(err)) => return Err(err.to_string()),
+        Err(failure) => Some(failure.to_string()),
+    };
+
+    if replay.next < replay.events.len() {
+        let mut err = format!(
+            "the module stopped after {} calls into the host, but the trace continues with {} events",
+            replay.calls,
+            replay.events.len() - replay.next
+        );
+        if let Some(failure) = failure {
+            err = format!("{}\nthe module failed: {}", err, failure);
+        }
+        return Err(err);
+    }
+    Ok(Replayed {
+        calls: replay.calls,
+        stats: replay.stats,
+        returned,
+        failure,
+        stack_overflow,
+    })
+}
+
+/// Calls the exports of a module for the replay, until the trace is used up.
+struct ReplayInvoker<'r> {
+    instance: &'r ModuleRef,
+    replay: &'r mut Replay,
+    stack: &'r mut StackRecycler,
+}
+
+impl Invoker for ReplayInvoker<'_> {
+    fn invoke(&mut self, call: &Call) -> Result<Option<RuntimeValue>, wasmi::Error> {
+        let invocation = self.replay.stats.begin_invocation();
+        let result = self.instance.invoke_export_with_stack(
+            &call.export,
+            &call.args,
+            self.replay,
+            self.stack,
+        );
+        self.replay.stats.end_invocation(invocation);
+        result
+    }
+
+    /// The loop of a lifecycle goes on as long as the trace continues with a pause, the
+    /// module was stopped where the trace ends.
+    fn pause(&mut self, _interval: Duration) -> bool {
+        if self.replay.events.get(self.replay.next) != Some(&Event::Pause) {
+            return false;
+        }
+        self.replay.next += 1;
+        true
+    }
+}
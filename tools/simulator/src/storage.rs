@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::esp::{ESP_ERR_NVS_INVALID_NAME, ESP_ERR_NVS_KEY_TOO_LONG};
+
+/// The longest key NVS accepts, in bytes.
+const MAX_KEY_LEN: usize = 15;
+
+/// Checks a key like NVS does.
+fn check_key(key: &str) -> Result<(), i32> {
+    if key.contains('\0') {
+        return Err(ESP_ERR_NVS_INVALID_NAME);
+    }
+    if key.len() > MAX_KEY_LEN {
+        return Err(ESP_ERR_NVS_KEY_TOO_LONG);
+    }
+
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The persistent key-value storage of a simulated module. Instead of an NVS namespace, it
+/// is kept in a file, one entry per line with the key and the value as hex, so that it
+/// persists between runs. Without a file, it only lasts for one run. Errors are the error
+/// codes of NVS.
+#[derive(Default)]
+pub(crate) struct Storage {
+    path: Option<PathBuf>,
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl Storage {
+    /// Opens the storage kept in the file, which is created by the first write.
+    pub(crate) fn open(path: &Path) -> Result<Self, String> {
+        let mut entries = BTreeMap::new();
+        if path.exists() {
+            let text = fs::read_to_string(path)
+                .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+            for line in text.lines() {
+                let entry = line.split_once(' ').and_then(|(key, value)| {
+                    let key = String::from_utf8(from_hex(key)?).ok()?;
+                    Some((key, from_hex(value)?))
+                });
+                let (key, value) = entry
+                    .ok_or_else(|| format!("{} is not a storage: {}", path.display(), line))?;
+                entries.insert(key, value);
+            }
+        }
+
+        Ok(Self {
+            path: Some(path.to_owned()),
+            entries,
+        })
+    }
+
+    /// Reads the value stored under `key`, returns `None` if there is none.
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Vec<u8>>, i32> {
+        check_key(key)?;
+        Ok(self.entries.get(key).cloned())
+    }
+
+    /// Stores `value` under `key` and writes the file.
+    pub(crate) fn set(&mut self, key: &str, value: &[u8]) -> Result<(), i32> {
+        check_key(key)?;
+        self.entries.insert(key.to_owned(), value.to_vec());
+        self.commit();
+        Ok(())
+    }
+
+    /// Removes `key`. Returns false if there was no such key.
+    pub(crate) fn delete(&mut self, key: &str) -> Result<bool, i32> {
+        check_key(key)?;
+        if self.entries.remove(key).is_none() {
+            return Ok(false);
+        }
+        self.commit();
+        Ok(true)
+    }
+
+    /// Returns all keys, in order.
+    pub(crate) fn keys(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+
+    /// Writes the entries to the file. The flash of the simulated board doesn't fail, the
+    /// file is only reported if it can't be written.
+    fn commit(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let text: String = self
+            .entries
+            .iter()
+            .map(|(key, value)| format!("{} {}\n", to_hex(key.as_bytes()), to_hex(value)))
+            .collect();
+        if let Err(err) = fs::write(path, text) {
+            eprintln!("could not write the storage to {}: {}", path.display(), err);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+use std::thread;
+
+use crate::board::Board;
+use crate::entry::Entry;
+use crate::host::{self, Ran};
+use crate::manifest::Manifest;
+
+/// A module the supervisor runs, like a `ModuleSpec` of the firmware.
+pub(crate) struct ModuleSpec {
+    pub(crate) name: String,
+    pub(crate) wasm: Vec<u8>,
+    pub(crate) manifest: Manifest,
+    pub(crate) entry: Entry,
+    pub(crate) seed: u64,
+}
+
+/// How a module the supervisor ran ended.
+pub(crate) struct Status {
+    pub(crate) name: String,
+    pub(crate) result: Result<Ran, String>,
+}
+
+/// Runs every module on its own thread against the same board, like the supervisor of the
+/// firmware runs every module in its own task. Each module has its own virtual clock.
+/// Returns how the modules ended, in the order they were given.
+pub(crate) fn supervise(modules: Vec<ModuleSpec>, board: &Arc<Board>) -> Vec<Status> {
+    let threads: Vec<_> = modules
+        .into_iter()
+        .map(|spec| {
+            let board = board.clone();
+            let name = spec.name.clone();
+            let thread = thread::Builder::new().name(spec.name).spawn(move || {
+                let options = host::Options {
+                    entry: spec.entry,
+                    echo: true,
+                    seed: spec.seed,
+                    board,
+                    ..Default::default()
+                };
+                host::run(&spec.wasm, &spec.manifest, options)
+            });
+            (name, thread)
+        })
+        .collect();
+
+    threads
+        .into_iter()
+        .map(|(name, thread)| {
+            let result = match thread {
+                Ok(thread) => thread
+                    .join()
+                    .unwrap_or_else(|_| Err("the simulator panicked".into())),
+                Err(err) => Err(format!("could not start a thread: {}", err)),
+            };
+            Status { name, result }
+        })
+        .collect()
+}
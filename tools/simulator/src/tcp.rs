@@ -0,0 +1,47 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::debugger::Transport;
+
+/// Connects the debugger to a client over TCP, like the firmware does over its console.
+pub(crate) struct TcpTransport {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // a line that was only partially received so far
+    pending: Vec<u8>,
+}
+
+impl TcpTransport {
+    /// Waits for a client on the listener, which is bound to a port of localhost.
+    pub(crate) fn accept(listener: TcpListener) -> io::Result<Self> {
+        eprintln!("waiting for a debugger on {}", listener.local_addr()?);
+        let (stream, client) = listener.accept()?;
+        eprintln!("debugger connected from {}", client);
+
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            pending: Vec::new(),
+        })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn read_line(&mut self, wait: bool) -> Option<String> {
+        self.writer.set_nonblocking(!wait).ok()?;
+        match self.reader.read_until(b'\n', &mut self.pending) {
+            Ok(_) if self.pending.ends_with(b"\n") => {
+                let line = String::from_utf8_lossy(&self.pending).trim().to_string();
+                self.pending.clear();
+                Some(line)
+            }
+            // the client closed the connection, or there is no complete line yet
+            _ => None,
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        // a client that is gone is noticed on the next read
+        let _ = writeln!(self.writer, "{}", line);
+    }
+}
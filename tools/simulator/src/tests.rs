@@ -0,0 +1,583 @@
+//! Runs the guests in `guests` on the simulated board and against traces.
+
+use std::cell::Cell;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::board::Board;
+use crate::entry::Entry;
+use crate::host::{self, Ran};
+use crate::imports::TIME_NOW_US_INDEX;
+use crate::instance;
+use crate::manifest::Manifest;
+use crate::metering::{FuelConfig, Instrumentation};
+use crate::peripherals::{Claims, Peripheral};
+use crate::preemption::{Preemption, PreemptionConfig, YieldHook};
+use crate::profiler::{Profile, ProfileConfig};
+use crate::replay::{self, Replayed};
+use crate::stack::StackLimits;
+use crate::supervisor::{self, ModuleSpec};
+use crate::tcp::TcpTransport;
+use crate::trace::Event;
+use wasmi::memory_units::Pages;
+use wasmi::RuntimeValue;
+
+/// Compiles a guest of `guests` into a module.
+fn guest(name: &str) -> Vec<u8> {
+    let path = format!("{}/guests/{}", env!("CARGO_MANIFEST_DIR"), name);
+    wat::parse_file(&path).unwrap_or_else(|err| panic!("could not compile {}: {}", path, err))
+}
+
+/// Runs a guest from the entry point on the simulated board, granted what the manifest grants.
+fn run(name: &str, manifest: &str, entry: &str) -> Ran {
+    run_with(name, manifest, entry, host::Options::default())
+}
+
+/// Runs a guest like [`run`], with further options.
+fn run_with(name: &str, manifest: &str, entry: &str, options: host::Options) -> Ran {
+    let options = host::Options {
+        entry: Entry::parse(entry).unwrap(),
+        ..options
+    };
+    host::run(&guest(name), &Manifest::parse(manifest).unwrap(), options).unwrap()
+}
+
+#[test]
+fn delays_advance_the_virtual_clock() {
+    let ran = run("clock.wat", "", "start");
+
+    assert_eq!(ran.failure, None);
+    assert_eq!(ran.elapsed, Duration::from_micros(1_500_250));
+}
+
+#[test]
+fn timers_expire_in_virtual_time() {
+    let ran = run("timers.wat", "", "start");
+
+    assert_eq!(ran.failure, None);
+    assert_eq!(ran.elapsed, Duration::from_millis(1200));
+}
+
+#[test]
+fn delays_sleep_whole_ticks_and_busy_wait_the_rest() {
+    let ran = run("delays.wat", "", "start");
+
+    assert_eq!(ran.failure, None);
+    assert_eq!(ran.elapsed, Duration::from_micros(2_545_000));
+    assert_eq!(ran.busy_waited, Duration::from_micros(15_000));
+}
+
+#[test]
+fn storage_persists_between_runs() {
+    let path = std::env::temp_dir().join(format!("simulator-storage-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let options = || host::Options {
+        storage: Some(path.clone()),
+        ..Default::default()
+    };
+
+    for run in 1..=3 {
+        let entry = format!("start i32:{}", run);
+        let ran = run_with("storage.wat", "storage", &entry, options());
+        assert_eq!(ran.failure, None);
+    }
+    let stored = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    // `runs` is 3
+    assert_eq!(stored, "72756e73 03000000\n");
+}
+
+#[test]
+fn storage_lasts_for_one_run_without_a_file() {
+    for _ in 0..2 {
+        assert_eq!(run("storage.wat", "storage", "start i32:1").failure, None);
+    }
+}
+
+#[test]
+fn storage_is_denied_without_a_grant() {
+    assert_eq!(run("storage.wat", "", "denied").failure, None);
+}
+
+/// Returns the random bytes the guest `random.wat` got with the seed.
+fn sample(seed: u64) -> u64 {
+    let options = host::Options {
+        seed,
+        ..Default::default()
+    };
+    match run_with("random.wat", "", "sample", options).returned {
+        Some(RuntimeValue::I64(sample)) => sample as u64,
+        returned => panic!("sample returned {:?}", returned),
+    }
+}
+
+#[test]
+fn the_same_seed_gets_the_same_random_bytes() {
+    assert_eq!(sample(7), sample(7));
+    assert_ne!(sample(7), sample(8));
+}
+
+/// A budget that is refilled as soon as the module slept.
+const FUEL: FuelConfig = FuelConfig {
+    limit: 1000,
+    refill_interval: Some(Duration::ZERO),
+};
+
+/// Runs a guest with [`FUEL`].
+fn run_fueled(name: &str, entry: &str) -> Ran {
+    let options = host::Options {
+        fuel: Some(FUEL),
+        ..Default::default()
+    };
+    run_with(name, "", entry, options)
+}
+
+#[test]
+fn a_spinning_module_runs_out_of_fuel() {
+    let failure = run_fueled("spin.wat", "start").failure.unwrap();
+    assert!(
+        failure.contains("the module ran out of fuel"),
+        "{}",
+        failure
+    );
+}
+
+#[test]
+fn a_sleeping_module_is_refilled() {
+    assert_eq!(run_fueled("spin.wat", "sleepy").failure, None);
+}
+
+/// Counts how often the module gave up the core, by yielding and by sleeping.
+#[derive(Clone, Default)]
+struct CountingHook {
+    yields: Rc<Cell<u32>>,
+    sleeps: Rc<Cell<u32>>,
+}
+
+impl YieldHook for CountingHook {
+    fn yield_now(&mut self) {
+        self.yields.set(self.yields.get() + 1);
+    }
+
+    fn slept(&mut self) {
+        self.sleeps.set(self.sleeps.get() + 1);
+    }
+}
+
+#[test]
+fn busy_waits_count_toward_preemption() {
+    let hook = CountingHook::default();
+    let config = PreemptionConfig {
+        instructions: u64::MAX,
+        host_calls: u32::MAX,
+        busy_wait: Duration::from_millis(100),
+    };
+    let options = host::Options {
+        preemption: Some(Preemption::new(config, Box::new(hook.clone()))),
+        ..Default::default()
+    };
+
+    let ran = run_with("busy.wat", "", "start", options);
+    assert_eq!(ran.failure, None);
+    // 999.9 ms of busy waiting, then a sleep of a second and one of half a second
+    assert_eq!(ran.busy_waited, Duration::from_micros(999_900));
+    assert_eq!(hook.yields.get(), 9);
+    assert_eq!(hook.sleeps.get(), 2);
+}
+
+/// Describes a guest for the supervisor.
+fn spec(name: &str, manifest: &str, entry: &str) -> ModuleSpec {
+    ModuleSpec {
+        name: name.into(),
+        wasm: guest(name),
+        manifest: Manifest::parse(manifest).unwrap(),
+        entry: Entry::parse(entry).unwrap(),
+        seed: 0,
+    }
+}
+
+#[test]
+fn modules_run_side_by_side_on_one_board() {
+    // pin 6 belongs to someone else, pin 8 is high
+    let mut claims = Claims::default();
+    assert!(claims.claim(Peripheral::Gpio(6)));
+    let board = Arc::new(Board::default());
+    board.set_level(8, true);
+
+    let modules = vec![
+        spec("gpio.wat", "gpio 6 7 8 12", "start"),
+        spec("clock.wat", "", "start"),
+        spec("unbounded.wat", "", "start"),
+    ];
+    let statuses = supervisor::supervise(modules, &board);
+
+    let names: Vec<&str> = statuses.iter().map(|status| status.name.as_str()).collect();
+    assert_eq!(names, ["gpio.wat", "clock.wat", "unbounded.wat"]);
+    assert_eq!(statuses[0].result.as_ref().unwrap().failure, None);
+    let clock = statuses[1].result.as_ref().unwrap();
+    assert_eq!(clock.failure, None);
+    assert_eq!(clock.elapsed, Duration::from_micros(1_500_250));
+    let failure = statuses[2]
+        .result
+        .as_ref()
+        .unwrap()
+        .failure
+        .as_ref()
+        .unwrap();
+    assert!(failure.contains("StackOverflow"), "{}", failure);
+
+    // the pins of a module are released once it stopped
+    assert!(board.level(7));
+    assert!(claims.claim(Peripheral::Gpio(8)));
+}
+
+#[test]
+fn the_pins_of_a_uart_stay_with_its_module() {
+    let manifest = "uart\ngpio 2 3 4\nchannel sim-uart-held sim-uart-done";
+    let modules = vec![
+        spec("uart.wat", manifest, "hold"),
+        spec("uart.wat", manifest, "take"),
+    ];
+
+    for status in supervisor::supervise(modules, &Default::default()) {
+        assert_eq!(status.result.unwrap().failure, None, "{}", status.name);
+    }
+
+    // the UART and the pins are released once the module stopped
+    let mut claims = Claims::default();
+    assert!(claims.claim_uart(&[2, 3]));
+    assert!(claims.claim_gpio(4));
+}
+
+#[test]
+fn channels_answer_like_on_the_device() {
+    let ran = run("channel.wat", "channel sim-loop", "start");
+
+    assert_eq!(ran.failure, None);
+    assert_eq!(ran.elapsed, Duration::from_millis(1));
+}
+
+#[test]
+fn modules_talk_over_channels() {
+    let manifest = "channel sim-ping sim-pong";
+    let modules = vec![
+        spec("pingpong.wat", manifest, "ping"),
+        spec("pingpong.wat", manifest, "pong"),
+    ];
+
+    for status in supervisor::supervise(modules, &Default::default()) {
+        assert_eq!(status.result.unwrap().failure, None);
+    }
+}
+
+#[test]
+fn an_imported_memory_is_limited_by_the_host() {
+    let manifest = Manifest::parse("memory 131072").unwrap();
+    let instrumentation = Instrumentation {
+        fuel: false,
+        calls: false,
+    };
+    let wasm = guest("imported_memory.wat");
+    let loaded = instance::load(&wasm, &manifest, instrumentation).unwrap();
+    let memory = loaded.memory.unwrap();
+    // the module declares no maximum, the host sets it
+    assert_eq!(memory.maximum(), Some(Pages(2)));
+    assert_eq!(
+        run("imported_memory.wat", "memory 131072", "start").failure,
+        None
+    );
+
+    let options = host::Options {
+        entry: Entry::parse("start").unwrap(),
+        ..Default::default()
+    };
+    let manifest = Manifest::parse("memory 65535").unwrap();
+    let err = host::run(&guest("imported_memory.wat"), &manifest, options)
+        .err()
+        .unwrap();
+    assert!(
+        err.contains("needs 1 pages of memory, but it may have at most 0"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn the_functions_of_the_instrumentation_can_not_be_imported() {
+    let options = host::Options {
+        entry: Entry::parse("start").unwrap(),
+        ..Default::default()
+    };
+    let wasm = guest("internal_import.wat");
+    let err = host::run(&wasm, &Manifest::default(), options)
+        .err()
+        .unwrap();
+    assert!(err.contains("unknown function __consume_fuel"), "{}", err);
+}
+
+#[test]
+fn a_memory_exported_under_another_name_is_found() {
+    assert_eq!(run("renamed_memory.wat", "", "start").failure, None);
+}
+
+#[test]
+fn a_module_without_memory_gets_error_codes() {
+    let manifest = "storage\nchannel sim-none";
+    assert_eq!(run("no_memory.wat", manifest, "start").failure, None);
+}
+
+/// Replays a guest of `guests` against the events, granted nothing.
+fn replay(name: &str, events: Vec<Event>) -> Result<Replayed, String> {
+    replay::replay(
+        &guest(name),
+        &Manifest::default(),
+        events,
+        replay::Options::default(),
+    )
+}
+
+#[test]
+fn memory_grows_like_recorded() {
+    let replayed = replay(
+        "grow.wat",
+        vec![
+            Event::Grow {
+                pages: 1,
+                result: 1,
+            },
+            Event::Grow {
+                pages: 100,
+                result: -1,
+            },
+        ],
+    )
+    .unwrap();
+    assert_eq!(replayed.failure, None);
+    let stats = replayed.stats.to_json();
+    assert!(
+        stats.contains("\"heap_watermark\":131072,\"failed_grows\":1"),
+        "{}",
+        stats
+    );
+
+    // the memory grew on the device but has to grow by other pages here
+    let err = replay(
+        "grow.wat",
+        vec![Event::Grow {
+            pages: 2,
+            result: 1,
+        }],
+    )
+    .err()
+    .unwrap();
+    assert!(err.contains("grew its memory by 1 pages"), "{}", err);
+}
+
+#[test]
+fn profiles_end_in_the_host_function_that_was_called() {
+    let read = || {
+        [
+            Event::Call {
+                index: TIME_NOW_US_INDEX as u32,
+                args: Vec::new(),
+            },
+            Event::Return(Some(RuntimeValue::I64(0))),
+        ]
+    };
+    let events = [read(), read(), read()].concat();
+    let profile = Arc::new(Mutex::new(Profile::default()));
+    let config = ProfileConfig {
+        instructions: 0,
+        host_calls: 1,
+    };
+    let options = replay::Options {
+        profile: Some((config, profile.clone())),
+        ..Default::default()
+    };
+    let replayed = replay::replay(&guest("profile.wat"), &Manifest::default(), events, options);
+    assert_eq!(replayed.unwrap().failure, None);
+
+    // the function without a name is named by its index, after the imported function
+    assert_eq!(
+        profile.lock().unwrap().collapsed(),
+        [
+            "start;function_2;read:clock;time_now_us 2",
+            "start;read:clock;time_now_us 1"
+        ]
+    );
+}
+
+#[test]
+fn a_debugger_halts_on_a_breakpoint_and_reads_the_module() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    // the client answers every halt, its commands are prefixed with `> ` in the transcript
+    let client = thread::spawn(move || {
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut transcript = vec![lines.next().unwrap().unwrap()];
+        let script = [
+            ("break read", 1),
+            ("continue", 2),
+            ("backtrace", 1),
+            ("memory 16 4", 1),
+            ("locals", 1),
+            ("detach", 1),
+        ];
+        for (command, replies) in script {
+            writeln!(&stream, "{}", command).unwrap();
+            transcript.push(format!("> {}", command));
+            for _ in 0..replies {
+                transcript.push(lines.next().unwrap().unwrap());
+            }
+        }
+        transcript
+    });
+
+    let transport = TcpTransport::accept(listener).unwrap();
+    let events = vec![
+        Event::Call {
+            index: TIME_NOW_US_INDEX as u32,
+            args: Vec::new(),
+        },
+        Event::Return(Some(RuntimeValue::I64(0))),
+    ];
+    let options = replay::Options {
+        debug: Some(Box::new(transport)),
+        ..Default::default()
+    };
+    let replayed = replay::replay(&guest("debug.wat"), &Manifest::default(), events, options);
+    assert_eq!(replayed.unwrap().failure, None);
+
+    assert_eq!(
+        client.join().unwrap(),
+        [
+            "halted entered 2 (start)",
+            "> break read",
+            "ok 1 (read)",
+            "> continue",
+            "ok",
+            "halted entered 1 (read)",
+            "> backtrace",
+            "ok 1 (read), 2 (start)",
+            "> memory 16 4",
+            "ok 7761736d",
+            "> locals",
+            "err unknown command locals",
+            "> detach",
+            "ok",
+        ]
+    );
+}
+
+/// Replays a guest that doesn't call the host with the stack limits, like `stack` does.
+fn replay_with_stack(name: &str, value_stack: usize, call_depth: usize) -> Result<(), String> {
+    let options = replay::Options {
+        stack: StackLimits {
+            value_stack,
+            call_depth,
+        },
+        ..Default::default()
+    };
+    replay::replay(&guest(name), &Manifest::default(), Vec::new(), options).map(|_| ())
+}
+
+/// Asserts that the guest overflows its stack with the limits, which is not recorded.
+fn assert_overflows(name: &str, value_stack: usize, call_depth: usize, exceeded: &str) {
+    let err = replay_with_stack(name, value_stack, call_depth).unwrap_err();
+    assert!(
+        err.starts_with("the module diverged at event 1: it trapped, but the trace ends\n"),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains(&format!("StackOverflow ({})", exceeded)),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn stack_limits_name_the_limit_a_module_exceeded() {
+    // the smallest limits `stack` finds for the guests
+    replay_with_stack("recursion.wat", 8016, 1003).unwrap();
+    replay_with_stack("frames.wat", 13472, 53).unwrap();
+
+    assert_overflows(
+        "recursion.wat",
+        8016,
+        1002,
+        "the call stack exceeded 1002 frames",
+    );
+    assert_overflows(
+        "recursion.wat",
+        8008,
+        1003,
+        "the value stack exceeded 8008 bytes at a depth of 1001 frames",
+    );
+    // with both limits too small, the value stack overflows before the last frame is entered
+    assert_overflows(
+        "recursion.wat",
+        8008,
+        1002,
+        "the value stack exceeded 8008 bytes at a depth of 1001 frames",
+    );
+    assert_overflows("frames.wat", 13472, 52, "the call stack exceeded 52 frames");
+    assert_overflows(
+        "frames.wat",
+        13464,
+        53,
+        "the value stack exceeded 13464 bytes at a depth of 51 frames",
+    );
+    assert_overflows(
+        "unbounded.wat",
+        64 * 1024,
+        100,
+        "the call stack exceeded 100 frames",
+    );
+}
+
+#[test]
+fn calls_return_plain_values_unless_checked() {
+    let ran = run("lifecycle.wat", "", "add i32:2 i32:3");
+    assert_eq!(ran.returned, Some(RuntimeValue::I32(5)));
+    assert_eq!(ran.failure, None);
+
+    let ran = run("lifecycle.wat", "", "checked add i32:2 i32:3");
+    assert_eq!(
+        ran.failure.as_deref(),
+        Some("add returned the error code 5")
+    );
+}
+
+#[test]
+fn a_lifecycle_runs_until_an_error_code() {
+    let entry = "lifecycle setup loop deinit";
+    let ran = run("lifecycle.wat", "", entry);
+    assert_eq!(
+        ran.failure.as_deref(),
+        Some("loop returned the error code 7")
+    );
+
+    // the module paused twice on the device, the replay loops as often
+    let options = replay::Options {
+        entry: Entry::parse(entry).unwrap(),
+        ..Default::default()
+    };
+    let replayed = replay::replay(
+        &guest("lifecycle.wat"),
+        &Manifest::default(),
+        vec![Event::Pause, Event::Pause],
+        options,
+    )
+    .unwrap();
+    assert_eq!(
+        replayed.failure.as_deref(),
+        Some("loop returned the error code 7")
+    );
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::scheduler::TimerHandle;
+
+/// The maximum number of timers a single module can hold at once, like on the device.
+pub(crate) const MAX_TIMERS: u32 = 8;
+
+/// A timer of the simulated board together with the guest function it calls back.
+struct Timer {
+    // tells the timers apart that had the same handle, it is never reused
+    id: u64,
+    func_index: u32,
+    /// The period of a periodic timer.
+    period: Option<Duration>,
+    /// The virtual time the timer expires at next.
+    due: Duration,
+}
+
+/// The software timers of a simulated module, which expire in virtual time. Timers that are
+/// due at the same time expire in the order they were started, so that every run calls
+/// back the module in the same order.
+#[derive(Default)]
+pub(crate) struct Timers {
+    timers: BTreeMap<TimerHandle, Timer>,
+    next_id: u64,
+}
+
+impl Timers {
+    /// Starts a timer that expires `period` after `now`, either once or periodically.
+    /// Returns the handle of the new timer, or `None` if the module has too many timers.
+    pub(crate) fn start(
+        &mut self,
+        now: Duration,
+        period: Duration,
+        periodic: bool,
+        func_index: u32,
+    ) -> Option<TimerHandle> {
+        let handle = (0..MAX_TIMERS).find(|handle| !self.timers.contains_key(handle))?;
+        self.timers.insert(
+            handle,
+            Timer {
+                id: self.next_id,
+                func_index,
+                period: periodic.then_some(period),
+                due: now + period,
+            },
+        );
+        self.next_id += 1;
+
+        Some(handle)
+    }
+
+    /// Stops and frees a timer. Returns false if the handle is unknown.
+    pub(crate) fn cancel(&mut self, handle: TimerHandle) -> bool {
+        self.timers.remove(&handle).is_some()
+    }
+
+    /// Returns true if no timer is active.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Returns the virtual time the next timer expires at.
+    pub(crate) fn next_due(&self) -> Option<Duration> {
+        self.timers.values().map(|timer| timer.due).min()
+    }
+
+    /// Takes the timer that expires first, if it is due at `now`, and returns its handle and
+    /// the index of the guest function to call. A periodic timer is due again one period
+    /// later, a one-shot timer is freed.
+    pub(crate) fn next_expired(&mut self, now: Duration) -> Option<(TimerHandle, u32)> {
+        let (&handle, timer) = self
+            .timers
+            .iter_mut()
+            .filter(|(_, timer)| timer.due <= now)
+            .min_by_key(|(_, timer)| (timer.due, timer.id))?;
+        let func_index = timer.func_index;
+        match timer.period {
+            Some(period) => timer.due += period,
+            None => {
+                self.timers.remove(&handle);
+            }
+        }
+
+        Some((handle, func_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::MIN_PERIOD;
+
+    #[test]
+    fn timers_due_at_once_expire_in_the_order_they_were_started() {
+        let mut timers = Timers::default();
+        let first = timers.start(Duration::ZERO, MIN_PERIOD, false, 10).unwrap();
+        let second = timers.start(Duration::ZERO, MIN_PERIOD, false, 11).unwrap();
+        // the handle of the first timer is reused by a timer that is due at the same time
+        assert!(timers.cancel(first));
+        let third = timers.start(Duration::ZERO, MIN_PERIOD, false, 12).unwrap();
+        assert_eq!(third, first);
+
+        assert_eq!(timers.next_expired(Duration::ZERO), None);
+        assert_eq!(timers.next_expired(MIN_PERIOD), Some((second, 11)));
+        assert_eq!(timers.next_expired(MIN_PERIOD), Some((third, 12)));
+        assert_eq!(timers.next_expired(MIN_PERIOD), None);
+        assert!(timers.is_empty());
+    }
+}
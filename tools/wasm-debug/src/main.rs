@@ -0,0 +1,102 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{exit, Command};
+use std::thread;
+
+/// The prefix of the lines of the debugger on the console of the firmware, all other
+/// lines are log output.
+const CONSOLE_PREFIX: &str = "debug ";
+
+/// The baud rate of the console of the firmware.
+const BAUD_RATE: &str = "115200";
+
+const USAGE: &str = "usage:
+    wasm-debug serial <device>    debugs the module on the device, over its console
+    wasm-debug tcp <address>      debugs the module in the simulator, e.g. at localhost:1234";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args[..] {
+        ["serial", device] => serial(device),
+        ["tcp", address] => tcp(address),
+        _ => Err(USAGE.into()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
+
+/// Opens the console of the device, in raw mode so that lines are passed as they are.
+fn serial(device: &str) -> Result<(), String> {
+    let status = Command::new("stty")
+        .args(["-F", device, BAUD_RATE, "raw", "-echo"])
+        .status()
+        .map_err(|err| format!("could not run stty: {}", err))?;
+    if !status.success() {
+        return Err(format!("could not configure {}", device));
+    }
+
+    let console: File = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device)
+        .map_err(|err| format!("could not open {}: {}", device, err))?;
+    let reader = console
+        .try_clone()
+        .map_err(|err| format!("could not open {}: {}", device, err))?;
+
+    session(reader, console, Some(CONSOLE_PREFIX))
+}
+
+fn tcp(address: &str) -> Result<(), String> {
+    let stream = TcpStream::connect(address)
+        .map_err(|err| format!("could not connect to {}: {}", address, err))?;
+    let reader = stream
+        .try_clone()
+        .map_err(|err| format!("could not connect to {}: {}", address, err))?;
+
+    session(reader, stream, None)
+}
+
+/// Sends the commands typed by the user and prints the replies and events of the debugger,
+/// until either side is closed. With a `prefix`, only the lines starting with it are from
+/// the debugger.
+fn session(
+    reader: impl Read + Send + 'static,
+    mut writer: impl Write,
+    prefix: Option<&'static str>,
+) -> Result<(), String> {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let line = line.trim_end();
+            match prefix {
+                Some(prefix) => {
+                    if let Some(start) = line.find(prefix) {
+                        println!("{}", &line[start + prefix.len()..]);
+                    }
+                }
+                None => println!("{}", line),
+            }
+        }
+        eprintln!("the debugger closed the connection");
+        exit(0);
+    });
+
+    for command in io::stdin().lock().lines() {
+        let command = command.map_err(|err| format!("could not read a command: {}", err))?;
+        writeln!(writer, "{}", command.trim())
+            .and_then(|_| writer.flush())
+            .map_err(|err| format!("could not send the command: {}", err))?;
+    }
+
+    Ok(())
+}
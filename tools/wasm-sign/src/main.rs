@@ -0,0 +1,108 @@
+use std::fs;
+use std::process::exit;
+
+use ed25519_compact::{KeyPair, PublicKey, Seed};
+
+// the section walker and the signatures of the runtime, so that both agree on the layout
+// of signed modules
+#[allow(dead_code)]
+#[path = "../../../src/sections.rs"]
+mod sections;
+#[path = "../../../src/signature.rs"]
+mod signature;
+
+use signature::{split_signature, SignaturePolicy, SIGNATURE_SECTION};
+
+const USAGE: &str = "usage:
+    wasm-sign keygen <name>                  writes the key pair <name>.key and <name>.pub
+    wasm-sign sign <key> <module> [output]   signs the module, in place if no output is given
+    wasm-sign verify <pub> <module>          verifies the signature of the module";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args[..] {
+        ["keygen", name] => keygen(name),
+        ["sign", key, module] => sign(key, module, module),
+        ["sign", key, module, output] => sign(key, module, output),
+        ["verify", public_key, module] => verify(public_key, module),
+        _ => Err(USAGE.into()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("could not read {}: {}", path, err))
+}
+
+fn write(path: &str, bytes: &[u8]) -> Result<(), String> {
+    fs::write(path, bytes).map_err(|err| format!("could not write {}: {}", path, err))
+}
+
+/// Generates a key pair. The secret key is stored as its 32 byte seed, the public key
+/// in the 32 byte raw format that is compiled into the firmware.
+fn keygen(name: &str) -> Result<(), String> {
+    let seed = Seed::generate();
+    let key_pair = KeyPair::from_seed(seed);
+
+    write(&format!("{}.key", name), seed.as_ref())?;
+    write(&format!("{}.pub", name), key_pair.pk.as_ref())?;
+
+    println!("wrote {0}.key and {0}.pub, keep {0}.key secret", name);
+    Ok(())
+}
+
+/// Appends `value` in the unsigned LEB128 encoding.
+fn write_u32(bytes: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn sign(key: &str, module: &str, output: &str) -> Result<(), String> {
+    let seed = Seed::from_slice(&read(key)?).map_err(|_| format!("{} is not a secret key", key))?;
+    let key_pair = KeyPair::from_seed(seed);
+    let bytes = read(module)?;
+
+    // signing again replaces the old signature
+    let (unsigned, _) = split_signature(&bytes)?;
+    let signature = key_pair.sk.sign(unsigned, None);
+
+    let mut content = Vec::new();
+    write_u32(&mut content, SIGNATURE_SECTION.len() as u32);
+    content.extend_from_slice(SIGNATURE_SECTION.as_bytes());
+    content.extend_from_slice(signature.as_ref());
+
+    let mut signed = unsigned.to_vec();
+    signed.push(0);
+    write_u32(&mut signed, content.len() as u32);
+    signed.append(&mut content);
+
+    write(output, &signed)?;
+    println!("signed {} into {}", module, output);
+    Ok(())
+}
+
+/// Verifies a module like the firmware does, unsigned modules are rejected.
+fn verify(public_key: &str, module: &str) -> Result<(), String> {
+    let public_key = PublicKey::from_slice(&read(public_key)?)
+        .map_err(|_| format!("{} is not a public key", public_key))?;
+    let bytes = read(module)?;
+
+    signature::verify(module, &bytes, SignaturePolicy::Reject, &public_key)
+        .map_err(|err| format!("{}: {}", module, err))?;
+
+    println!("the signature of {} is valid", module);
+    Ok(())
+}